@@ -0,0 +1,434 @@
+//! Rasterizing procedural inputs of a material into textures
+//!
+//! `StandardMaterial` can only read inputs from constants or textures. When
+//! baking is enabled, inputs of the surface shader that are connected to a
+//! node graph are evaluated on the CPU with
+//! [`Evaluator`](materialx_parser::eval::Evaluator): once if they don't vary
+//! over the surface, or else at every texel of an image that covers the UV
+//! range `0..1`. Nodes reading `position` see `(u, v, 0)`, so 3D patterns are
+//! baked as a slice through the object.
+
+use crate::{
+    color::{decode_srgb, default_colorspace},
+    settings::MaterialXLoaderSettings,
+    standard_material::{multiply, MaterialError},
+};
+use bevy_asset::{AssetPath, LoadContext, RenderAssetUsages};
+use bevy_color::{Color, ColorToPacked as _, LinearRgba, Srgba};
+use bevy_image::{
+    Image, ImageAddressMode, ImageFilterMode, ImageLoaderSettings, ImageSampler,
+    ImageSamplerDescriptor,
+};
+use bevy_math::Affine2;
+use bevy_pbr::StandardMaterial;
+use bevy_reflect::Reflect;
+use materialx_parser::{
+    ast::ColorSpace,
+    data_types::{DataType, DataTypeAndValue, Vector2, Vector3},
+    eval::{EvalError, Evaluator, Program, ShadingContext},
+    filename::Udim,
+    graph::NodeId,
+    MaterialX,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, warn};
+use wgpu_types::{Extent3d, TextureDimension, TextureFormat};
+
+mod nodes;
+pub(crate) use nodes::COMPONENT_OFFSETS;
+
+/// How procedural inputs are turned into textures
+#[derive(Debug, Clone, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BakeSettings {
+    /// Width and height of baked textures in pixels
+    pub resolution: u32,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        BakeSettings { resolution: 512 }
+    }
+}
+
+/// `StandardMaterial` texture slots that can be filled by baking
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    BaseColor,
+    Roughness,
+    Metalness,
+    Normal,
+}
+
+impl Slot {
+    const ALL: [Slot; 4] = [
+        Slot::BaseColor,
+        Slot::Roughness,
+        Slot::Metalness,
+        Slot::Normal,
+    ];
+
+    /// Input of `standard_surface` read into this slot
+    fn input(self) -> &'static str {
+        match self {
+            Slot::BaseColor => "base_color",
+            Slot::Roughness => "specular_roughness",
+            Slot::Metalness => "metalness",
+            Slot::Normal => "normal",
+        }
+    }
+
+    fn has_texture(self, material: &StandardMaterial) -> bool {
+        match self {
+            Slot::BaseColor => material.base_color_texture.is_some(),
+            Slot::Roughness | Slot::Metalness => material.metallic_roughness_texture.is_some(),
+            Slot::Normal => material.normal_map_texture.is_some(),
+        }
+    }
+}
+
+/// The evaluator baking the materials of `def`
+///
+/// `None` if `settings` don't enable baking, or with a warning if `def` can't
+/// be evaluated.
+pub(crate) fn evaluator(
+    def: &MaterialX,
+    settings: &MaterialXLoaderSettings,
+    path: &AssetPath<'_>,
+) -> Option<Evaluator> {
+    settings.bake.as_ref()?;
+    Evaluator::new(def)
+        .inspect_err(|e| warn!(%path, "Can't bake materials: {e}"))
+        .ok()
+}
+
+/// Bake the connected inputs of `surface` into `res` with `evaluator`, if
+/// there is one and `settings` enable baking
+///
+/// Baked textures are added as sub-assets labeled `{material}/{input}`
+/// (e.g. `Marble_3D/base_color`), except for roughness and metalness, which
+/// share `{material}/metallic_roughness`. Inputs that can't be evaluated, e.g. because
+/// they use nodes the evaluator doesn't support, are skipped with a warning.
+pub(crate) async fn bake_material(
+    evaluator: Option<&mut Evaluator>,
+    surface: &str,
+    material: &str,
+    res: &mut StandardMaterial,
+    settings: &MaterialXLoaderSettings,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> Result<(), MaterialError> {
+    let (Some(bake), Some(evaluator)) = (&settings.bake, evaluator) else {
+        return Ok(());
+    };
+    let Some(surface) = evaluator.graph().find(None, surface) else {
+        return Ok(());
+    };
+    let slots = Slot::ALL
+        .into_iter()
+        .filter(|slot| !slot.has_texture(res))
+        .filter(|slot| {
+            evaluator
+                .graph()
+                .upstream(surface)
+                .any(|edge| edge.to_input == slot.input())
+        })
+        .collect::<Vec<_>>();
+    if slots.is_empty() {
+        return Ok(());
+    }
+
+    let default = default_colorspace(settings);
+    let images = load_images(evaluator, surface, &slots, &default, path, loader).await?;
+    // Replaces the images of the previous material
+    nodes::register(evaluator, images);
+
+    let size = bake.resolution.max(1);
+    let mut roughness = None;
+    let mut metalness = None;
+    for slot in slots {
+        let program = match evaluator.input(surface, slot.input()) {
+            Ok(program) => program,
+            Err(e) => {
+                warn!(%path, "Can't bake `{}` of material {material}: {e}", slot.input());
+                continue;
+            }
+        };
+
+        if program.is_constant() {
+            let value = program
+                .eval(&ShadingContext::default())
+                .ok()
+                .flatten()
+                .and_then(|value| components(&value));
+            if let Some(value) = value {
+                debug!("Folded `{}` into a constant", slot.input());
+                fold(slot, value, res);
+            }
+            continue;
+        }
+
+        let texels = match rasterize(&program, size) {
+            Ok(texels) => texels,
+            Err(e) => {
+                warn!(%path, "Can't bake `{}` of material {material}: {e}", slot.input());
+                continue;
+            }
+        };
+        debug!("Baked `{}` at {size}x{size}", slot.input());
+        if res.uv_transform != Affine2::IDENTITY {
+            // All textures of a `StandardMaterial` share one UV transform
+            warn!(%path, "Baked `{}` of material {material} is tiled like its image textures", slot.input());
+        }
+        let label = format!("{material}/{}", slot.input());
+        match slot {
+            Slot::BaseColor => {
+                let data = texels
+                    .iter()
+                    .flat_map(|&[r, g, b, _]| {
+                        Srgba::from(LinearRgba::rgb(r as f32, g as f32, b as f32)).to_u8_array()
+                    })
+                    .collect();
+                let image = image(size, data, TextureFormat::Rgba8UnormSrgb);
+                res.base_color_texture = Some(loader.add_labeled_asset(label, image));
+            }
+            Slot::Normal => {
+                let data = texels
+                    .iter()
+                    .flat_map(|&[x, y, z, _]| {
+                        [
+                            unorm(x * 0.5 + 0.5),
+                            unorm(y * 0.5 + 0.5),
+                            unorm(z * 0.5 + 0.5),
+                            255,
+                        ]
+                    })
+                    .collect();
+                let image = image(size, data, TextureFormat::Rgba8Unorm);
+                res.normal_map_texture = Some(loader.add_labeled_asset(label, image));
+            }
+            Slot::Roughness => roughness = Some(texels),
+            Slot::Metalness => metalness = Some(texels),
+        }
+    }
+
+    // glTF convention: roughness in green, metalness in blue
+    if roughness.is_some() || metalness.is_some() {
+        let channel = |baked: &Option<Vec<[f64; 4]>>, scalar: f32, i: usize| {
+            baked
+                .as_ref()
+                .map_or(unorm(scalar as f64), |texels| unorm(texels[i][0]))
+        };
+        let data = (0..(size * size) as usize)
+            .flat_map(|i| {
+                [
+                    0,
+                    channel(&roughness, res.perceptual_roughness, i),
+                    channel(&metalness, res.metallic, i),
+                    255,
+                ]
+            })
+            .collect();
+        let label = format!("{material}/metallic_roughness");
+        let image = image(size, data, TextureFormat::Rgba8Unorm);
+        res.perceptual_roughness = 1.0;
+        res.metallic = 1.0;
+        res.metallic_roughness_texture = Some(loader.add_labeled_asset(label, image));
+    }
+
+    Ok(())
+}
+
+/// Set the constant of `slot` in `res` to `value`
+fn fold(slot: Slot, [r, g, b, _]: [f64; 4], res: &mut StandardMaterial) {
+    match slot {
+        // Keep the tint and alpha the mapping put there
+        Slot::BaseColor => {
+            res.base_color = Color::LinearRgba(multiply(
+                res.base_color.into(),
+                LinearRgba::rgb(r as f32, g as f32, b as f32),
+            ))
+        }
+        Slot::Roughness => res.perceptual_roughness = r as f32,
+        Slot::Metalness => res.metallic = r as f32,
+        Slot::Normal => {}
+    }
+}
+
+/// Load the files read by image nodes that the baked inputs depend on
+///
+/// Color images are decoded according to their color space, or `default`.
+async fn load_images(
+    evaluator: &Evaluator,
+    surface: NodeId,
+    slots: &[Slot],
+    default: &ColorSpace,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> Result<HashMap<String, Image>, MaterialError> {
+    let graph = evaluator.graph();
+    let mut stack = graph
+        .upstream(surface)
+        .filter(|edge| slots.iter().any(|slot| edge.to_input == slot.input()))
+        .map(|edge| edge.from)
+        .collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    let mut images = HashMap::new();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        stack.extend(graph.upstream(id).map(|edge| edge.from));
+
+        let node = graph.node(id);
+        if !matches!(node.category.as_str(), "image" | "tiledimage") {
+            continue;
+        }
+        let Some(port) = node.input("file") else {
+            continue;
+        };
+        let Some(DataTypeAndValue::Filename(file)) = &port.value else {
+            continue;
+        };
+        if images.contains_key(file) {
+            continue;
+        }
+        // Only the first UV tile is baked
+        let texture = path.resolve_embed(&Udim::FIRST.filename(file))?;
+        let color = matches!(node.r#type, DataType::Color3 | DataType::Color4);
+        let srgb = decode_srgb(port.colorspace.as_ref().unwrap_or(default), color);
+        let image = loader
+            .loader()
+            .with_settings(move |settings: &mut ImageLoaderSettings| settings.is_srgb = srgb)
+            .immediate()
+            .load::<Image>(&texture)
+            .await;
+        match image {
+            Ok(image) => {
+                images.insert(file.clone(), image.take());
+            }
+            Err(e) => warn!(%texture, "Failed to load texture for baking: {e}"),
+        }
+    }
+    Ok(images)
+}
+
+/// Evaluate `program` at the center of each texel of a `size`×`size` image
+///
+/// Rows go from the top of the image down, i.e. from `v = 1` to `v = 0`.
+fn rasterize(program: &Program, size: u32) -> Result<Vec<[f64; 4]>, EvalError> {
+    let mut res = Vec::with_capacity((size * size) as usize);
+    let mut ctx = ShadingContext::default();
+    for y in 0..size {
+        for x in 0..size {
+            let u = (x as f64 + 0.5) / size as f64;
+            let v = 1.0 - (y as f64 + 0.5) / size as f64;
+            ctx.uv = Vector2([u, v]);
+            ctx.position = Vector3([u, v, 0.0]);
+            let value = program.eval(&ctx)?;
+            res.push(value.as_ref().and_then(components).unwrap_or_default());
+        }
+    }
+    Ok(res)
+}
+
+/// Components of a numeric value, with floats splatted to all four
+fn components(value: &DataTypeAndValue) -> Option<[f64; 4]> {
+    Some(match value {
+        DataTypeAndValue::Float(x) => [*x; 4],
+        DataTypeAndValue::Integer(x) => [*x as f64; 4],
+        DataTypeAndValue::Vector2(Vector2([x, y])) => [*x, *y, 0.0, 1.0],
+        DataTypeAndValue::Color3(Vector3([x, y, z]))
+        | DataTypeAndValue::Vector3(Vector3([x, y, z])) => [*x, *y, *z, 1.0],
+        DataTypeAndValue::Color4(v) | DataTypeAndValue::Vector4(v) => v.0,
+        _ => return None,
+    })
+}
+
+fn unorm(x: f64) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn image(size: u32, data: Vec<u8>, format: TextureFormat) -> Image {
+    let mut image = Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+        RenderAssetUsages::default(),
+    );
+    // Procedural patterns usually tile, like the images they're made from
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Linear,
+        min_filter: ImageFilterMode::Linear,
+        ..Default::default()
+    });
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr as _;
+
+    const XML: &str = r#"<materialx version="1.39">
+  <nodegraph name="NG">
+    <multiply name="tint" type="color3">
+      <input name="in1" type="color3" value="0.5, 1, 1" />
+      <input name="in2" type="float" value="0.5" />
+    </multiply>
+    <texcoord name="uv" type="vector2" />
+    <separate2 name="split" type="multioutput">
+      <input name="in" type="vector2" nodename="uv" />
+    </separate2>
+    <output name="color" type="color3" nodename="tint" />
+    <output name="v" type="float" nodename="split" output="outy" />
+  </nodegraph>
+  <standard_surface name="SR" type="surfaceshader">
+    <input name="base_color" type="color3" nodegraph="NG" output="color" />
+    <input name="specular_roughness" type="float" nodegraph="NG" output="v" />
+  </standard_surface>
+</materialx>"#;
+
+    fn evaluator() -> (Evaluator, NodeId) {
+        let def = MaterialX::from_str(XML).unwrap();
+        let evaluator = Evaluator::new(&def).unwrap();
+        let surface = evaluator.graph().find(None, "SR").unwrap();
+        (evaluator, surface)
+    }
+
+    #[test]
+    fn constant_folding() {
+        let (evaluator, surface) = evaluator();
+        let program = evaluator.input(surface, "base_color").unwrap();
+        assert!(program.is_constant());
+        let value = program.eval(&ShadingContext::default()).unwrap();
+        let value = components(&value.unwrap()).unwrap();
+
+        let mut res = StandardMaterial {
+            base_color: Color::linear_rgba(1.0, 0.5, 1.0, 0.25),
+            ..Default::default()
+        };
+        fold(Slot::BaseColor, value, &mut res);
+        assert_eq!(res.base_color, Color::linear_rgba(0.25, 0.25, 0.5, 0.25));
+        fold(Slot::Roughness, [0.3; 4], &mut res);
+        assert_eq!(res.perceptual_roughness, 0.3);
+    }
+
+    #[test]
+    fn rasterize_top_row_is_v_1() {
+        let (evaluator, surface) = evaluator();
+        let program = evaluator.input(surface, "specular_roughness").unwrap();
+        assert!(!program.is_constant());
+        let texels = rasterize(&program, 2).unwrap();
+        let v = texels.iter().map(|texel| texel[0]).collect::<Vec<_>>();
+        assert_eq!(v, [0.75, 0.75, 0.25, 0.25]);
+    }
+}
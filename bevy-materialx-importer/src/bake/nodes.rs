@@ -0,0 +1,269 @@
+//! Nodes the evaluator needs for baking but doesn't have built in
+
+use super::components;
+use crate::texture::Sampling;
+use bevy_color::{ColorToComponents as _, LinearRgba};
+use bevy_image::Image;
+use materialx_parser::{
+    data_types::{DataType, DataTypeAndValue, Vector2, Vector3, Vector4},
+    eval::{EvalError, Evaluator, Inputs, ShadingContext},
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// Add texture lookups (reading from `images`, by file name), normal maps, and
+/// noise to `evaluator`
+pub(super) fn register(evaluator: &mut Evaluator, images: HashMap<String, Image>) {
+    let images = Arc::new(images);
+    for category in ["image", "tiledimage"] {
+        let images = images.clone();
+        evaluator.register(category, move |inputs, ctx| image(inputs, ctx, &images));
+    }
+    evaluator.register("normalmap", normalmap);
+    evaluator.register("noise2d", |inputs, ctx| {
+        let [u, v] = vector2(inputs, "texcoord", ctx.uv.0)?;
+        noise(inputs, [u, v, 0.0], perlin)
+    });
+    evaluator.register("noise3d", |inputs, ctx| {
+        let position = vector3(inputs, "position", ctx.position.0)?;
+        noise(inputs, position, perlin)
+    });
+    evaluator.register("fractal3d", |inputs, ctx| {
+        let position = vector3(inputs, "position", ctx.position.0)?;
+        let octaves = inputs.float("octaves", 3.0)? as u32;
+        let lacunarity = inputs.float("lacunarity", 2.0)?;
+        let diminish = inputs.float("diminish", 0.5)?;
+        noise(inputs, position, |p| {
+            let (mut sum, mut amplitude, mut frequency) = (0.0, 1.0, 1.0);
+            for _ in 0..octaves {
+                sum += amplitude * perlin(p.map(|x| x * frequency));
+                amplitude *= diminish;
+                frequency *= lacunarity;
+            }
+            sum
+        })
+    });
+    evaluator.register("cellnoise2d", |inputs, ctx| {
+        let [u, v] = vector2(inputs, "texcoord", ctx.uv.0)?;
+        Ok(DataTypeAndValue::Float(cell([u, v, 0.0])))
+    });
+    evaluator.register("cellnoise3d", |inputs, ctx| {
+        let position = vector3(inputs, "position", ctx.position.0)?;
+        Ok(DataTypeAndValue::Float(cell(position)))
+    });
+}
+
+/// `image` and `tiledimage`, sampled like [`Sampling`] says
+///
+/// Files that couldn't be loaded, and texels outside of the image with a
+/// `constant` address mode, read as the `default` input.
+fn image(
+    inputs: &Inputs,
+    ctx: &ShadingContext,
+    images: &HashMap<String, Image>,
+) -> Result<DataTypeAndValue, EvalError> {
+    let default = inputs
+        .get("default")
+        .and_then(components)
+        .unwrap_or_default();
+    let image = match inputs.get("file") {
+        Some(DataTypeAndValue::Filename(file)) => images.get(file),
+        _ => None,
+    };
+    let Some(image) = image else {
+        return output(inputs, default);
+    };
+
+    let [mut u, mut v] = vector2(inputs, "texcoord", ctx.uv.0)?;
+    if inputs.node.category == "tiledimage" {
+        let [tile_u, tile_v] = vector2(inputs, "uvtiling", [1.0, 1.0])?;
+        let [offset_u, offset_v] = vector2(inputs, "uvoffset", [0.0, 0.0])?;
+        u = u * tile_u - offset_u;
+        v = v * tile_v - offset_v;
+    }
+    let sampling = Sampling::new(&inputs.node.category, |name| inputs.get(name).cloned());
+
+    // Images were loaded as sRGB if their color space says so
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let texel = |x: f64, y: f64| -> [f64; 4] {
+        let (Some(x), Some(y)) = (sampling.u.texel(x, width), sampling.v.texel(y, height)) else {
+            return default;
+        };
+        let color = match image.get_color_at(x as u32, y as u32) {
+            Ok(color) => LinearRgba::from(color).to_f32_array(),
+            Err(_) => [0.0; 4],
+        };
+        color.map(f64::from)
+    };
+
+    // `v` goes up, rows go down
+    let x = u * width;
+    let y = (1.0 - v) * height;
+    if sampling.closest {
+        return output(inputs, texel(x.floor(), y.floor()));
+    }
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let lerp = |a: [f64; 4], b: [f64; 4], t: f64| -> [f64; 4] {
+        std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+    };
+    let top = lerp(texel(x0, y0), texel(x0 + 1.0, y0), fx);
+    let bottom = lerp(texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0), fx);
+    output(inputs, lerp(top, bottom, fy))
+}
+
+/// `normalmap`: transform a tangent space normal into the shading frame
+fn normalmap(inputs: &Inputs, ctx: &ShadingContext) -> Result<DataTypeAndValue, EvalError> {
+    let [x, y, z] = vector3(inputs, "in", [0.5, 0.5, 1.0])?;
+    let scale = inputs.float("scale", 1.0)?;
+    let n = vector3(inputs, "normal", ctx.normal.0)?;
+    let t = vector3(inputs, "tangent", ctx.tangent.0)?;
+    let b = vector3(inputs, "bitangent", cross(n, t))?;
+    let (x, y, z) = (
+        (x * 2.0 - 1.0) * scale,
+        (y * 2.0 - 1.0) * scale,
+        z * 2.0 - 1.0,
+    );
+    let v: [f64; 3] = std::array::from_fn(|i| t[i] * x + b[i] * y + n[i] * z);
+    let length = v.iter().map(|c| c * c).sum::<f64>().sqrt();
+    Ok(DataTypeAndValue::Vector3(Vector3(if length > 0.0 {
+        v.map(|c| c / length)
+    } else {
+        n
+    })))
+}
+
+/// Offsets of the noise fields used for the components of vector outputs
+pub(crate) const COMPONENT_OFFSETS: [[f64; 3]; 4] = [
+    [0.0, 0.0, 0.0],
+    [19.1, 33.4, 47.2],
+    [74.2, -124.5, 99.4],
+    [-3.9, 57.1, -81.3],
+];
+
+/// `f(position) * amplitude + pivot`, once per component of the output
+fn noise(
+    inputs: &Inputs,
+    position: [f64; 3],
+    f: impl Fn([f64; 3]) -> f64,
+) -> Result<DataTypeAndValue, EvalError> {
+    let amplitude = match inputs.get("amplitude") {
+        None => [1.0; 4],
+        Some(value) => components(value)
+            .ok_or_else(|| invalid(inputs, "amplitude", "a numeric type", value))?,
+    };
+    let pivot = inputs.float("pivot", 0.0)?;
+    let res = std::array::from_fn(|i| {
+        let p = std::array::from_fn(|j| position[j] + COMPONENT_OFFSETS[i][j]);
+        f(p) * amplitude[i] + pivot
+    });
+    output(inputs, res)
+}
+
+/// Gradient noise in about `-1..1`
+fn perlin(p: [f64; 3]) -> f64 {
+    let cell = p.map(|x| x.floor());
+    let [fx, fy, fz] = std::array::from_fn(|i| p[i] - cell[i]);
+    let [x, y, z] = cell.map(|x| x as i64);
+    let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        let h = hash([x + dx, y + dy, z + dz]) & 15;
+        let (px, py, pz) = (fx - dx as f64, fy - dy as f64, fz - dz as f64);
+        // Ken Perlin's 12 gradients (and 4 repeats) along the cube edges
+        let u = if h < 8 { px } else { py };
+        let v = match h {
+            0..=3 => py,
+            12 | 14 => px,
+            _ => pz,
+        };
+        (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+    };
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+/// A random value in `0..1` for each unit cell
+fn cell(p: [f64; 3]) -> f64 {
+    hash(p.map(|x| x.floor() as i64)) as f64 / u32::MAX as f64
+}
+
+fn hash([x, y, z]: [i64; 3]) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn vector2(inputs: &Inputs, name: &str, default: [f64; 2]) -> Result<[f64; 2], EvalError> {
+    match inputs.get(name) {
+        None => Ok(default),
+        Some(DataTypeAndValue::Vector2(v)) => Ok(v.0),
+        Some(DataTypeAndValue::Float(x)) => Ok([*x; 2]),
+        Some(other) => Err(invalid(inputs, name, "a vector2", other)),
+    }
+}
+
+fn vector3(inputs: &Inputs, name: &str, default: [f64; 3]) -> Result<[f64; 3], EvalError> {
+    match inputs.get(name) {
+        None => Ok(default),
+        Some(DataTypeAndValue::Vector3(v)) => Ok(v.0),
+        Some(DataTypeAndValue::Float(x)) => Ok([*x; 3]),
+        Some(other) => Err(invalid(inputs, name, "a vector3", other)),
+    }
+}
+
+fn invalid(
+    inputs: &Inputs,
+    input: &str,
+    expected: &'static str,
+    found: &DataTypeAndValue,
+) -> EvalError {
+    EvalError::InvalidInput {
+        node: inputs.node.name.clone(),
+        input: input.into(),
+        expected,
+        found: found.tag(),
+    }
+}
+
+/// A value of the node's output type made from the first components of `v`
+fn output(inputs: &Inputs, [x, y, z, w]: [f64; 4]) -> Result<DataTypeAndValue, EvalError> {
+    Ok(match &inputs.node.r#type {
+        DataType::Float => DataTypeAndValue::Float(x),
+        DataType::Vector2 => DataTypeAndValue::Vector2(Vector2([x, y])),
+        DataType::Vector3 => DataTypeAndValue::Vector3(Vector3([x, y, z])),
+        DataType::Color3 => DataTypeAndValue::Color3(Vector3([x, y, z])),
+        DataType::Vector4 => DataTypeAndValue::Vector4(Vector4([x, y, z, w])),
+        DataType::Color4 => DataTypeAndValue::Color4(Vector4([x, y, z, w])),
+        other => {
+            return Err(EvalError::InvalidOutput {
+                node: inputs.node.name.clone(),
+                expected: "a numeric type",
+                found: other.clone(),
+            })
+        }
+    })
+}
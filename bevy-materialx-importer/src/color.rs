@@ -0,0 +1,40 @@
+//! Decoding textures according to their color space
+//!
+//! Constant colors are converted to linear Rec.709 by the parser (see
+//! [`materialx_parser::color`]). Images are decoded by the GPU, which can only
+//! undo the sRGB transfer function: gamma encoded images are approximated
+//! with it, and images with other primaries than Rec.709 aren't converted.
+
+use crate::settings::MaterialXLoaderSettings;
+use materialx_parser::{
+    ast::ColorSpace,
+    color::{Primaries, Transfer},
+};
+use tracing::debug;
+
+/// Whether an image in `colorspace` is loaded as sRGB
+///
+/// Only images holding `color` data are; normals, roughness and other data
+/// are read as they are stored.
+pub(crate) fn decode_srgb(colorspace: &ColorSpace, color: bool) -> bool {
+    if !color {
+        return false;
+    }
+    if !matches!(colorspace.primaries(), Some(Primaries::Rec709) | None) {
+        debug!("Reading texture in `{colorspace}` with Rec.709 primaries");
+    }
+    matches!(
+        colorspace.transfer(),
+        Some(Transfer::Srgb | Transfer::Gamma(_))
+    )
+}
+
+/// Color space of images that neither declare one nor are in a document that
+/// does
+pub(crate) fn default_colorspace(settings: &MaterialXLoaderSettings) -> ColorSpace {
+    // Unknown names parse as `ColorSpace::Unknown`, which reads images as is
+    settings
+        .default_colorspace
+        .parse()
+        .unwrap_or(ColorSpace::SrgbTexture)
+}
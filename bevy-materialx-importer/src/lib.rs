@@ -1,22 +1,68 @@
 #![doc = include_str!("../README.md")]
 
-use bevy_app::{App, Plugin};
-use bevy_asset::AssetApp as _;
+use bevy_app::{App, Plugin, Update};
+use bevy_asset::{processor::LoadTransformAndSave, AssetApp as _, AssetServer};
+use bevy_pbr::MaterialPlugin;
 use bevy_reflect::Reflect;
+use materialx_parser::nodedef::NodeDefRegistry;
+use std::sync::Arc;
 
+mod bake;
+pub use bake::BakeSettings;
+mod color;
+mod shader;
+pub use shader::{
+    generate_wgsl, GeneratedShader, MaterialXExtension, MaterialXMaterial, ShaderCache,
+    ShaderError, ShaderMaterialError, Texture, Uniform,
+};
 pub(crate) mod standard_material;
-pub use standard_material::material_to_pbr;
+pub use standard_material::{
+    material_to_pbr, MaterialXProcessor, MaterialXToStandardMaterial, ProcessError,
+    ProcessedMaterial, StandardMaterialLoader, StandardMaterialSaver,
+};
+mod settings;
+pub use settings::{MaterialTarget, MaterialXLoaderSettings, UnsupportedInputs};
 mod loader;
-pub use loader::{MaterialX, MaterialXLoader};
+pub use loader::{MaterialX, MaterialXLoader, NamedMaterial};
+mod look;
+pub use look::ApplyLook;
+mod nodegraph;
+mod texture;
+mod udim;
+pub use nodegraph::{BuildError, GraphInput, InputValue, MaterialXNodeGraph, VariantError};
 
 #[derive(Debug, Default, Clone, Reflect)]
-pub struct MaterialXPlugin;
+pub struct MaterialXPlugin {
+    /// Node definitions (e.g. from MaterialX's `stdlib` and `bxdf` libraries)
+    /// used to fill in default values for inputs missing on nodes
+    #[reflect(ignore)]
+    pub nodedefs: Arc<NodeDefRegistry>,
+    /// Process `.mtlx` files into `StandardMaterial`s by default when Bevy
+    /// runs the asset processor, see [`MaterialXProcessor`]
+    pub process: bool,
+}
 
 impl Plugin for MaterialXPlugin {
     fn build(&self, app: &mut App) {
-        app.register_asset_loader(MaterialXLoader);
+        let server = app.world().resource::<AssetServer>().clone();
+        app.register_asset_loader(MaterialXLoader {
+            nodedefs: self.nodedefs.clone(),
+            shaders: ShaderCache::new(server),
+        });
+        app.register_asset_loader(StandardMaterialLoader);
+        app.register_asset_processor::<MaterialXProcessor>(LoadTransformAndSave::new(
+            MaterialXToStandardMaterial,
+            StandardMaterialSaver,
+        ));
+        if self.process {
+            app.set_default_asset_processor::<MaterialXProcessor>("mtlx");
+        }
+        app.add_plugins(MaterialPlugin::<MaterialXMaterial>::default());
         app.init_asset::<MaterialX>();
+        app.init_asset::<MaterialXNodeGraph>();
         app.register_type::<MaterialX>();
         app.register_asset_reflect::<MaterialX>();
+        app.register_type::<ApplyLook>();
+        app.add_systems(Update, look::apply_looks);
     }
 }
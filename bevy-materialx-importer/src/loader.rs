@@ -1,33 +1,95 @@
-// TODO: Add preprocessor to convert mtlx to standard material in some format (e.g. ron)
-
-use crate::{material_to_pbr, standard_material::StandardMaterialTransformError};
-use bevy_asset::{io::Reader, Asset, AssetLoader, AsyncReadExt, LoadContext, ReflectAsset};
+use crate::{
+    bake,
+    color::default_colorspace,
+    nodegraph::{nodegraph_asset, variant_material, MaterialXNodeGraph},
+    settings::{MaterialTarget, MaterialXLoaderSettings},
+    shader::{shader_material, MaterialXMaterial, ShaderCache},
+    standard_material::{convert_material, find_material, StandardMaterialTransformError},
+    texture::{TextureFiles, Textures},
+    udim::load_udim_sets,
+};
+use bevy_asset::{
+    io::{AssetSourceId, Reader},
+    Asset, AssetLoader, AssetPath, AsyncReadExt, Handle, LoadContext, ReadAssetBytesError,
+    ReflectAsset,
+};
 use bevy_pbr::StandardMaterial;
 use bevy_reflect::Reflect;
+use materialx_parser::{
+    ast::{ColorSpace, FileResolver, IncludeError},
+    eval::Evaluator,
+    filename::Udim,
+    graph::NodeGraph,
+    nodedef::NodeDefRegistry,
+    Element,
+};
 use smol_str::SmolStr;
-use std::str::FromStr;
+use std::{collections::HashMap, sync::Arc};
+use tracing::warn;
 
-#[derive(Debug, Default)]
-pub struct MaterialXLoader;
+#[derive(Debug)]
+pub struct MaterialXLoader {
+    /// Node definitions used to fill in default input values
+    pub nodedefs: Arc<NodeDefRegistry>,
+    /// Shaders shared by all materials this loader generates
+    pub shaders: ShaderCache,
+}
 
 #[derive(Debug, Asset, Reflect)]
 #[reflect(Asset)]
 pub struct MaterialX {
     pub file_name: Option<String>,
-    pub material_name: Option<SmolStr>,
+    /// The first `surfacematerial` converted to a `StandardMaterial`, or the
+    /// default if the file only defines nodegraphs
+    ///
+    /// Loading `file.mtlx#name` gives the material `name` as the
+    /// [labeled sub-asset](Self::materials) instead.
     pub material: StandardMaterial,
+    /// The material rendered with a shader generated from its node graph
+    ///
+    /// `None` if the graph uses nodes that have no WGSL implementation.
+    pub shader_material: Option<Handle<MaterialXMaterial>>,
+    /// Nodegraphs producing surface shaders, also available as sub-assets
+    /// labeled with their names
+    pub nodegraphs: Vec<Handle<MaterialXNodeGraph>>,
+    /// Materials for the variants of all variant sets, also available as
+    /// sub-assets labeled `{variantset}/{variant}`
+    pub variants: Vec<Handle<MaterialXMaterial>>,
+    /// All `surfacematerial`s in document order, also available as
+    /// sub-assets labeled with their names
+    pub materials: Vec<NamedMaterial>,
     pub source: materialx_parser::MaterialX,
+    /// The image files the `StandardMaterial`s read, for the
+    /// [processor](crate::MaterialXProcessor)
+    #[reflect(ignore)]
+    pub(crate) files: TextureFiles,
+}
+
+/// A `surfacematerial` of a [`MaterialX`] file
+#[derive(Debug, Clone, Reflect)]
+pub struct NamedMaterial {
+    pub name: SmolStr,
+    pub material: Handle<StandardMaterial>,
+    /// Labeled `{name}/MaterialXMaterial`, see [`MaterialX::shader_material`]
+    pub shader_material: Option<Handle<MaterialXMaterial>>,
+}
+
+impl MaterialX {
+    /// The `surfacematerial` named `name`
+    pub fn material(&self, name: &str) -> Option<&NamedMaterial> {
+        self.materials.iter().find(|material| material.name == name)
+    }
 }
 
 impl AssetLoader for MaterialXLoader {
     type Asset = MaterialX;
-    type Settings = ();
+    type Settings = MaterialXLoaderSettings;
     type Error = LoaderError;
 
-    async fn load<'a>(
-        &'a self,
+    async fn load(
+        &self,
         reader: &mut dyn Reader,
-        _settings: &(),
+        settings: &MaterialXLoaderSettings,
         load_context: &mut LoadContext<'_>,
     ) -> Result<Self::Asset, Self::Error> {
         let mut res = String::new();
@@ -38,20 +100,81 @@ impl AssetLoader for MaterialXLoader {
                 path: load_context.path().to_string_lossy().to_string(),
                 source: e,
             })?;
-        let def = materialx_parser::MaterialX::from_str(&res)?;
         let path = load_context.asset_path().to_owned();
-        let material_name = load_context.asset_path().label().map(|x| x.into());
+        let mut def = load_document(res, &path, load_context).await?;
+        def.flatten_filenames();
+        if !settings.texture_search_paths.is_empty() {
+            find_textures(
+                &mut def,
+                &settings.texture_search_paths,
+                &path,
+                load_context,
+            )
+            .await;
+        }
+        if !self.nodedefs.is_empty() {
+            for issue in self.nodedefs.apply_defaults(&mut def) {
+                warn!(%path, "{issue}");
+            }
+        }
+        let udims = load_udim_sets(&mut def, &path, load_context).await;
 
-        let material = material_to_pbr(&def, material_name.clone(), &path, load_context)?;
+        // Shared by the shaders of all nodegraphs and materials
+        let graph = match settings.target {
+            MaterialTarget::StandardMaterial => None,
+            MaterialTarget::Shader => match NodeGraph::try_from(&def) {
+                Ok(graph) => Some(graph),
+                Err(e) => {
+                    warn!(%path, "Can't build node graph, no shaders are generated: {e}");
+                    None
+                }
+            },
+        };
+        let (graphs, variants) = match &graph {
+            None => (Vec::new(), Vec::new()),
+            Some(graph) => {
+                let colorspace = default_colorspace(settings);
+                let graphs = self.load_nodegraphs(&def, graph, &colorspace, &path, load_context);
+                let variants = load_variants(&def, &graphs, &path, load_context);
+                (graphs, variants)
+            }
+        };
+        let nodegraphs = graphs
+            .into_iter()
+            .map(|graph| load_context.add_labeled_asset(graph.name.to_string(), graph))
+            .collect::<Vec<_>>();
+        let mut textures = Textures {
+            files: TextureFiles::new(),
+            udims,
+        };
+        // Shared by the baking of all materials
+        let mut evaluator = bake::evaluator(&def, settings, &path);
+        let (materials, root) = self
+            .load_materials(
+                &def,
+                graph.as_ref(),
+                settings,
+                &mut textures,
+                evaluator.as_mut(),
+                &path,
+                load_context,
+            )
+            .await?;
+        let (material, shader_material) = match root {
+            Some(root) => root,
+            None if !nodegraphs.is_empty() => (StandardMaterial::default(), None),
+            None => return Err(StandardMaterialTransformError::NoMaterialDefined.into()),
+        };
 
         Ok(MaterialX {
-            file_name: path
-                .path()
-                .file_name()
-                .map(|x| x.to_string_lossy().to_string()),
-            material_name,
+            file_name: file_name(&path),
             material,
+            shader_material,
+            nodegraphs,
+            variants,
+            materials,
             source: def,
+            files: textures.files,
         })
     }
 
@@ -60,6 +183,376 @@ impl AssetLoader for MaterialXLoader {
     }
 }
 
+impl MaterialXLoader {
+    /// The nodegraphs producing surface shaders
+    ///
+    /// Nodegraphs that can't be turned into shaders are skipped with a warning.
+    fn load_nodegraphs(
+        &self,
+        def: &materialx_parser::MaterialX,
+        graph: &NodeGraph,
+        default_colorspace: &ColorSpace,
+        path: &AssetPath<'_>,
+        load_context: &mut LoadContext<'_>,
+    ) -> Vec<MaterialXNodeGraph> {
+        let mut res = Vec::new();
+        for nodegraph in def.elements.values().filter(|e| e.tag == "nodegraph") {
+            let asset = nodegraph_asset(
+                def,
+                graph,
+                nodegraph,
+                default_colorspace,
+                &self.shaders,
+                path,
+                load_context,
+            );
+            match asset {
+                Ok(Some(asset)) => res.push(asset),
+                Ok(None) => {}
+                Err(e) => warn!(%path, "Can't load nodegraph {}: {e}", nodegraph.name),
+            }
+        }
+        res
+    }
+
+    /// Add every `surfacematerial` as a sub-asset labeled with its name, and
+    /// its [`MaterialXMaterial`] labeled `{name}/MaterialXMaterial`
+    ///
+    /// Also returns the first material for the root asset. Materials that can't be converted are replaced by the
+    /// [fallback material](MaterialXLoaderSettings::fallback_material),
+    /// including its shader, if there is one, or else skipped with a warning.
+    /// Failing to convert the first material without a fallback is an error.
+    ///
+    /// Shaders are only generated if there is a `graph`, and textures only
+    /// baked if there is an `evaluator`. The textures of the materials are
+    /// recorded in `textures`.
+    #[allow(clippy::too_many_arguments)]
+    async fn load_materials(
+        &self,
+        def: &materialx_parser::MaterialX,
+        graph: Option<&NodeGraph>,
+        settings: &MaterialXLoaderSettings,
+        textures: &mut Textures,
+        mut evaluator: Option<&mut Evaluator>,
+        path: &AssetPath<'_>,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<
+        (
+            Vec<NamedMaterial>,
+            Option<(StandardMaterial, Option<Handle<MaterialXMaterial>>)>,
+        ),
+        LoaderError,
+    > {
+        let names = def
+            .elements
+            .values()
+            .filter(|e| e.tag == "surfacematerial")
+            .map(|e| e.name.clone())
+            .collect::<Vec<_>>();
+        let fallback = match &settings.fallback_material {
+            Some(name) => {
+                let name = Some(name.into());
+                let evaluator = evaluator.as_deref_mut();
+                Some(
+                    convert_material(def, name, path, settings, textures, evaluator, load_context)
+                        .await?,
+                )
+            }
+            None => None,
+        };
+
+        let mut res = Vec::new();
+        let mut root_material = None;
+        for (i, name) in names.iter().enumerate() {
+            let is_root = i == 0;
+            let material = convert_material(
+                def,
+                Some(name.clone()),
+                path,
+                settings,
+                textures,
+                evaluator.as_deref_mut(),
+                load_context,
+            )
+            .await
+            .map(Some);
+            // The material whose surface shader the shader is made from
+            let mut source = name.as_str();
+            let material = match (material, &fallback) {
+                (Ok(material), _) => material,
+                (Err(e), Some(fallback)) => {
+                    warn!(%path, "Using fallback for material {name}: {e}");
+                    source = settings.fallback_material.as_deref().unwrap_or_default();
+                    Some(fallback.clone())
+                }
+                (Err(e), None) if is_root => return Err(e.into()),
+                (Err(e), None) => {
+                    warn!(%path, "Can't load material {name}: {e}");
+                    None
+                }
+            };
+            let Some(material) = material else {
+                continue;
+            };
+
+            let shader_material = graph.and_then(|graph| {
+                self.load_shader_material(
+                    def,
+                    graph,
+                    source,
+                    name,
+                    material.clone(),
+                    &default_colorspace(settings),
+                    path,
+                    load_context,
+                )
+            });
+            if is_root {
+                root_material = Some((material.clone(), shader_material.clone()));
+            }
+            res.push(NamedMaterial {
+                name: name.clone(),
+                material: load_context.add_labeled_asset(name.to_string(), material),
+                shader_material,
+            });
+        }
+        Ok((res, root_material))
+    }
+
+    /// The [`MaterialXMaterial`] for the material `source`, added as a
+    /// sub-asset labeled `{name}/MaterialXMaterial`
+    #[allow(clippy::too_many_arguments)]
+    fn load_shader_material(
+        &self,
+        def: &materialx_parser::MaterialX,
+        graph: &NodeGraph,
+        source: &str,
+        name: &SmolStr,
+        base: StandardMaterial,
+        default_colorspace: &ColorSpace,
+        path: &AssetPath<'_>,
+        load_context: &mut LoadContext<'_>,
+    ) -> Option<Handle<MaterialXMaterial>> {
+        let material = find_material(def, Some(source.into()))
+            .map_err(|e| e.to_string())
+            .and_then(|(_, surface)| {
+                shader_material(
+                    graph,
+                    &surface.name,
+                    name,
+                    default_colorspace,
+                    &self.shaders,
+                    path,
+                    load_context,
+                )
+                .map_err(|e| e.to_string())
+            });
+        match material {
+            Ok(material) => {
+                let material = MaterialXMaterial { base, ..material };
+                Some(load_context.add_labeled_asset(format!("{name}/MaterialXMaterial"), material))
+            }
+            Err(e) => {
+                warn!(%path, "No shader for material {name}: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// Parse `source`, the contents of the file at `path`, and resolve its includes
+///
+/// The parser reads files synchronously, so included files are read through
+/// the asset server one at a time, whenever parsing stops at one it hasn't
+/// read yet.
+async fn load_document(
+    source: String,
+    path: &AssetPath<'_>,
+    load_context: &mut LoadContext<'_>,
+) -> Result<materialx_parser::MaterialX, LoaderError> {
+    let root = path.without_label().to_string();
+    let mut files = Prefetched(HashMap::from([(SmolStr::from(&root), source)]));
+    loop {
+        let err = match materialx_parser::MaterialX::load_with_resolver(&root, &files) {
+            Ok(def) => return Ok(def),
+            Err(e) => e,
+        };
+        let Some(missing) = unread_include(&err).filter(|p| !files.0.contains_key(*p)) else {
+            return Err(err.into());
+        };
+        let missing = missing.clone();
+        let bytes = load_context
+            .read_asset_bytes(missing.as_str())
+            .await
+            .map_err(|source| LoaderError::FailedToReadInclude {
+                path: missing.to_string(),
+                source,
+            })?;
+        files
+            .0
+            .insert(missing, String::from_utf8_lossy(&bytes).into_owned());
+    }
+}
+
+/// Point `filename` inputs whose files don't exist relative to `path` to the
+/// first directory in `search_paths` containing them
+async fn find_textures(
+    def: &mut materialx_parser::MaterialX,
+    search_paths: &[String],
+    path: &AssetPath<'_>,
+    load_context: &mut LoadContext<'_>,
+) {
+    let mut files = Vec::new();
+    for element in def.elements.values() {
+        collect_files(element, &mut files);
+    }
+    files.sort();
+    files.dedup();
+
+    let mut found = HashMap::new();
+    for file in files {
+        // UDIM sets are found by their first tile
+        let first = Udim::FIRST.filename(&file);
+        let Ok(relative) = path.resolve_embed(&first) else {
+            continue;
+        };
+        if load_context.read_asset_bytes(relative).await.is_ok() {
+            continue;
+        }
+        for dir in search_paths {
+            let Ok(candidate) = AssetPath::parse(dir).resolve(&file) else {
+                continue;
+            };
+            let Ok(first) = AssetPath::parse(dir).resolve(&first) else {
+                continue;
+            };
+            if load_context.read_asset_bytes(first).await.is_ok() {
+                // Absolute asset paths aren't resolved relative to the file
+                let candidate = match candidate.source() {
+                    AssetSourceId::Default => format!("/{}", candidate.path().display()),
+                    _ => candidate.to_string(),
+                };
+                found.insert(file.clone(), SmolStr::from(candidate));
+                break;
+            }
+        }
+        if !found.contains_key(&file) {
+            warn!(%path, "Texture {file} not found");
+        }
+    }
+
+    if !found.is_empty() {
+        for element in def.elements.values_mut() {
+            replace_files(element, &found);
+        }
+    }
+}
+
+fn collect_files(element: &Element, files: &mut Vec<SmolStr>) {
+    if element
+        .attributes
+        .get("type")
+        .is_some_and(|t| t == "filename")
+    {
+        files.extend(element.attributes.get("value").cloned());
+    }
+    for child in element.children.values() {
+        collect_files(child, files);
+    }
+}
+
+fn replace_files(element: &mut Element, found: &HashMap<SmolStr, SmolStr>) {
+    if element
+        .attributes
+        .get("type")
+        .is_some_and(|t| t == "filename")
+    {
+        if let Some(value) = element.attributes.get_mut("value") {
+            if let Some(file) = found.get(value) {
+                *value = file.clone();
+            }
+        }
+    }
+    for child in element.children.values_mut() {
+        replace_files(child, found);
+    }
+}
+
+/// The file that couldn't be read, if that's why loading failed
+fn unread_include(err: &materialx_parser::Error) -> Option<&SmolStr> {
+    match err {
+        materialx_parser::Error::Include(IncludeError::Read { path, .. }) => Some(path),
+        materialx_parser::Error::Include(IncludeError::Parse { source, .. }) => {
+            unread_include(source)
+        }
+        _ => None,
+    }
+}
+
+/// Files already read through the asset server, by asset path
+struct Prefetched(HashMap<SmolStr, String>);
+
+impl FileResolver for Prefetched {
+    fn resolve(&self, base: Option<&str>, href: &str) -> Result<SmolStr, std::io::Error> {
+        let Some(base) = base else {
+            return Ok(href.into());
+        };
+        AssetPath::parse(base)
+            .resolve_embed(href)
+            .map(|path| path.to_string().into())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    }
+
+    fn read(&self, path: &str) -> Result<String, std::io::Error> {
+        self.0
+            .get(path)
+            .cloned()
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+}
+
+/// Add the materials for the variants of all variant sets as sub-assets
+///
+/// Variant sets whose nodedef isn't implemented by one of `graphs` are skipped.
+fn load_variants(
+    def: &materialx_parser::MaterialX,
+    graphs: &[MaterialXNodeGraph],
+    path: &AssetPath<'_>,
+    load_context: &mut LoadContext<'_>,
+) -> Vec<Handle<MaterialXMaterial>> {
+    let mut res = Vec::new();
+    for set in def.variantsets() {
+        let graph = set
+            .nodegraph(def)
+            .and_then(|nodegraph| graphs.iter().find(|graph| graph.name == nodegraph.name));
+        let Some(graph) = graph else {
+            warn!(%path, "No nodegraph for variant set {}", set.name);
+            continue;
+        };
+        for (name, variant) in &set.variants {
+            let label = format!("{}/{}", set.name, name);
+            let variant = match variant {
+                Ok(variant) => variant,
+                Err(e) => {
+                    warn!(%path, "Can't read variant {label}: {e}");
+                    continue;
+                }
+            };
+            match variant_material(graph, variant, path, load_context) {
+                Ok(material) => res.push(load_context.add_labeled_asset(label, material)),
+                Err(e) => warn!(%path, "Can't build variant {label}: {e}"),
+            }
+        }
+    }
+    res
+}
+
+fn file_name(path: &AssetPath<'_>) -> Option<String> {
+    path.path()
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum LoaderError {
@@ -70,6 +563,11 @@ pub enum LoaderError {
         path: String,
         source: std::io::Error,
     },
+    #[error("Failed to read included file `{path}`: {source}")]
+    FailedToReadInclude {
+        path: String,
+        source: ReadAssetBytesError,
+    },
     #[error("Failed to convert MaterialX to StandardMaterial: {0}")]
     FailedToConvertMaterialX(#[from] StandardMaterialTransformError),
 }
@@ -0,0 +1,91 @@
+//! Applying looks to spawned scenes
+//!
+//! Add [`ApplyLook`] to an entity, usually the one with the `SceneRoot` of a
+//! glTF file, to replace the materials of the meshes below it with the ones
+//! the look assigns to them. A mesh's geometry path is made of the [`Name`]s
+//! of the entities between the root and the mesh, like `/Car/Body/Body.0`.
+
+use crate::MaterialX;
+use bevy_asset::{AssetEvent, Assets, Handle};
+use bevy_core::Name;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::{Children, HierarchyQueryExt as _, Parent};
+use bevy_pbr::MeshMaterial3d;
+use bevy_reflect::Reflect;
+use bevy_render::mesh::Mesh3d;
+use smol_str::SmolStr;
+use tracing::warn;
+
+/// Assign the materials of a look to all meshes below this entity
+///
+/// Meshes the look doesn't assign a material to keep their own. Meshes
+/// spawned later, e.g. once a scene finished loading, are updated as well.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct ApplyLook {
+    pub materialx: Handle<MaterialX>,
+    /// Name of the `<look>` element, or `None` for the first look in the file
+    pub look: Option<SmolStr>,
+}
+
+#[allow(clippy::too_many_arguments)] // System parameters
+pub(crate) fn apply_looks(
+    roots: Query<(Entity, Ref<ApplyLook>)>,
+    new_meshes: Query<Entity, Added<Mesh3d>>,
+    children: Query<&Children>,
+    parents: Query<&Parent>,
+    nodes: Query<(Option<&Name>, Has<Mesh3d>)>,
+    materialx: Res<Assets<MaterialX>>,
+    mut events: EventReader<AssetEvent<MaterialX>>,
+    mut commands: Commands,
+) {
+    let loaded = events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // Roots that got new meshes below them
+    let mut grown = Vec::new();
+    for mesh in &new_meshes {
+        let ancestors = std::iter::once(mesh).chain(parents.iter_ancestors(mesh));
+        grown.extend(ancestors.filter(|&entity| roots.contains(entity)));
+    }
+    for (root, apply) in &roots {
+        if !apply.is_changed() && !loaded.contains(&apply.materialx.id()) && !grown.contains(&root)
+        {
+            continue;
+        }
+        let Some(asset) = materialx.get(&apply.materialx) else {
+            continue;
+        };
+        let look = match &apply.look {
+            Some(name) => asset.source.look(name).ok(),
+            None => asset.source.looks().next(),
+        };
+        let Some(look) = look else {
+            warn!(look = ?apply.look, file = ?asset.file_name, "Look not found");
+            continue;
+        };
+
+        let mut stack = vec![(root, String::new())];
+        while let Some((entity, path)) = stack.pop() {
+            if nodes.get(entity).is_ok_and(|(_, mesh)| mesh) {
+                let material = look.material_for(&asset.source, &path);
+                if let Some(material) = material.and_then(|name| asset.material(&name)) {
+                    commands
+                        .entity(entity)
+                        .insert(MeshMaterial3d(material.material.clone()));
+                }
+            }
+            for &child in children.get(entity).into_iter().flatten() {
+                let path = match nodes.get(child) {
+                    Ok((Some(name), _)) => format!("{path}/{name}"),
+                    _ => path.clone(),
+                };
+                stack.push((child, path));
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Nodegraphs as material templates
+//!
+//! A `<nodegraph>` whose output is a surface shader (or a material) is loaded
+//! as a [`MaterialXNodeGraph`], labeled with the nodegraph's name. Its
+//! interface inputs can be set from code to build any number of materials
+//! that all share the graph's shader.
+
+use crate::{
+    color::decode_srgb,
+    shader::{instantiate, uniform_value, MaterialXMaterial, ShaderCache, ShaderMaterialError},
+    texture::{load_image, Sampling},
+};
+use bevy_asset::{Asset, AssetPath, Handle, LoadContext, ParseAssetPathError};
+use bevy_color::{Color, ColorToComponents as _, LinearRgba};
+use bevy_image::Image;
+use bevy_math::Vec4;
+use bevy_reflect::TypePath;
+use materialx_parser::{
+    ast::ColorSpace,
+    data_types::{DataType, DataTypeAndValue, Vector3},
+    graph::{NodeGraph, NodeId},
+    nodedef::NodeDef,
+    variant::Variant,
+    Element, GetByTypeAndName as _, MaterialX,
+};
+use smol_str::SmolStr;
+use std::collections::HashMap;
+
+/// A nodegraph producing a surface shader, with its interface inputs
+#[derive(Debug, Clone, Asset, TypePath)]
+pub struct MaterialXNodeGraph {
+    pub name: SmolStr,
+    /// Interface inputs, in document order
+    pub inputs: Vec<GraphInput>,
+    /// The material with the default values of all inputs
+    pub material: MaterialXMaterial,
+    /// Where the shader reads each interface input
+    bindings: HashMap<SmolStr, Vec<Binding>>,
+}
+
+/// An `<input>` of a nodegraph
+#[derive(Debug, Clone)]
+pub struct GraphInput {
+    pub name: SmolStr,
+    pub r#type: DataType,
+    pub default: Option<DataTypeAndValue>,
+}
+
+/// A value for an interface input, see [`MaterialXNodeGraph::build`]
+#[derive(Debug, Clone)]
+pub enum InputValue {
+    Value(DataTypeAndValue),
+    /// An image to read instead of the input's file
+    Image(Handle<Image>),
+}
+
+impl From<DataTypeAndValue> for InputValue {
+    fn from(value: DataTypeAndValue) -> Self {
+        InputValue::Value(value)
+    }
+}
+
+impl From<f32> for InputValue {
+    fn from(value: f32) -> Self {
+        InputValue::Value(value.into())
+    }
+}
+
+/// A `color3` in linear RGB, as used by MaterialX
+impl From<Color> for InputValue {
+    fn from(color: Color) -> Self {
+        let [r, g, b] = LinearRgba::from(color).to_vec3().to_array();
+        InputValue::Value(DataTypeAndValue::Color3(Vector3([r, g, b].map(f64::from))))
+    }
+}
+
+impl From<Handle<Image>> for InputValue {
+    fn from(image: Handle<Image>) -> Self {
+        InputValue::Image(image)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    /// Index in the uniform array, and the type stored there
+    Uniform(usize, DataType),
+    /// Index of the texture binding, whether images for it are loaded as
+    /// sRGB, and how they are sampled
+    Texture(usize, bool, Sampling),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum BuildError {
+    #[error("Nodegraph has no input `{name}`")]
+    UnknownInput { name: SmolStr },
+    #[error("Input `{name}` isn't read by the shader, so it can't be changed")]
+    NotOverridable { name: SmolStr },
+    #[error("Input `{name}` expects a value of type `{expected}`, got `{found}`")]
+    InvalidValue {
+        name: SmolStr,
+        expected: DataType,
+        found: DataType,
+    },
+}
+
+impl MaterialXNodeGraph {
+    pub fn input(&self, name: &str) -> Option<&GraphInput> {
+        self.inputs.iter().find(|input| input.name == name)
+    }
+
+    /// A material with `values` replacing the defaults of the given inputs
+    ///
+    /// The material uses the same shader as all other materials built from
+    /// this graph. File inputs take an [`InputValue::Image`].
+    pub fn build(&self, values: &[(&str, InputValue)]) -> Result<MaterialXMaterial, BuildError> {
+        let mut material = self.material.clone();
+        for (name, value) in values {
+            let name = SmolStr::from(*name);
+            let Some(input) = self.input(&name) else {
+                return Err(BuildError::UnknownInput { name });
+            };
+            let bindings = self.bindings.get(&name).map_or(&[][..], Vec::as_slice);
+            if bindings.is_empty() {
+                return Err(BuildError::NotOverridable { name });
+            }
+            for binding in bindings {
+                match (binding, value) {
+                    (Binding::Uniform(i, ty), InputValue::Value(value))
+                        if value.tag().as_str() == ty.as_str() =>
+                    {
+                        // `uniform_value` only fails for types that are never in uniforms
+                        let components = uniform_value(value).unwrap_or_default();
+                        material.extension.uniforms[*i] = Vec4::from_array(components);
+                    }
+                    (Binding::Texture(i, ..), InputValue::Image(image)) => {
+                        *material.extension.textures_mut()[*i] = Some(image.clone());
+                    }
+                    (_, InputValue::Value(value)) => {
+                        return Err(BuildError::InvalidValue {
+                            name,
+                            expected: input.r#type.clone(),
+                            found: value.tag(),
+                        })
+                    }
+                    (_, InputValue::Image(_)) => {
+                        return Err(BuildError::InvalidValue {
+                            name,
+                            expected: input.r#type.clone(),
+                            found: DataType::Filename,
+                        })
+                    }
+                }
+            }
+        }
+        Ok(material)
+    }
+}
+
+/// The nodegraph asset for `nodegraph`, `None` if it doesn't output a surface
+/// shader
+///
+/// The material's shader is added to `shaders` unless it already has one with
+/// the same structure.
+pub(crate) fn nodegraph_asset(
+    def: &MaterialX,
+    graph: &NodeGraph,
+    nodegraph: &Element,
+    default_colorspace: &ColorSpace,
+    shaders: &ShaderCache,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> Result<Option<MaterialXNodeGraph>, ShaderMaterialError> {
+    let Some(surface) = find_surface(graph, nodegraph) else {
+        return Ok(None);
+    };
+    let (material, generated) = instantiate(
+        graph,
+        surface,
+        &nodegraph.name,
+        default_colorspace,
+        shaders,
+        path,
+        loader,
+    )?;
+
+    let mut bindings = HashMap::<SmolStr, Vec<Binding>>::new();
+    for (i, uniform) in generated.uniforms.iter().enumerate() {
+        if let Some(name) = interface_name(def, graph, uniform.node, &uniform.input) {
+            let binding = Binding::Uniform(i, uniform.r#type.clone());
+            bindings.entry(name).or_default().push(binding);
+        }
+    }
+    for node in graph.nodes() {
+        if node.nodegraph.as_ref() != Some(&nodegraph.name)
+            || !matches!(node.category.as_str(), "image" | "tiledimage")
+        {
+            continue;
+        }
+        let Some(name) = interface_name(def, graph, node.id, "file") else {
+            continue;
+        };
+        let texture = match node.input("file").and_then(|port| port.value.as_ref()) {
+            Some(DataTypeAndValue::Filename(file)) => {
+                generated.textures.iter().position(|t| t.file == *file)
+            }
+            _ => None,
+        };
+        if let Some(i) = texture {
+            let texture = &generated.textures[i];
+            let colorspace = texture.colorspace.as_ref().unwrap_or(default_colorspace);
+            let srgb = decode_srgb(colorspace, texture.color);
+            let binding = Binding::Texture(i, srgb, texture.sampling);
+            bindings.entry(name).or_default().push(binding);
+        }
+    }
+
+    // Functional nodegraphs take their interface from their nodedef
+    let mut inputs = nodegraph
+        .children
+        .values()
+        .filter(|e| e.tag == "input")
+        .map(|e| {
+            let tag = e.attributes.get("type").cloned().unwrap_or_default();
+            let default = e
+                .attributes
+                .get("value")
+                .and_then(|value| DataTypeAndValue::from_tag_and_value(&tag, value).ok());
+            GraphInput {
+                name: e.name.clone(),
+                r#type: data_type(&tag),
+                default: match def.colorspace_in([e, nodegraph]) {
+                    Some(colorspace) => default.map(|value| colorspace.linearize(value)),
+                    None => default,
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    let nodedef = nodegraph
+        .attributes
+        .get("nodedef")
+        .and_then(|name| def.get::<NodeDef>(name.clone()).ok());
+    for input in nodedef.iter().flat_map(|nodedef| nodedef.inputs.values()) {
+        if !inputs.iter().any(|i| i.name == input.name) {
+            inputs.push(GraphInput {
+                name: input.name.clone(),
+                r#type: data_type(&input.r#type),
+                default: input.default_value(),
+            });
+        }
+    }
+
+    Ok(Some(MaterialXNodeGraph {
+        name: nodegraph.name.clone(),
+        inputs,
+        material,
+        bindings,
+    }))
+}
+
+/// The material for a variant of the variant set `set`, labeled
+/// `{set}/{variant}`
+///
+/// Files are loaded relative to `path`. Values for inputs that the graph
+/// doesn't have or doesn't read, like tokens, are ignored.
+pub(crate) fn variant_material(
+    graph: &MaterialXNodeGraph,
+    variant: &Variant,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> Result<MaterialXMaterial, VariantError> {
+    let mut values = Vec::new();
+    for (name, value) in &variant.values {
+        if !graph.bindings.contains_key(name) {
+            continue;
+        }
+        let value = match value {
+            DataTypeAndValue::Filename(file) => {
+                // Decoded and sampled like the file it replaces
+                let (srgb, sampling) = graph.bindings[name]
+                    .iter()
+                    .find_map(|binding| match binding {
+                        Binding::Texture(_, srgb, sampling) => Some((*srgb, *sampling)),
+                        Binding::Uniform(..) => None,
+                    })
+                    .unwrap_or_default();
+                let file = path.resolve_embed(file)?;
+                InputValue::Image(load_image(loader, file, srgb, sampling))
+            }
+            value => InputValue::Value(value.clone()),
+        };
+        values.push((name.as_str(), value));
+    }
+    Ok(graph.build(&values)?)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum VariantError {
+    #[error("Failed to build material: {0}")]
+    Build(#[from] BuildError),
+    #[error("Failed to parse asset path: {0}")]
+    ParseAssetPath(#[from] ParseAssetPathError),
+}
+
+/// The surface shader node connected to an output of `nodegraph`, either
+/// directly or through a material node
+fn find_surface(graph: &NodeGraph, nodegraph: &Element) -> Option<NodeId> {
+    nodegraph
+        .children
+        .values()
+        .filter(|e| e.tag == "output")
+        .find_map(|output| {
+            let node = output.attributes.get("nodename")?;
+            let id = graph.find(Some(&nodegraph.name), node)?;
+            match output.attributes.get("type")?.as_str() {
+                "surfaceshader" => Some(id),
+                "material" => graph
+                    .upstream(id)
+                    .find(|edge| edge.to_input == "surfaceshader")
+                    .map(|edge| edge.from),
+                _ => None,
+            }
+        })
+}
+
+/// The interface input that the input `input` of `node` is bound to
+fn interface_name(
+    def: &MaterialX,
+    graph: &NodeGraph,
+    node: NodeId,
+    input: &str,
+) -> Option<SmolStr> {
+    let node = graph.node(node);
+    let nodegraph = def.elements.get(node.nodegraph.as_ref()?)?;
+    let element = nodegraph.children.get(&node.name)?;
+    element
+        .children
+        .get(input)?
+        .attributes
+        .get("interfacename")
+        .cloned()
+}
+
+fn data_type(tag: &str) -> DataType {
+    tag.parse().unwrap_or(DataType::Unknown(tag.to_string()))
+}
@@ -0,0 +1,70 @@
+//! Settings of the [`MaterialXLoader`](crate::MaterialXLoader)
+//!
+//! Like for every Bevy asset loader, they can be set per file in a `.meta`
+//! file next to the `.mtlx` file, or when loading it with
+//! `AssetServer::load_with_settings`.
+
+use crate::BakeSettings;
+use bevy_reflect::Reflect;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
+#[serde(default)]
+pub struct MaterialXLoaderSettings {
+    pub target: MaterialTarget,
+    /// Rasterize inputs driven by procedural node graphs into textures
+    ///
+    /// Without this, such inputs keep the default value of `StandardMaterial`.
+    pub bake: Option<BakeSettings>,
+    /// Color space of color textures when neither their `file` input nor an
+    /// element containing it has a `colorspace` attribute
+    ///
+    /// Constant colors without one are always in `lin_rec709`.
+    pub default_colorspace: String,
+    /// What to do with materials using features `StandardMaterial` can't
+    /// represent
+    pub unsupported: UnsupportedInputs,
+    /// Asset paths of directories to look for textures in when they aren't
+    /// found relative to the `.mtlx` file
+    ///
+    /// Finding out whether a texture exists reads it, so this slows down
+    /// loading a bit.
+    pub texture_search_paths: Vec<String>,
+    /// Name of a material to use instead of materials that can't be converted
+    pub fallback_material: Option<String>,
+}
+
+impl Default for MaterialXLoaderSettings {
+    fn default() -> Self {
+        MaterialXLoaderSettings {
+            target: MaterialTarget::default(),
+            bake: None,
+            default_colorspace: "srgb_texture".into(),
+            unsupported: UnsupportedInputs::default(),
+            texture_search_paths: Vec::new(),
+            fallback_material: None,
+        }
+    }
+}
+
+/// What the loader turns materials into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Reflect)]
+pub enum MaterialTarget {
+    /// Only `StandardMaterial`s
+    ///
+    /// Nodegraphs and variants need generated shaders, so they aren't loaded.
+    StandardMaterial,
+    /// `StandardMaterial`s and [`MaterialXMaterial`](crate::MaterialXMaterial)s
+    /// with shaders generated from the node graphs
+    #[default]
+    Shader,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Reflect)]
+pub enum UnsupportedInputs {
+    /// Log a warning and ignore the input
+    #[default]
+    Warn,
+    /// Fail to convert the material
+    Error,
+}
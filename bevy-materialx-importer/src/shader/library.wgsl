@@ -0,0 +1,155 @@
+// Helpers used by the code generated for node graphs. The noise functions
+// match the ones used when baking on the CPU.
+
+fn mx_sample(t: texture_2d<f32>, s: sampler, uv: vec2<f32>) -> vec4<f32> {
+    // MaterialX's `v` goes up, Bevy's goes down
+    return textureSample(t, s, vec2<f32>(uv.x, 1.0 - uv.y));
+}
+
+fn mx_sample_constant(
+    t: texture_2d<f32>,
+    s: sampler,
+    uv: vec2<f32>,
+    border: vec2<bool>,
+    fallback: vec4<f32>,
+) -> vec4<f32> {
+    // Axes with a `constant` address mode read the node's `default` outside
+    // of 0..1
+    let outside = (uv < vec2<f32>(0.0)) | (uv > vec2<f32>(1.0));
+    return select(mx_sample(t, s, uv), fallback, any(outside & border));
+}
+
+fn mx_rotate2d(v: vec2<f32>, degrees: f32) -> vec2<f32> {
+    let a = radians(degrees);
+    let s = sin(a);
+    let c = cos(a);
+    return vec2<f32>(c * v.x + s * v.y, -s * v.x + c * v.y);
+}
+
+fn mx_place2d(
+    uv: vec2<f32>,
+    pivot: vec2<f32>,
+    scale: vec2<f32>,
+    rotate: f32,
+    offset: vec2<f32>,
+    order: i32,
+) -> vec2<f32> {
+    let p = uv - pivot;
+    if order == 0 {
+        return mx_rotate2d(p / scale, rotate) - offset + pivot;
+    }
+    return mx_rotate2d(p - offset, rotate) / scale + pivot;
+}
+
+fn mx_triplanar_uv(p: vec3<f32>, axis: i32, upaxis: i32) -> vec2<f32> {
+    // Coordinates across the plane facing `axis`, with `v` along the up axis
+    // on the planes containing it
+    let a = p[(axis + 1) % 3];
+    let b = p[(axis + 2) % 3];
+    return select(vec2<f32>(a, b), vec2<f32>(b, a), (axis + 1) % 3 == upaxis);
+}
+
+fn mx_triplanar(x: vec4<f32>, y: vec4<f32>, z: vec4<f32>, n: vec3<f32>, blend: f32) -> vec4<f32> {
+    // A blend of 1 weighs the planes by the normal, lower ones sharpen the
+    // transitions up to picking the plane most facing the normal
+    let w = pow(abs(normalize(n)), vec3<f32>(1.0 / clamp(blend, 0.0625, 1.0)));
+    return (x * w.x + y * w.y + z * w.z) / (w.x + w.y + w.z);
+}
+
+fn mx_normalmap(v: vec3<f32>, scale: f32, n: vec3<f32>, t: vec3<f32>, b: vec3<f32>) -> vec3<f32> {
+    let d = (v * 2.0 - 1.0) * vec3<f32>(scale, scale, 1.0);
+    return normalize(t * d.x + b * d.y + n * d.z);
+}
+
+fn mx_hsvtorgb(hsv: vec3<f32>) -> vec3<f32> {
+    let h = fract(hsv.x) * 6.0;
+    let i = floor(h);
+    let f = h - i;
+    let v = hsv.z;
+    let p = v * (1.0 - hsv.y);
+    let q = v * (1.0 - hsv.y * f);
+    let t = v * (1.0 - hsv.y * (1.0 - f));
+    switch i32(i) {
+        case 0: { return vec3<f32>(v, t, p); }
+        case 1: { return vec3<f32>(q, v, p); }
+        case 2: { return vec3<f32>(p, v, t); }
+        case 3: { return vec3<f32>(p, q, v); }
+        case 4: { return vec3<f32>(t, p, v); }
+        default: { return vec3<f32>(v, p, q); }
+    }
+}
+
+fn mx_rgbtohsv(rgb: vec3<f32>) -> vec3<f32> {
+    let max_c = max(max(rgb.r, rgb.g), rgb.b);
+    let min_c = min(min(rgb.r, rgb.g), rgb.b);
+    let delta = max_c - min_c;
+    let s = select(0.0, delta / max_c, max_c > 0.0);
+    var h = 0.0;
+    if delta == 0.0 {
+        h = 0.0;
+    } else if max_c == rgb.r {
+        let x = (rgb.g - rgb.b) / delta;
+        h = x - 6.0 * floor(x / 6.0);
+    } else if max_c == rgb.g {
+        h = (rgb.b - rgb.r) / delta + 2.0;
+    } else {
+        h = (rgb.r - rgb.g) / delta + 4.0;
+    }
+    return vec3<f32>(h / 6.0, s, max_c);
+}
+
+fn mx_hash(p: vec3<i32>) -> u32 {
+    let q = bitcast<vec3<u32>>(p);
+    var h = (q.x * 0x8da6b343u) ^ (q.y * 0xd8163841u) ^ (q.z * 0xcb1ab31fu);
+    h = h ^ (h >> 13u);
+    h = h * 0x5bd1e995u;
+    return h ^ (h >> 15u);
+}
+
+fn mx_gradient(hash: u32, p: vec3<f32>) -> f32 {
+    let h = hash & 15u;
+    let u = select(p.y, p.x, h < 8u);
+    var v = p.z;
+    if h < 4u {
+        v = p.y;
+    } else if h == 12u || h == 14u {
+        v = p.x;
+    }
+    return select(-u, u, (h & 1u) == 0u) + select(-v, v, (h & 2u) == 0u);
+}
+
+fn mx_perlin(p: vec3<f32>) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let i = vec3<i32>(cell);
+    let w = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let c000 = mx_gradient(mx_hash(i), f);
+    let c100 = mx_gradient(mx_hash(i + vec3<i32>(1, 0, 0)), f - vec3<f32>(1.0, 0.0, 0.0));
+    let c010 = mx_gradient(mx_hash(i + vec3<i32>(0, 1, 0)), f - vec3<f32>(0.0, 1.0, 0.0));
+    let c110 = mx_gradient(mx_hash(i + vec3<i32>(1, 1, 0)), f - vec3<f32>(1.0, 1.0, 0.0));
+    let c001 = mx_gradient(mx_hash(i + vec3<i32>(0, 0, 1)), f - vec3<f32>(0.0, 0.0, 1.0));
+    let c101 = mx_gradient(mx_hash(i + vec3<i32>(1, 0, 1)), f - vec3<f32>(1.0, 0.0, 1.0));
+    let c011 = mx_gradient(mx_hash(i + vec3<i32>(0, 1, 1)), f - vec3<f32>(0.0, 1.0, 1.0));
+    let c111 = mx_gradient(mx_hash(i + vec3<i32>(1, 1, 1)), f - vec3<f32>(1.0, 1.0, 1.0));
+    return mix(
+        mix(mix(c000, c100, w.x), mix(c010, c110, w.x), w.y),
+        mix(mix(c001, c101, w.x), mix(c011, c111, w.x), w.y),
+        w.z,
+    );
+}
+
+fn mx_fractal(p: vec3<f32>, octaves: i32, lacunarity: f32, diminish: f32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var i = 0; i < octaves; i++) {
+        sum += amplitude * mx_perlin(p * frequency);
+        amplitude *= diminish;
+        frequency *= lacunarity;
+    }
+    return sum;
+}
+
+fn mx_cell(p: vec3<f32>) -> f32 {
+    return f32(mx_hash(vec3<i32>(floor(p)))) / 4294967295.0;
+}
@@ -0,0 +1,322 @@
+//! Rendering node graphs with generated shaders
+//!
+//! A [`MaterialXMaterial`] is a `StandardMaterial` extended with a fragment
+//! shader generated from the node graph (see [`generate_wgsl`]). The base
+//! material holds the values of unconnected inputs and is used as is in
+//! prepasses; the generated shader computes the connected inputs before
+//! calling Bevy's PBR lighting.
+//!
+//! Materials whose graphs have the same structure share their shader through
+//! a [`ShaderCache`], and with it their render pipeline, so that e.g. many
+//! tinted variants of a material are drawn like one material with different
+//! uniforms.
+
+use crate::{color::decode_srgb, texture::load_image};
+use bevy_asset::{
+    Asset, AssetId, AssetPath, AssetServer, Handle, LoadContext, ParseAssetPathError,
+};
+use bevy_image::Image;
+use bevy_math::Vec4;
+use bevy_pbr::{
+    ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline,
+    StandardMaterial,
+};
+use bevy_reflect::Reflect;
+use bevy_render::{
+    mesh::MeshVertexBufferLayoutRef,
+    render_resource::{
+        AsBindGroup, RenderPipelineDescriptor, Shader, SpecializedMeshPipelineError,
+    },
+};
+use materialx_parser::{
+    ast::ColorSpace,
+    graph::{GraphError, NodeGraph, NodeId},
+};
+use smol_str::SmolStr;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+mod wgsl;
+pub(crate) use wgsl::uniform_value;
+pub use wgsl::{
+    generate_wgsl, GeneratedShader, ShaderError, Texture, Uniform, MAX_TEXTURES, MAX_UNIFORMS,
+};
+
+/// A material rendered with a shader generated from its node graph
+pub type MaterialXMaterial = ExtendedMaterial<StandardMaterial, MaterialXExtension>;
+
+/// Resources of a generated shader
+///
+/// The bindings match the ones declared by [`generate_wgsl`].
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+#[bind_group_data(MaterialXKey)]
+pub struct MaterialXExtension {
+    /// Constant inputs of the node graph, see [`GeneratedShader::uniforms`]
+    #[uniform(100)]
+    pub uniforms: [Vec4; MAX_UNIFORMS],
+    #[texture(101)]
+    #[sampler(102)]
+    pub texture_0: Option<Handle<Image>>,
+    #[texture(103)]
+    #[sampler(104)]
+    pub texture_1: Option<Handle<Image>>,
+    #[texture(105)]
+    #[sampler(106)]
+    pub texture_2: Option<Handle<Image>>,
+    #[texture(107)]
+    #[sampler(108)]
+    pub texture_3: Option<Handle<Image>>,
+    #[texture(109)]
+    #[sampler(110)]
+    pub texture_4: Option<Handle<Image>>,
+    #[texture(111)]
+    #[sampler(112)]
+    pub texture_5: Option<Handle<Image>>,
+    #[texture(113)]
+    #[sampler(114)]
+    pub texture_6: Option<Handle<Image>>,
+    #[texture(115)]
+    #[sampler(116)]
+    pub texture_7: Option<Handle<Image>>,
+    /// The generated fragment shader
+    pub shader: Handle<Shader>,
+}
+
+impl MaterialXExtension {
+    pub fn new(shader: Handle<Shader>) -> Self {
+        MaterialXExtension {
+            uniforms: [Vec4::ZERO; MAX_UNIFORMS],
+            texture_0: None,
+            texture_1: None,
+            texture_2: None,
+            texture_3: None,
+            texture_4: None,
+            texture_5: None,
+            texture_6: None,
+            texture_7: None,
+            shader,
+        }
+    }
+
+    /// The texture bindings, by index
+    pub fn textures_mut(&mut self) -> [&mut Option<Handle<Image>>; MAX_TEXTURES] {
+        [
+            &mut self.texture_0,
+            &mut self.texture_1,
+            &mut self.texture_2,
+            &mut self.texture_3,
+            &mut self.texture_4,
+            &mut self.texture_5,
+            &mut self.texture_6,
+            &mut self.texture_7,
+        ]
+    }
+}
+
+/// Materials with the same shader can share a pipeline
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MaterialXKey {
+    shader: AssetId<Shader>,
+}
+
+impl From<&MaterialXExtension> for MaterialXKey {
+    fn from(extension: &MaterialXExtension) -> Self {
+        MaterialXKey {
+            shader: extension.shader.id(),
+        }
+    }
+}
+
+impl MaterialExtension for MaterialXExtension {
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            // Prepasses keep using the base material's shaders
+            if !fragment.shader_defs.contains(&"PREPASS_PIPELINE".into()) {
+                fragment.shader = Handle::Weak(key.bind_group_data.shader);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generated shaders, by [structural hash](GeneratedShader::hash)
+///
+/// The shaders are added to the [`AssetServer`] rather than to the file that
+/// first needed them, so they stay valid for materials of other files even if
+/// that file fails to load or is reloaded. Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct ShaderCache {
+    server: AssetServer,
+    shaders: Arc<Mutex<Shaders>>,
+}
+
+/// Sources and their shaders by hash, more than one if hashes collide
+type Shaders = HashMap<u64, Vec<(String, Handle<Shader>)>>;
+
+impl ShaderCache {
+    pub fn new(server: AssetServer) -> Self {
+        ShaderCache {
+            server,
+            shaders: Default::default(),
+        }
+    }
+
+    /// The shader for `generated`, added with the name `path` if no material
+    /// with the same structure was loaded before
+    fn get_or_add(
+        &self,
+        generated: &GeneratedShader,
+        path: impl FnOnce() -> String,
+    ) -> Handle<Shader> {
+        let mut shaders = self.shaders.lock().unwrap_or_else(|e| e.into_inner());
+        get_or_add(&mut shaders, generated, || {
+            self.server
+                .add(Shader::from_wgsl(generated.source.clone(), path()))
+        })
+    }
+
+    /// Number of distinct shaders generated so far
+    pub fn len(&self) -> usize {
+        let shaders = self.shaders.lock().unwrap_or_else(|e| e.into_inner());
+        shaders.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn get_or_add(
+    shaders: &mut Shaders,
+    generated: &GeneratedShader,
+    add: impl FnOnce() -> Handle<Shader>,
+) -> Handle<Shader> {
+    let shaders = shaders.entry(generated.hash).or_default();
+    // Equal hashes make equal sources likely, but only the source decides
+    if let Some((_, shader)) = shaders
+        .iter()
+        .find(|(source, _)| *source == generated.source)
+    {
+        return shader.clone();
+    }
+    let shader = add();
+    shaders.push((generated.source.clone(), shader.clone()));
+    shader
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ShaderMaterialError {
+    #[error("Failed to build node graph: {0}")]
+    Graph(#[from] GraphError),
+    #[error("Surface shader `{name}` not found")]
+    SurfaceNotFound { name: SmolStr },
+    #[error("Failed to generate shader: {0}")]
+    Shader(#[from] ShaderError),
+    #[error("Failed to parse asset path: {0}")]
+    ParseAssetPath(#[from] ParseAssetPathError),
+}
+
+/// Generate the shader for the surface shader node `surface` at the root of
+/// the document `graph` was built from
+///
+/// If `shaders` has no shader with the same structure yet, the shader is
+/// added to it, named like a sub-asset labeled `{material}/shader`. The
+/// textures it reads are loaded relative to `path`, color textures that don't
+/// declare a color space as `default_colorspace`. The base material is left at
+/// its defaults.
+pub(crate) fn shader_material(
+    graph: &NodeGraph,
+    surface: &str,
+    material: &str,
+    default_colorspace: &ColorSpace,
+    shaders: &ShaderCache,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> Result<MaterialXMaterial, ShaderMaterialError> {
+    let id = graph
+        .find(None, surface)
+        .ok_or_else(|| ShaderMaterialError::SurfaceNotFound {
+            name: surface.into(),
+        })?;
+    let (material, _) = instantiate(
+        graph,
+        id,
+        material,
+        default_colorspace,
+        shaders,
+        path,
+        loader,
+    )?;
+    Ok(material)
+}
+
+/// Like [`shader_material`], for a surface shader node of `graph`, also
+/// returning the generated shader to find out where its inputs are read
+pub(crate) fn instantiate(
+    graph: &NodeGraph,
+    surface: NodeId,
+    label: &str,
+    default_colorspace: &ColorSpace,
+    shaders: &ShaderCache,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> Result<(MaterialXMaterial, GeneratedShader), ShaderMaterialError> {
+    let generated = generate_wgsl(graph, surface)?;
+
+    let shader = shaders.get_or_add(&generated, || format!("{path}#{label}/shader"));
+    let mut extension = MaterialXExtension::new(shader);
+    for (slot, uniform) in extension.uniforms.iter_mut().zip(&generated.uniforms) {
+        *slot = Vec4::from_array(uniform.value);
+    }
+    for (slot, texture) in extension
+        .textures_mut()
+        .into_iter()
+        .zip(&generated.textures)
+    {
+        let colorspace = texture.colorspace.as_ref().unwrap_or(default_colorspace);
+        let srgb = decode_srgb(colorspace, texture.color);
+        let file = path.resolve_embed(&texture.file)?;
+        *slot = Some(load_image(loader, file, srgb, texture.sampling));
+    }
+    let base = StandardMaterial::default();
+    Ok((ExtendedMaterial { base, extension }, generated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shader(source: &str, hash: u64) -> GeneratedShader {
+        GeneratedShader {
+            source: source.into(),
+            hash,
+            uniforms: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cache_compares_sources() {
+        let mut shaders = Shaders::new();
+        let mut added = 0;
+        let mut add = || {
+            added += 1;
+            Handle::weak_from_u128(added)
+        };
+        let a = get_or_add(&mut shaders, &shader("a", 1), &mut add);
+        assert_eq!(get_or_add(&mut shaders, &shader("a", 1), &mut add), a);
+        // A hash collision gets its own shader
+        let b = get_or_add(&mut shaders, &shader("b", 1), &mut add);
+        assert_ne!(a, b);
+        assert_eq!(get_or_add(&mut shaders, &shader("b", 1), &mut add), b);
+        assert_eq!(shaders[&1].len(), 2);
+    }
+}
@@ -0,0 +1,1190 @@
+//! Generating WGSL from node graphs
+//!
+//! Every node upstream of the surface shader becomes one `let` binding in the
+//! fragment shader, in dependency order. Constant inputs are read from a
+//! uniform array instead of being inlined, so that materials that only differ
+//! in their values generate the same source. Files read by `image`,
+//! `tiledimage` and `triplanarprojection` nodes get a texture and sampler
+//! binding each.
+//!
+//! Variables are numbered in the order they are emitted rather than named
+//! after nodes, so the source only depends on the structure of the graph:
+//! which nodes there are, how they are connected and which of their inputs
+//! are set. Materials with the same structure can share one shader and one
+//! pipeline, see [`GeneratedShader::hash`].
+
+use crate::{
+    bake::COMPONENT_OFFSETS,
+    texture::{AddressMode, Sampling},
+};
+use materialx_parser::{
+    ast::ColorSpace,
+    data_types::{DataType, DataTypeAndValue},
+    filename::has_udim,
+    graph::{GraphNode, NodeGraph, NodeId, Port},
+};
+use smol_str::SmolStr;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    hash::{DefaultHasher, Hash as _, Hasher as _},
+};
+
+/// Size of the uniform array holding constant inputs
+pub const MAX_UNIFORMS: usize = 64;
+/// Number of texture bindings
+pub const MAX_TEXTURES: usize = 8;
+
+/// Bindings of the extension, after those of `StandardMaterial`
+pub(crate) const UNIFORMS_BINDING: u32 = 100;
+
+const LIBRARY: &str = include_str!("library.wgsl");
+
+/// Inputs of `standard_surface` set by the generated shader, and how
+const SURFACE_INPUTS: &[(&str, DataType, &str)] = &[
+    (
+        "base_color",
+        DataType::Color3,
+        "pbr_input.material.base_color = vec4<f32>({}, pbr_input.material.base_color.a);",
+    ),
+    (
+        "specular_roughness",
+        DataType::Float,
+        "pbr_input.material.perceptual_roughness = {};",
+    ),
+    (
+        "metalness",
+        DataType::Float,
+        "pbr_input.material.metallic = {};",
+    ),
+    (
+        "coat",
+        DataType::Float,
+        "pbr_input.material.clearcoat = {};",
+    ),
+    (
+        "coat_roughness",
+        DataType::Float,
+        "pbr_input.material.clearcoat_perceptual_roughness = {};",
+    ),
+    ("normal", DataType::Vector3, "pbr_input.N = normalize({});"),
+];
+
+/// A fragment shader and the resources it reads
+#[derive(Debug, Clone)]
+pub struct GeneratedShader {
+    pub source: String,
+    /// Structural hash of the graph: equal for graphs that only differ in
+    /// their constant values and file names, which generate the same source
+    pub hash: u64,
+    /// Values for the uniform array, by index
+    pub uniforms: Vec<Uniform>,
+    /// Files read by the texture bindings, by index
+    pub textures: Vec<Texture>,
+}
+
+/// A file read by a texture binding
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub file: String,
+    /// Whether the file holds colors rather than other data, like normals
+    pub color: bool,
+    /// Color space declared for the file, see [`Port::colorspace`]
+    pub colorspace: Option<ColorSpace>,
+    pub(crate) sampling: Sampling,
+}
+
+/// A constant input stored in the uniform array
+#[derive(Debug, Clone)]
+pub struct Uniform {
+    pub node: NodeId,
+    pub input: SmolStr,
+    pub r#type: DataType,
+    /// Components of the value, padded with zeros
+    pub value: [f32; 4],
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ShaderError {
+    #[error("Node `{node}` has category `{category}`, which has no WGSL implementation")]
+    Unsupported { node: SmolStr, category: SmolStr },
+    #[error("Input `{input}` of node `{node}` has type `{found}`, which has no WGSL equivalent")]
+    InvalidInput {
+        node: SmolStr,
+        input: SmolStr,
+        found: DataType,
+    },
+    #[error("Node `{node}` has type `{found}`, which has no WGSL equivalent")]
+    InvalidOutput { node: SmolStr, found: DataType },
+    #[error("Node `{node}` depends on itself")]
+    Cycle { node: SmolStr },
+    #[error("More than {MAX_UNIFORMS} constant inputs")]
+    TooManyUniforms,
+    #[error("More than {MAX_TEXTURES} texture files")]
+    TooManyTextures,
+    #[error("Node `{node}` reads the UDIM set `{file}`, which generated shaders can't sample")]
+    UdimSet { node: SmolStr, file: String },
+}
+
+/// Generate a fragment shader for the surface shader node `surface`
+///
+/// The shader starts from `StandardMaterial`'s inputs and overrides those set
+/// on the surface shader before lighting: connected inputs with the result of
+/// their nodes, and constant ones with their uniform, so that instances of a
+/// graph can change them. Inputs that aren't set keep the base material's
+/// value.
+pub fn generate_wgsl(graph: &NodeGraph, surface: NodeId) -> Result<GeneratedShader, ShaderError> {
+    let mut generator = Generator {
+        graph,
+        body: String::new(),
+        vars: HashMap::new(),
+        uniforms: Vec::new(),
+        textures: Vec::new(),
+    };
+    let mut assignments = String::new();
+    let node = graph.node(surface);
+    for (input, ty, template) in SURFACE_INPUTS {
+        match graph.upstream(surface).find(|edge| edge.to_input == *input) {
+            Some(edge) => generator.visit(edge.from, &mut Vec::new())?,
+            // A constant normal doesn't follow the surface
+            None if *input == "normal" => continue,
+            None => {}
+        }
+        let Some(value) = generator.input(node, input)? else {
+            continue;
+        };
+        let value = cast(&value.code, &value.ty, ty);
+        writeln!(assignments, "    {}", template.replace("{}", &value)).unwrap();
+    }
+
+    let mut source = String::from(HEADER);
+    writeln!(
+        source,
+        "@group(2) @binding({UNIFORMS_BINDING}) var<uniform> mx_uniforms: array<vec4<f32>, {MAX_UNIFORMS}>;"
+    )
+    .unwrap();
+    for i in 0..generator.textures.len() {
+        let (texture, sampler) = texture_bindings(i);
+        writeln!(
+            source,
+            "@group(2) @binding({texture}) var mx_texture_{i}: texture_2d<f32>;\n\
+             @group(2) @binding({sampler}) var mx_sampler_{i}: sampler;"
+        )
+        .unwrap();
+    }
+    source.push('\n');
+    source.push_str(LIBRARY);
+    source.push_str(FRAGMENT_START);
+    source.push_str(&generator.body);
+    source.push('\n');
+    source.push_str(&assignments);
+    source.push_str(FRAGMENT_END);
+
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    Ok(GeneratedShader {
+        hash: hasher.finish(),
+        source,
+        uniforms: generator.uniforms,
+        textures: generator.textures,
+    })
+}
+
+/// Texture and sampler binding of texture `i`
+pub(crate) fn texture_bindings(i: usize) -> (u32, u32) {
+    let texture = UNIFORMS_BINDING + 1 + 2 * i as u32;
+    (texture, texture + 1)
+}
+
+const HEADER: &str = "#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_view_bindings::globals,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
+}
+
+";
+
+const FRAGMENT_START: &str = "
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+#ifdef VERTEX_UVS_A
+    let mx_texcoord = vec2<f32>(in.uv.x, 1.0 - in.uv.y);
+#else
+    let mx_texcoord = vec2<f32>(0.0);
+#endif
+    let mx_position = in.world_position.xyz;
+    let mx_normal = pbr_input.world_normal;
+#ifdef VERTEX_TANGENTS
+    let mx_tangent = normalize(in.world_tangent.xyz);
+    let mx_bitangent = cross(mx_normal, mx_tangent) * sign(in.world_tangent.w);
+#else
+    let mx_tangent = vec3<f32>(1.0, 0.0, 0.0);
+    let mx_bitangent = cross(mx_normal, mx_tangent);
+#endif
+    let mx_time = globals.time;
+
+";
+
+const FRAGMENT_END: &str = "
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+    var out: FragmentOutput;
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
+";
+
+/// A WGSL expression and its type
+struct Expr {
+    code: String,
+    ty: DataType,
+}
+
+struct Generator<'g> {
+    graph: &'g NodeGraph,
+    body: String,
+    /// Numbers and types of the variables emitted so far, by node
+    vars: HashMap<NodeId, (usize, DataType)>,
+    uniforms: Vec<Uniform>,
+    textures: Vec<Texture>,
+}
+
+impl Generator<'_> {
+    /// Emit `id` after the nodes it depends on
+    fn visit(&mut self, id: NodeId, path: &mut Vec<NodeId>) -> Result<(), ShaderError> {
+        if self.vars.contains_key(&id) {
+            return Ok(());
+        }
+        let graph = self.graph;
+        let node = graph.node(id);
+        if path.contains(&id) {
+            return Err(ShaderError::Cycle {
+                node: node.name.clone(),
+            });
+        }
+        path.push(id);
+        for edge in graph.upstream(id) {
+            self.visit(edge.from, path)?;
+        }
+        path.pop();
+
+        let (code, ty) = self.node(node)?;
+        let var = self.vars.len();
+        writeln!(self.body, "    let n{var} = {code}; // {}", node.category).unwrap();
+        self.vars.insert(id, (var, ty));
+        Ok(())
+    }
+
+    /// Variable of an emitted node, picking a component for outputs like `outx`
+    fn connected(&self, from: NodeId, output: Option<&str>) -> Expr {
+        let (var, ty) = &self.vars[&from];
+        let (var, ty) = (format!("n{var}"), ty.clone());
+        let component = match output {
+            Some("outx" | "outr") => 'x',
+            Some("outy" | "outg") => 'y',
+            Some("outz" | "outb") => 'z',
+            Some("outw" | "outa") => 'w',
+            _ => return Expr { code: var, ty },
+        };
+        Expr {
+            code: format!("{var}.{component}"),
+            ty: DataType::Float,
+        }
+    }
+
+    fn is_set(&self, node: &GraphNode, name: &str) -> bool {
+        self.graph
+            .upstream(node.id)
+            .any(|edge| edge.to_input == name)
+            || node.input(name).is_some_and(|port| port.value.is_some())
+    }
+
+    /// Value of an input with its own type, `None` if it isn't set
+    ///
+    /// Constant values are added to the uniforms.
+    fn input(&mut self, node: &GraphNode, name: &str) -> Result<Option<Expr>, ShaderError> {
+        if let Some(edge) = self
+            .graph
+            .upstream(node.id)
+            .find(|edge| edge.to_input == name)
+        {
+            return Ok(Some(self.connected(edge.from, edge.from_output.as_deref())));
+        }
+        let Some(value) = node.input(name).and_then(|port| port.value.as_ref()) else {
+            return Ok(None);
+        };
+        // Strings and file names select behavior, they are no values
+        let Some(padded) = uniform_value(value) else {
+            return Ok(None);
+        };
+        let ty = value.tag();
+        if self.uniforms.len() == MAX_UNIFORMS {
+            return Err(ShaderError::TooManyUniforms);
+        }
+        let slot = format!("mx_uniforms[{}]", self.uniforms.len());
+        self.uniforms.push(Uniform {
+            node: node.id,
+            input: name.into(),
+            r#type: ty.clone(),
+            value: padded,
+        });
+        let code = match ty {
+            DataType::Float => format!("{slot}.x"),
+            DataType::Integer => format!("i32({slot}.x)"),
+            DataType::Boolean => format!("({slot}.x != 0.0)"),
+            DataType::Vector2 => format!("{slot}.xy"),
+            DataType::Vector3 | DataType::Color3 => format!("{slot}.xyz"),
+            _ => slot,
+        };
+        Ok(Some(Expr { code, ty }))
+    }
+
+    /// Value of an input converted to `ty`, or `default` if it isn't set
+    fn arg(
+        &mut self,
+        node: &GraphNode,
+        name: &str,
+        ty: &DataType,
+        default: &str,
+    ) -> Result<String, ShaderError> {
+        match self.input(node, name)? {
+            Some(value) => {
+                if wgsl_type(&value.ty).is_none() {
+                    return Err(ShaderError::InvalidInput {
+                        node: node.name.clone(),
+                        input: name.into(),
+                        found: value.ty,
+                    });
+                }
+                Ok(cast(&value.code, &value.ty, ty))
+            }
+            None => Ok(default.into()),
+        }
+    }
+
+    /// Value of an input with its own type, or `default` of type `ty`
+    fn raw(
+        &mut self,
+        node: &GraphNode,
+        name: &str,
+        ty: DataType,
+        default: &str,
+    ) -> Result<Expr, ShaderError> {
+        Ok(match self.input(node, name)? {
+            Some(value) => value,
+            None => Expr {
+                code: default.into(),
+                ty,
+            },
+        })
+    }
+
+    /// Texture binding reading the file `name` of the image node `node`
+    ///
+    /// Nodes reading the same file share a binding, which is decoded and
+    /// sampled like the first of them wants.
+    fn texture(&mut self, node: &GraphNode, name: &str, file: &Port) -> Result<usize, ShaderError> {
+        if let Some(i) = self.textures.iter().position(|t| t.file == name) {
+            return Ok(i);
+        }
+        if has_udim(name) {
+            return Err(ShaderError::UdimSet {
+                node: node.name.clone(),
+                file: name.into(),
+            });
+        }
+        if self.textures.len() == MAX_TEXTURES {
+            return Err(ShaderError::TooManyTextures);
+        }
+        self.textures.push(Texture {
+            file: name.into(),
+            color: matches!(node.r#type, DataType::Color3 | DataType::Color4),
+            colorspace: file.colorspace.clone(),
+            sampling: Sampling::new(&node.category, |name| {
+                node.input(name).and_then(|port| port.value.clone())
+            }),
+        });
+        Ok(self.textures.len() - 1)
+    }
+
+    /// Expression computing a node, and its type
+    fn node(&mut self, node: &GraphNode) -> Result<(String, DataType), ShaderError> {
+        let t = node.r#type.clone();
+        let ty = |found: &DataType| ShaderError::InvalidOutput {
+            node: node.name.clone(),
+            found: found.clone(),
+        };
+        let splat = |x: f64| -> Result<String, ShaderError> {
+            Ok(cast(&format!("{x:?}"), &DataType::Float, &t))
+        };
+        // Multioutput nodes take the type of their input
+        if wgsl_type(&t).is_none() && !node.category.starts_with("separate") {
+            return Err(ty(&t));
+        }
+        let float = DataType::Float;
+
+        macro_rules! unary {
+            ($f:literal) => {{
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                format!($f, x)
+            }};
+            ($f:literal, $default:expr) => {{
+                let x = self.arg(node, "in", &t, &splat($default)?)?;
+                format!($f, x)
+            }};
+        }
+        macro_rules! binary {
+            ($f:literal, $default:expr) => {{
+                let a = self.arg(node, "in1", &t, &splat(0.0)?)?;
+                let b = self.arg(node, "in2", &t, &splat($default)?)?;
+                format!($f, a, b)
+            }};
+        }
+
+        let code = match node.category.as_str() {
+            "constant" => self.arg(node, "value", &t, &splat(0.0)?)?,
+            "dot" => self.arg(node, "in", &t, &splat(0.0)?)?,
+
+            "add" => binary!("({} + {})", 0.0),
+            "subtract" => binary!("({} - {})", 0.0),
+            "multiply" => binary!("({} * {})", 1.0),
+            "divide" => binary!("({} / {})", 1.0),
+            "modulo" => {
+                let a = self.arg(node, "in1", &t, &splat(0.0)?)?;
+                let b = self.arg(node, "in2", &t, &splat(1.0)?)?;
+                format!("({a} - {b} * floor({a} / {b}))")
+            }
+            "invert" => {
+                let amount = self.arg(node, "amount", &t, &splat(1.0)?)?;
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                format!("({amount} - {x})")
+            }
+            "absval" => unary!("abs({})"),
+            "sign" => unary!("sign({})"),
+            "floor" => unary!("floor({})"),
+            "ceil" => unary!("ceil({})"),
+            "round" => unary!("round({})"),
+            "power" => binary!("pow({}, {})", 1.0),
+            "safepower" => {
+                let a = self.arg(node, "in1", &t, &splat(0.0)?)?;
+                let b = self.arg(node, "in2", &t, &splat(1.0)?)?;
+                format!("(sign({a}) * pow(abs({a}), {b}))")
+            }
+            "sin" => unary!("sin({})"),
+            "cos" => unary!("cos({})"),
+            "tan" => unary!("tan({})"),
+            "asin" => unary!("asin({})"),
+            "acos" => unary!("acos({})"),
+            "atan2" => {
+                let y = self.arg(node, "iny", &t, &splat(0.0)?)?;
+                let x = self.arg(node, "inx", &t, &splat(1.0)?)?;
+                format!("atan2({y}, {x})")
+            }
+            "sqrt" => unary!("sqrt({})"),
+            "ln" => unary!("log({})", 1.0),
+            "exp" => unary!("exp({})"),
+            "clamp" => {
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                let low = self.arg(node, "low", &t, &splat(0.0)?)?;
+                let high = self.arg(node, "high", &t, &splat(1.0)?)?;
+                format!("clamp({x}, {low}, {high})")
+            }
+            "min" => binary!("min({}, {})", 0.0),
+            "max" => binary!("max({}, {})", 0.0),
+            "trianglewave" => {
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                format!("(2.0 * abs({x} - floor({x} + 0.5)))")
+            }
+            "mix" => {
+                let bg = self.arg(node, "bg", &t, &splat(0.0)?)?;
+                let fg = self.arg(node, "fg", &t, &splat(0.0)?)?;
+                let mix = self.arg(node, "mix", &t, &splat(0.0)?)?;
+                format!("mix({bg}, {fg}, {mix})")
+            }
+            "smoothstep" => {
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                let low = self.arg(node, "low", &t, &splat(0.0)?)?;
+                let high = self.arg(node, "high", &t, &splat(1.0)?)?;
+                format!("smoothstep({low}, {high}, {x})")
+            }
+            "remap" => {
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                let in_low = self.arg(node, "inlow", &t, &splat(0.0)?)?;
+                let in_high = self.arg(node, "inhigh", &t, &splat(1.0)?)?;
+                let out_low = self.arg(node, "outlow", &t, &splat(0.0)?)?;
+                let out_high = self.arg(node, "outhigh", &t, &splat(1.0)?)?;
+                format!(
+                    "({out_low} + ({x} - {in_low}) / ({in_high} - {in_low}) * ({out_high} - {out_low}))"
+                )
+            }
+
+            "and" | "or" | "xor" => {
+                let a = self.arg(node, "in1", &DataType::Boolean, "false")?;
+                let b = self.arg(node, "in2", &DataType::Boolean, "false")?;
+                let op = match node.category.as_str() {
+                    "and" => "&&",
+                    "or" => "||",
+                    _ => "!=",
+                };
+                cast(&format!("({a} {op} {b})"), &DataType::Boolean, &t)
+            }
+            "not" => {
+                let x = self.arg(node, "in", &DataType::Boolean, "false")?;
+                cast(&format!("(!{x})"), &DataType::Boolean, &t)
+            }
+            "ifgreater" | "ifgreatereq" | "ifequal" => {
+                let value1 = self.arg(node, "value1", &float, "1.0")?;
+                let value2 = self.arg(node, "value2", &float, "0.0")?;
+                let op = match node.category.as_str() {
+                    "ifgreater" => ">",
+                    "ifgreatereq" => ">=",
+                    _ => "==",
+                };
+                let condition = format!("({value1} {op} {value2})");
+                if matches!(t, DataType::Boolean) && !self.is_set(node, "in1") {
+                    condition
+                } else {
+                    let in1 = self.arg(node, "in1", &t, &splat(0.0)?)?;
+                    let in2 = self.arg(node, "in2", &t, &splat(0.0)?)?;
+                    format!("select({in2}, {in1}, {condition})")
+                }
+            }
+
+            "normalize" => unary!("normalize({})"),
+            "magnitude" => {
+                let x = self.raw(node, "in", DataType::Vector3, "vec3<f32>(0.0)")?;
+                cast(&format!("length({})", x.code), &float, &t)
+            }
+            "distance" => {
+                let a = self.raw(node, "in1", DataType::Vector3, "vec3<f32>(0.0)")?;
+                let b = self.arg(node, "in2", &a.ty, &cast("0.0", &float, &a.ty))?;
+                cast(&format!("distance({}, {b})", a.code), &float, &t)
+            }
+            "dotproduct" => {
+                let a = self.raw(node, "in1", DataType::Vector3, "vec3<f32>(0.0)")?;
+                let b = self.arg(node, "in2", &a.ty, &cast("0.0", &float, &a.ty))?;
+                cast(&format!("dot({}, {b})", a.code), &float, &t)
+            }
+            "crossproduct" => {
+                let a = self.arg(node, "in1", &t, &splat(0.0)?)?;
+                let b = self.arg(node, "in2", &t, &splat(0.0)?)?;
+                format!("cross({a}, {b})")
+            }
+            "convert" => {
+                let x = self.raw(node, "in", DataType::Float, "0.0")?;
+                cast(&x.code, &x.ty, &t)
+            }
+            "combine2" | "combine3" | "combine4" => {
+                let count = (node.category.as_bytes()[7] - b'0') as usize;
+                let mut parts = Vec::new();
+                for name in &["in1", "in2", "in3", "in4"][..count] {
+                    let part = self.raw(node, name, float.clone(), "0.0")?;
+                    parts.push(match part.ty {
+                        DataType::Integer | DataType::Boolean => cast(&part.code, &part.ty, &float),
+                        _ => part.code,
+                    });
+                }
+                format!("{}({})", wgsl_type(&t).unwrap(), parts.join(", "))
+            }
+            // The outputs are picked from the input in `connected`
+            "separate2" | "separate3" | "separate4" => {
+                let x = self.raw(node, "in", DataType::Vector4, "vec4<f32>(0.0)")?;
+                return Ok((x.code, x.ty));
+            }
+            "extract" => {
+                let x = self.raw(node, "in", DataType::Vector4, "vec4<f32>(0.0)")?;
+                let index = self.arg(node, "index", &DataType::Integer, "0")?;
+                cast(&format!("{}[{index}]", x.code), &float, &t)
+            }
+
+            "hsvtorgb" | "rgbtohsv" => {
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                let f = format!("mx_{}", node.category);
+                match t {
+                    DataType::Color4 => format!("vec4<f32>({f}({x}.rgb), {x}.a)"),
+                    _ => format!("{f}({x})"),
+                }
+            }
+            "luminance" => {
+                let x = self.arg(node, "in", &t, &splat(0.0)?)?;
+                let coeffs = self.arg(
+                    node,
+                    "lumacoeffs",
+                    &DataType::Color3,
+                    "vec3<f32>(0.2722287, 0.6740818, 0.0536895)",
+                )?;
+                match t {
+                    DataType::Color4 => {
+                        format!("vec4<f32>(vec3<f32>(dot({x}.rgb, {coeffs})), {x}.a)")
+                    }
+                    _ => format!("vec3<f32>(dot({x}, {coeffs}))"),
+                }
+            }
+
+            "texcoord" => cast("mx_texcoord", &DataType::Vector2, &t),
+            "position" => cast("mx_position", &DataType::Vector3, &t),
+            "normal" => cast("mx_normal", &DataType::Vector3, &t),
+            "tangent" => cast("mx_tangent", &DataType::Vector3, &t),
+            "bitangent" => cast("mx_bitangent", &DataType::Vector3, &t),
+            "time" => cast("mx_time", &float, &t),
+
+            "image" | "tiledimage" => {
+                let Some(port) = node.input("file") else {
+                    return Ok((self.arg(node, "default", &t, &splat(0.0)?)?, t));
+                };
+                let Some(DataTypeAndValue::Filename(file)) = &port.value else {
+                    return Ok((self.arg(node, "default", &t, &splat(0.0)?)?, t));
+                };
+                let i = self.texture(node, file, port)?;
+                let mut uv = self.arg(node, "texcoord", &DataType::Vector2, "mx_texcoord")?;
+                if node.category == "tiledimage" {
+                    let tiling =
+                        self.arg(node, "uvtiling", &DataType::Vector2, "vec2<f32>(1.0)")?;
+                    let offset =
+                        self.arg(node, "uvoffset", &DataType::Vector2, "vec2<f32>(0.0)")?;
+                    uv = format!("({uv} * {tiling} - {offset})");
+                }
+                let sampling = self.textures[i].sampling;
+                let value = if sampling.has_constant() {
+                    // Samplers can't reliably clamp to a border color
+                    let default =
+                        self.arg(node, "default", &DataType::Vector4, "vec4<f32>(0.0)")?;
+                    let (u, v) = (
+                        sampling.u == AddressMode::Constant,
+                        sampling.v == AddressMode::Constant,
+                    );
+                    format!(
+                        "mx_sample_constant(mx_texture_{i}, mx_sampler_{i}, {uv}, \
+                         vec2<bool>({u}, {v}), {default})"
+                    )
+                } else {
+                    format!("mx_sample(mx_texture_{i}, mx_sampler_{i}, {uv})")
+                };
+                cast(&value, &DataType::Vector4, &t)
+            }
+            "triplanarprojection" => {
+                let position = self.arg(node, "position", &DataType::Vector3, "mx_position")?;
+                let normal = self.arg(node, "normal", &DataType::Vector3, "mx_normal")?;
+                let upaxis = self.arg(node, "upaxis", &DataType::Integer, "2")?;
+                let blend = self.arg(node, "blend", &float, "1.0")?;
+                let default = self.arg(node, "default", &DataType::Vector4, "vec4<f32>(0.0)")?;
+                // Planes without a file read the default
+                let mut planes = Vec::new();
+                for (axis, name) in ["filex", "filey", "filez"].into_iter().enumerate() {
+                    let plane = match node.input(name) {
+                        Some(
+                            port @ Port {
+                                value: Some(DataTypeAndValue::Filename(file)),
+                                ..
+                            },
+                        ) => {
+                            let i = self.texture(node, file, port)?;
+                            format!(
+                                "mx_sample(mx_texture_{i}, mx_sampler_{i}, \
+                                 mx_triplanar_uv({position}, {axis}, {upaxis}))"
+                            )
+                        }
+                        _ => default.clone(),
+                    };
+                    planes.push(plane);
+                }
+                let value = format!("mx_triplanar({}, {normal}, {blend})", planes.join(", "));
+                cast(&value, &DataType::Vector4, &t)
+            }
+            "place2d" => {
+                let uv = self.arg(node, "texcoord", &DataType::Vector2, "mx_texcoord")?;
+                let pivot = self.arg(node, "pivot", &DataType::Vector2, "vec2<f32>(0.0)")?;
+                let scale = self.arg(node, "scale", &DataType::Vector2, "vec2<f32>(1.0)")?;
+                let rotate = self.arg(node, "rotate", &float, "0.0")?;
+                let offset = self.arg(node, "offset", &DataType::Vector2, "vec2<f32>(0.0)")?;
+                let order = self.arg(node, "operationorder", &DataType::Integer, "0")?;
+                let value =
+                    format!("mx_place2d({uv}, {pivot}, {scale}, {rotate}, {offset}, {order})");
+                cast(&value, &DataType::Vector2, &t)
+            }
+            "rotate2d" => {
+                let v = self.arg(node, "in", &DataType::Vector2, "vec2<f32>(0.0)")?;
+                let amount = self.arg(node, "amount", &float, "0.0")?;
+                cast(
+                    &format!("mx_rotate2d({v}, {amount})"),
+                    &DataType::Vector2,
+                    &t,
+                )
+            }
+            "normalmap" => {
+                let v = self.arg(node, "in", &DataType::Vector3, "vec3<f32>(0.5, 0.5, 1.0)")?;
+                let scale = self.arg(node, "scale", &float, "1.0")?;
+                let n = self.arg(node, "normal", &DataType::Vector3, "mx_normal")?;
+                let tangent = self.arg(node, "tangent", &DataType::Vector3, "mx_tangent")?;
+                let b = self.arg(node, "bitangent", &DataType::Vector3, "mx_bitangent")?;
+                format!("mx_normalmap({v}, {scale}, {n}, {tangent}, {b})")
+            }
+            "noise2d" | "noise3d" | "fractal3d" => {
+                let position = if node.category == "noise2d" {
+                    let uv = self.arg(node, "texcoord", &DataType::Vector2, "mx_texcoord")?;
+                    format!("vec3<f32>({uv}, 0.0)")
+                } else {
+                    self.arg(node, "position", &DataType::Vector3, "mx_position")?
+                };
+                let noise = if node.category == "fractal3d" {
+                    let octaves = self.arg(node, "octaves", &DataType::Integer, "3")?;
+                    let lacunarity = self.arg(node, "lacunarity", &float, "2.0")?;
+                    let diminish = self.arg(node, "diminish", &float, "0.5")?;
+                    format!("mx_fractal({{}}, {octaves}, {lacunarity}, {diminish})")
+                } else {
+                    "mx_perlin({})".into()
+                };
+                let amplitude = self.arg(node, "amplitude", &t, &splat(1.0)?)?;
+                let pivot = self.arg(node, "pivot", &float, "0.0")?;
+                let width = width(&t).unwrap_or(1);
+                let components = COMPONENT_OFFSETS[..width]
+                    .iter()
+                    .map(|[x, y, z]| {
+                        let p = format!("({position} + vec3<f32>({x:?}, {y:?}, {z:?}))");
+                        noise.replace("{}", &p)
+                    })
+                    .collect::<Vec<_>>();
+                if width == 1 {
+                    format!("({} * {amplitude} + {pivot})", components[0])
+                } else {
+                    format!(
+                        "({}({}) * {amplitude} + {pivot})",
+                        wgsl_type(&t).unwrap(),
+                        components.join(", ")
+                    )
+                }
+            }
+            "cellnoise2d" | "cellnoise3d" => {
+                let position = if node.category == "cellnoise2d" {
+                    let uv = self.arg(node, "texcoord", &DataType::Vector2, "mx_texcoord")?;
+                    format!("vec3<f32>({uv}, 0.0)")
+                } else {
+                    self.arg(node, "position", &DataType::Vector3, "mx_position")?
+                };
+                cast(&format!("mx_cell({position})"), &float, &t)
+            }
+
+            _ => {
+                return Err(ShaderError::Unsupported {
+                    node: node.name.clone(),
+                    category: node.category.clone(),
+                })
+            }
+        };
+        Ok((code, t))
+    }
+}
+
+/// Components of a value stored in the uniform array, padded with zeros
+///
+/// `None` for values that can't be stored there, like strings.
+pub(crate) fn uniform_value(value: &DataTypeAndValue) -> Option<[f32; 4]> {
+    let components: &[f64] = match value {
+        DataTypeAndValue::Float(x) => &[*x],
+        DataTypeAndValue::Integer(x) => &[*x as f64],
+        DataTypeAndValue::Boolean(x) => &[if *x { 1.0 } else { 0.0 }],
+        DataTypeAndValue::Vector2(v) => &v.0,
+        DataTypeAndValue::Vector3(v) | DataTypeAndValue::Color3(v) => &v.0,
+        DataTypeAndValue::Vector4(v) | DataTypeAndValue::Color4(v) => &v.0,
+        _ => return None,
+    };
+    let mut padded = [0.0; 4];
+    for (p, c) in padded.iter_mut().zip(components) {
+        *p = *c as f32;
+    }
+    Some(padded)
+}
+
+/// Number of components of a numeric type
+fn width(ty: &DataType) -> Option<usize> {
+    match ty {
+        DataType::Float | DataType::Integer | DataType::Boolean => Some(1),
+        DataType::Vector2 => Some(2),
+        DataType::Vector3 | DataType::Color3 => Some(3),
+        DataType::Vector4 | DataType::Color4 => Some(4),
+        _ => None,
+    }
+}
+
+fn wgsl_type(ty: &DataType) -> Option<&'static str> {
+    Some(match ty {
+        DataType::Float => "f32",
+        DataType::Integer => "i32",
+        DataType::Boolean => "bool",
+        DataType::Vector2 => "vec2<f32>",
+        DataType::Vector3 | DataType::Color3 => "vec3<f32>",
+        DataType::Vector4 | DataType::Color4 => "vec4<f32>",
+        _ => return None,
+    })
+}
+
+/// Convert `code` of type `from` to `to`, like MaterialX's `convert` node
+///
+/// Scalars are repeated, extra components dropped, and missing ones filled
+/// with zeros (or one for alpha).
+fn cast(code: &str, from: &DataType, to: &DataType) -> String {
+    let (Some(from_width), Some(to_width)) = (width(from), width(to)) else {
+        return code.into();
+    };
+    match (from, to) {
+        (DataType::Integer, DataType::Float) => return format!("f32({code})"),
+        (DataType::Boolean, DataType::Float) => return format!("select(0.0, 1.0, {code})"),
+        (DataType::Float, DataType::Integer) => return format!("i32({code})"),
+        (DataType::Boolean, DataType::Integer) => return format!("select(0, 1, {code})"),
+        (DataType::Float, DataType::Boolean) => return format!("({code} != 0.0)"),
+        (DataType::Integer, DataType::Boolean) => return format!("({code} != 0)"),
+        _ => {}
+    }
+    if from_width == to_width {
+        return code.into();
+    }
+    if from_width == 1 {
+        let scalar = cast(code, from, &DataType::Float);
+        return match to {
+            DataType::Integer | DataType::Boolean => cast(&scalar, &DataType::Float, to),
+            _ => format!("{}({scalar})", wgsl_type(to).unwrap()),
+        };
+    }
+    if to_width == 1 {
+        return cast(&format!("{code}.x"), &DataType::Float, to);
+    }
+    match (from_width, to_width) {
+        (2, 3) => format!("vec3<f32>({code}, 0.0)"),
+        (2, 4) => format!("vec4<f32>({code}, 0.0, 1.0)"),
+        (3, 4) => format!("vec4<f32>({code}, 1.0)"),
+        (4, 3) => format!("{code}.xyz"),
+        _ => format!("{code}.xy"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use materialx_parser::MaterialX;
+    use naga_oil::compose::{
+        ComposableModuleDescriptor, Composer, NagaModuleDescriptor, ShaderDefValue,
+    };
+    use std::str::FromStr as _;
+
+    /// Just enough of Bevy's PBR modules for the generated shaders to compile
+    const BEVY_PBR: &[(&str, &str)] = &[
+        (
+            "forward_io",
+            "#define_import_path bevy_pbr::forward_io
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) world_tangent: vec4<f32>,
+}
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+}",
+        ),
+        (
+            "mesh_view_bindings",
+            "#define_import_path bevy_pbr::mesh_view_bindings
+struct Globals {
+    time: f32,
+    delta_time: f32,
+    frame_count: u32,
+}
+@group(0) @binding(11) var<uniform> globals: Globals;",
+        ),
+        (
+            "pbr_types",
+            "#define_import_path bevy_pbr::pbr_types
+struct StandardMaterial {
+    base_color: vec4<f32>,
+    perceptual_roughness: f32,
+    metallic: f32,
+    clearcoat: f32,
+    clearcoat_perceptual_roughness: f32,
+    flags: u32,
+}
+struct PbrInput {
+    material: StandardMaterial,
+    world_normal: vec3<f32>,
+    N: vec3<f32>,
+}
+const STANDARD_MATERIAL_FLAGS_UNLIT_BIT: u32 = 32u;",
+        ),
+        (
+            "pbr_fragment",
+            "#define_import_path bevy_pbr::pbr_fragment
+#import bevy_pbr::{forward_io::VertexOutput, pbr_types::PbrInput}
+fn pbr_input_from_standard_material(in: VertexOutput, is_front: bool) -> PbrInput {
+    var pbr_input: PbrInput;
+    pbr_input.world_normal = in.world_normal;
+    pbr_input.N = in.world_normal;
+    return pbr_input;
+}",
+        ),
+        (
+            "pbr_functions",
+            "#define_import_path bevy_pbr::pbr_functions
+#import bevy_pbr::pbr_types::{PbrInput, StandardMaterial}
+fn alpha_discard(material: StandardMaterial, color: vec4<f32>) -> vec4<f32> {
+    return color;
+}
+fn apply_pbr_lighting(in: PbrInput) -> vec4<f32> {
+    return in.material.base_color;
+}
+fn main_pass_post_lighting_processing(in: PbrInput, color: vec4<f32>) -> vec4<f32> {
+    return color;
+}",
+        ),
+    ];
+
+    /// Compose `source` with the stand-ins for Bevy's modules and validate
+    /// it, with and without optional vertex attributes
+    fn validate(source: &str) {
+        let mut composer = Composer::default();
+        for (name, module) in BEVY_PBR {
+            let added = composer.add_composable_module(ComposableModuleDescriptor {
+                source: module,
+                file_path: name,
+                ..Default::default()
+            });
+            if let Err(e) = added.map(|_| ()) {
+                panic!("{}", e.emit_to_string(&composer));
+            }
+        }
+        for defs in [&[][..], &["VERTEX_UVS_A", "VERTEX_TANGENTS"]] {
+            composer
+                .make_naga_module(NagaModuleDescriptor {
+                    source,
+                    file_path: "generated.wgsl",
+                    shader_defs: defs
+                        .iter()
+                        .map(|def| (def.to_string(), ShaderDefValue::Bool(true)))
+                        .collect(),
+                    ..Default::default()
+                })
+                .unwrap_or_else(|e| panic!("{}\n{source}", e.emit_to_string(&composer)));
+        }
+    }
+
+    fn load(name: &str) -> NodeGraph {
+        let file = std::fs::read_to_string(format!(
+            "../assets/materialx-examples/StandardSurface/{name}"
+        ))
+        .unwrap();
+        let mat = MaterialX::from_str(&file).unwrap();
+        NodeGraph::try_from(&mat).unwrap()
+    }
+
+    fn generate(graph: &NodeGraph, surface: &str) -> Result<GeneratedShader, ShaderError> {
+        generate_wgsl(graph, graph.find(None, surface).unwrap())
+    }
+
+    #[test]
+    fn examples() {
+        for (file, surface, textures) in [
+            ("standard_surface_marble_solid.mtlx", "SR_marble1", 0),
+            ("standard_surface_brass_tiled.mtlx", "SR_brass1", 1),
+            ("standard_surface_wood_tiled.mtlx", "SR_wood1", 2),
+            (
+                "standard_surface_brick_procedural.mtlx",
+                "N_StandardSurface",
+                6,
+            ),
+            ("standard_surface_chess_set.mtlx", "Chessboard", 4),
+            (
+                "standard_surface_greysphere_calibration.mtlx",
+                "SR_Greysphere_Calibration",
+                1,
+            ),
+        ] {
+            let shader = generate(&load(file), surface).unwrap_or_else(|e| panic!("{file}: {e}"));
+            assert_eq!(shader.textures.len(), textures, "{file}");
+            validate(&shader.source);
+        }
+    }
+
+    #[test]
+    fn triplanar() {
+        let mat = MaterialX::from_str(
+            r#"<materialx version="1.39">
+  <triplanarprojection name="tri" type="color3">
+    <input name="filex" type="filename" value="x.png" />
+    <input name="filey" type="filename" value="y.png" />
+    <input name="position" type="vector3" nodename="pos" />
+    <input name="blend" type="float" value="0.5" />
+  </triplanarprojection>
+  <position name="pos" type="vector3" />
+  <place2d name="place" type="vector2">
+    <input name="rotate" type="float" value="30" />
+  </place2d>
+  <tiledimage name="detail" type="float">
+    <input name="file" type="filename" value="detail.png" />
+    <input name="texcoord" type="vector2" nodename="place" />
+  </tiledimage>
+  <standard_surface name="surface" type="surfaceshader">
+    <input name="base_color" type="color3" nodename="tri" />
+    <input name="specular_roughness" type="float" nodename="detail" />
+  </standard_surface>
+</materialx>"#,
+        )
+        .unwrap();
+        let graph = NodeGraph::try_from(&mat).unwrap();
+        let shader = generate(&graph, "surface").unwrap();
+        // The missing `filez` reads the default instead of a texture
+        assert_eq!(shader.textures.len(), 3);
+        validate(&shader.source);
+    }
+
+    /// Two graphs that only differ in their values share a shader
+    #[test]
+    fn structural_hash() {
+        let xml = r#"<materialx version="1.39">
+  <multiply name="mul" type="color3">
+    <input name="in1" type="color3" value="0.5, 0.5, 0.5" />
+    <input name="in2" type="float" value="2" />
+  </multiply>
+  <standard_surface name="surface" type="surfaceshader">
+    <input name="base_color" type="color3" nodename="mul" />
+    <input name="metalness" type="float" value="1" />
+  </standard_surface>
+</materialx>"#;
+        let shader = |xml: &str| {
+            let graph = NodeGraph::try_from(&MaterialX::from_str(xml).unwrap()).unwrap();
+            generate(&graph, "surface").unwrap()
+        };
+        let a = shader(xml);
+        let b = shader(&xml.replace(r#"value="2""#, r#"value="3""#));
+        assert_eq!(a.hash, b.hash);
+        assert_eq!(a.source, b.source);
+        let values = |s: &GeneratedShader| s.uniforms.iter().map(|u| u.value).collect::<Vec<_>>();
+        assert_ne!(values(&a), values(&b));
+        assert_eq!(values(&a)[1], [2.0, 0.0, 0.0, 0.0]);
+        assert_eq!(values(&b)[1], [3.0, 0.0, 0.0, 0.0]);
+        validate(&a.source);
+    }
+
+    #[test]
+    fn casts() {
+        let types = [
+            DataType::Float,
+            DataType::Integer,
+            DataType::Boolean,
+            DataType::Vector2,
+            DataType::Vector3,
+            DataType::Color3,
+            DataType::Vector4,
+            DataType::Color4,
+        ];
+        let mut source = String::new();
+        for (i, from) in types.iter().enumerate() {
+            for (j, to) in types.iter().enumerate() {
+                let (from_type, to_type) = (wgsl_type(from).unwrap(), wgsl_type(to).unwrap());
+                writeln!(
+                    source,
+                    "fn cast_{i}_{j}(x: {from_type}) -> {to_type} {{ return {}; }}",
+                    cast("x", from, to)
+                )
+                .unwrap();
+            }
+        }
+        validate(&source);
+
+        assert_eq!(
+            cast("x", &DataType::Float, &DataType::Color3),
+            "vec3<f32>(x)"
+        );
+        assert_eq!(
+            cast("x", &DataType::Boolean, &DataType::Vector2),
+            "vec2<f32>(select(0.0, 1.0, x))"
+        );
+        assert_eq!(
+            cast("x", &DataType::Vector3, &DataType::Color4),
+            "vec4<f32>(x, 1.0)"
+        );
+        assert_eq!(
+            cast("x", &DataType::Vector2, &DataType::Vector4),
+            "vec4<f32>(x, 0.0, 1.0)"
+        );
+        assert_eq!(cast("x", &DataType::Color4, &DataType::Integer), "i32(x.x)");
+        assert_eq!(cast("x", &DataType::Vector4, &DataType::Vector2), "x.xy");
+        // Types without a WGSL equivalent are left alone
+        assert_eq!(cast("x", &DataType::String, &DataType::Float), "x");
+    }
+
+    #[test]
+    fn too_many_uniforms() {
+        let mut xml = String::from(r#"<materialx version="1.39">"#);
+        let mut previous = None;
+        for i in 0..=MAX_UNIFORMS {
+            write!(xml, r#"<add name="add{i}" type="float">"#).unwrap();
+            match previous {
+                Some(previous) => write!(
+                    xml,
+                    r#"<input name="in1" type="float" nodename="{previous}" />"#
+                ),
+                None => write!(xml, r#"<input name="in1" type="float" value="0" />"#),
+            }
+            .unwrap();
+            writeln!(
+                xml,
+                r#"<input name="in2" type="float" value="{i}" /></add>"#
+            )
+            .unwrap();
+            previous = Some(format!("add{i}"));
+        }
+        write!(
+            xml,
+            r#"<standard_surface name="surface" type="surfaceshader">
+  <input name="metalness" type="float" nodename="add{MAX_UNIFORMS}" />
+</standard_surface></materialx>"#
+        )
+        .unwrap();
+        let graph = NodeGraph::try_from(&MaterialX::from_str(&xml).unwrap()).unwrap();
+        assert!(matches!(
+            generate(&graph, "surface"),
+            Err(ShaderError::TooManyUniforms)
+        ));
+    }
+
+    #[test]
+    fn too_many_textures() {
+        let mut xml = String::from(r#"<materialx version="1.39">"#);
+        for i in 0..=MAX_TEXTURES {
+            writeln!(
+                xml,
+                r#"<image name="img{i}" type="float"><input name="file" type="filename" value="{i}.png" /></image>"#
+            )
+            .unwrap();
+            let in1 = match i {
+                0 => r#"value="0""#.to_string(),
+                _ => format!(r#"nodename="add{}""#, i - 1),
+            };
+            writeln!(
+                xml,
+                r#"<add name="add{i}" type="float"><input name="in1" type="float" {in1} /><input name="in2" type="float" nodename="img{i}" /></add>"#
+            )
+            .unwrap();
+        }
+        write!(
+            xml,
+            r#"<standard_surface name="surface" type="surfaceshader">
+  <input name="metalness" type="float" nodename="add{MAX_TEXTURES}" />
+</standard_surface></materialx>"#
+        )
+        .unwrap();
+        let graph = NodeGraph::try_from(&MaterialX::from_str(&xml).unwrap()).unwrap();
+        assert!(matches!(
+            generate(&graph, "surface"),
+            Err(ShaderError::TooManyTextures)
+        ));
+    }
+}
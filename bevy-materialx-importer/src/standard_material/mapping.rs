@@ -0,0 +1,812 @@
+//! Mapping the inputs of `standard_surface` onto `StandardMaterial`
+//!
+//! | `standard_surface`                        | `StandardMaterial`                                  | Fidelity     |
+//! |-------------------------------------------|-----------------------------------------------------|--------------|
+//! | `base`, `base_color`                      | `base_color` (product), `base_color_texture`        | exact        |
+//! | `metalness`                               | `metallic`, `metallic_roughness_texture`³           | exact        |
+//! | `specular_roughness`                      | `perceptual_roughness`, `metallic_roughness_texture`³ | exact      |
+//! | `specular`, `specular_IOR`                | `reflectance` (F0 of the IOR, scaled), `ior`        | exact        |
+//! | `specular_color`                          | —                                                   | dropped      |
+//! | `specular_anisotropy`, `specular_rotation`| `anisotropy_strength`, `anisotropy_rotation`        | exact        |
+//! | `transmission`                            | `specular_transmission`, `specular_transmission_texture`¹ | exact  |
+//! | `transmission_color`, `transmission_depth`| `attenuation_color`, `attenuation_distance`, `thickness` | approximated |
+//! | `transmission_scatter*`, `transmission_dispersion`, `transmission_extra_roughness` | — | dropped |
+//! | `thin_walled`                             | `double_sided`, no culling, zero `thickness`        | exact        |
+//! | `subsurface`, `subsurface_color`          | `diffuse_transmission`, mixed into `base_color`     | approximated |
+//! | `subsurface_radius`, `subsurface_scale`, `subsurface_anisotropy` | —            | dropped      |
+//! | `sheen*`                                  | —                                                   | dropped      |
+//! | `coat`, `coat_roughness`                  | `clearcoat`, `clearcoat_perceptual_roughness` and textures² | exact |
+//! | `coat_color`                              | multiplied into `base_color`                        | approximated |
+//! | `coat_normal`                             | `clearcoat_normal_texture`²                         | exact        |
+//! | `coat_IOR` (other than 1.5), `coat_anisotropy`, `coat_affect_*` | —              | dropped      |
+//! | `thin_film_*`                             | —                                                   | dropped      |
+//! | `emission`, `emission_color`              | `emissive` (product), `emissive_texture`            | exact        |
+//! | `opacity`                                 | `base_color` alpha (mean of the channels) or `base_color_texture` alpha⁴, `AlphaMode::Blend` or `Mask` | approximated |
+//! | `diffuse_roughness`                       | —                                                   | dropped      |
+//! | `normal`                                  | `normal_map_texture`                                | exact        |
+//! | `displacementshader` of the material      | `depth_map`                                         | approximated |
+//!
+//! ¹ With the `pbr_transmission_textures` feature.
+//! ² With the `pbr_multi_layer_material_textures` feature.
+//! ³ Packed from the channels the inputs read, see [`super::packing`].
+//! ⁴ Packed from one channel of the image, see [`super::packing`].
+//!
+//! Inputs connected to image nodes become textures where the table lists one;
+//! other connected inputs are dropped unless they are
+//! [baked](crate::BakeSettings). Inputs at their default values (or with a
+//! zero weight) aren't reported.
+
+use super::{
+    packing::{Channel, MetallicRoughness, Opacity, Packing},
+    standard_surface, surfacematerial, texture_colorspace, texture_path, MaterialError,
+};
+use crate::{
+    color::{decode_srgb, default_colorspace},
+    settings::MaterialXLoaderSettings,
+    texture::{Sampling, Textures},
+    udim::atlas_transform,
+};
+use bevy_asset::{AssetPath, Handle, LoadContext};
+use bevy_color::{Alpha as _, Color, LinearRgba, Mix as _};
+use bevy_image::Image;
+use bevy_math::{Affine2, Vec2};
+use bevy_pbr::{StandardMaterial, UvChannel};
+use bevy_render::alpha::AlphaMode;
+use materialx_parser::{
+    data_types::{DataTypeAndValue, ValueParseError, Vector2},
+    filename::has_udim,
+    nodes::{AccessError, ResolvedInput, UpstreamNode},
+    MaterialX,
+};
+use std::{f32::consts::TAU, fmt};
+use tracing::{debug, instrument};
+
+/// Inputs that [baking](crate::bake) can turn into textures
+const BAKED_INPUTS: &[&str] = &["base_color", "specular_roughness", "metalness", "normal"];
+
+/// How faithfully an input made it into the `StandardMaterial`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fidelity {
+    Approximated,
+    Dropped,
+}
+
+/// An input that couldn't be mapped exactly
+#[derive(Debug, Clone)]
+pub(crate) struct Note {
+    pub input: &'static str,
+    pub fidelity: Fidelity,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.input, self.reason)
+    }
+}
+
+/// Where an input gets its value from
+enum Source<T> {
+    /// A constant, or the default if the input isn't set
+    Value(T),
+    /// An image node
+    Image(Box<UpstreamNode>),
+    /// Any other node
+    Node,
+}
+
+/// Where textures are read on the mesh
+#[derive(Debug, Clone, PartialEq)]
+struct Placement {
+    transform: Affine2,
+    channel: UvChannel,
+}
+
+/// An image file, whether it's sRGB, and how it's sampled
+type TextureFile = (AssetPath<'static>, bool, Sampling);
+
+/// Reads the inputs of a surface shader, and records the notes about them
+struct Inputs<'a> {
+    def: &'a MaterialX,
+    surface: &'a standard_surface,
+    path: &'a AssetPath<'a>,
+    settings: &'a MaterialXLoaderSettings,
+    notes: Vec<Note>,
+    /// Placement of the first texture, which all textures share
+    placement: Option<Placement>,
+    textures: &'a mut Textures,
+}
+
+impl Inputs<'_> {
+    fn get<T>(&self, input: &'static str, default: T) -> Result<Source<T>, MaterialError>
+    where
+        T: TryFrom<DataTypeAndValue, Error = ValueParseError>,
+    {
+        match self.def.resolve(self.surface, None, input.into()) {
+            Ok(ResolvedInput::Value(value)) => {
+                Ok(Source::Value(value.try_into().map_err(
+                    |e: ValueParseError| e.at(&self.surface.location.child(input)),
+                )?))
+            }
+            Ok(ResolvedInput::Node(node))
+                if matches!(node.element.tag.as_str(), "image" | "tiledimage") =>
+            {
+                Ok(Source::Image(Box::new(node)))
+            }
+            Ok(ResolvedInput::Node(_)) => Ok(Source::Node),
+            Err(AccessError::NotFound { .. }) => Ok(Source::Value(default)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The constant value of `input`
+    ///
+    /// Connected inputs are noted as dropped (unless they are baked) and
+    /// return `default`.
+    fn value<T>(&mut self, input: &'static str, default: T) -> Result<T, MaterialError>
+    where
+        T: TryFrom<DataTypeAndValue, Error = ValueParseError> + Copy,
+    {
+        match self.get(input, default)? {
+            Source::Value(value) => Ok(value),
+            Source::Image(_) | Source::Node => {
+                self.connected(input);
+                Ok(default)
+            }
+        }
+    }
+
+    /// The node connected to `input`, if any
+    fn node(&self, input: &'static str) -> Result<Option<UpstreamNode>, MaterialError> {
+        match self.def.resolve(self.surface, None, input.into()) {
+            Ok(ResolvedInput::Node(node)) => Ok(Some(node)),
+            Ok(ResolvedInput::Value(_)) | Err(AccessError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn note(&mut self, input: &'static str, fidelity: Fidelity, reason: &'static str) {
+        self.notes.push(Note {
+            input,
+            fidelity,
+            reason,
+        });
+    }
+
+    /// Note that a connected input can't be represented
+    fn connected(&mut self, input: &'static str) {
+        let reason = match (self.settings.bake.is_some(), BAKED_INPUTS.contains(&input)) {
+            (true, true) => return,
+            (false, true) => "connected to nodes, enable baking to turn them into a texture",
+            (_, false) => "can only be a constant or an image",
+        };
+        self.note(input, Fidelity::Dropped, reason);
+    }
+
+    /// Load the file of the image node `node` connected to `input`
+    fn texture(
+        &mut self,
+        input: &'static str,
+        node: &UpstreamNode,
+        color: bool,
+        loader: &mut LoadContext<'_>,
+    ) -> Result<Option<Handle<Image>>, MaterialError> {
+        let file = self.texture_file(input, node, color)?;
+        Ok(file.map(|file| self.load(file, loader)))
+    }
+
+    /// The file of the image node `node` connected to `input`, whether it is
+    /// sRGB, and how it is sampled
+    fn texture_file(
+        &mut self,
+        input: &'static str,
+        node: &UpstreamNode,
+        color: bool,
+    ) -> Result<Option<TextureFile>, MaterialError> {
+        let Some((path, sampling)) = self.image_file(input, node)? else {
+            return Ok(None);
+        };
+        let default = default_colorspace(self.settings);
+        let srgb = decode_srgb(&texture_colorspace(self.def, node, &default), color);
+        Ok(Some((path, srgb, sampling)))
+    }
+
+    fn load(&mut self, file: TextureFile, loader: &mut LoadContext<'_>) -> Handle<Image> {
+        let (path, srgb, sampling) = file;
+        debug!("Loaded texture {path}");
+        self.textures.load(loader, path, srgb, sampling)
+    }
+
+    /// The file of the image node `node` connected to `input`, and how it is
+    /// sampled
+    fn image_file(
+        &mut self,
+        input: &'static str,
+        node: &UpstreamNode,
+    ) -> Result<Option<(AssetPath<'static>, Sampling)>, MaterialError> {
+        let Some(path) = texture_path(self.def, node, self.path)? else {
+            return Ok(None);
+        };
+        let sampling = Sampling::new(&node.element.tag, |name| self.image_input(node, name));
+        if sampling.has_constant() {
+            self.note(
+                input,
+                Fidelity::Approximated,
+                "the image's `default` outside of its borders is replaced by its edges",
+            );
+        }
+        let mut placement = self.placement(input, node)?;
+        if has_udim(&path.to_string()) {
+            let udims = self.def.udimset().unwrap_or_default();
+            placement.transform = atlas_transform(&udims) * placement.transform;
+        }
+        match &self.placement {
+            None => self.placement = Some(placement),
+            Some(first) if *first != placement => self.note(
+                input,
+                Fidelity::Approximated,
+                "uses the texture coordinates of the first texture, all textures share them",
+            ),
+            Some(_) => {}
+        }
+        Ok(Some((path, sampling)))
+    }
+
+    /// The image node and channel `input` reads, if it reads a single
+    /// channel of an image
+    ///
+    /// That's a float image (reading the first channel), or an `extract` or
+    /// `separate` node picking a channel of one.
+    fn image_channel(
+        &self,
+        input: &'static str,
+    ) -> Result<Option<(UpstreamNode, usize)>, MaterialError> {
+        let Some(node) = self.node(input)? else {
+            return Ok(None);
+        };
+        let channel = match node.element.tag.as_str() {
+            "image" | "tiledimage" => return Ok(Some((node, 0))),
+            "extract" => match self.image_input(&node, "index") {
+                None => 0,
+                Some(DataTypeAndValue::Integer(i @ 0..=3)) => i as usize,
+                Some(_) => return Ok(None),
+            },
+            "separate2" | "separate3" | "separate4" => {
+                // `outr`/`outx`, `outg`/`outy`, ...
+                match node
+                    .output
+                    .as_deref()
+                    .and_then(|output| output.chars().last())
+                {
+                    Some('r' | 'x') => 0,
+                    Some('g' | 'y') => 1,
+                    Some('b' | 'z') => 2,
+                    Some('a' | 'w') => 3,
+                    _ => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        match self
+            .def
+            .resolve(&node.element, node.parent(self.def), "in".into())
+        {
+            Ok(ResolvedInput::Node(image))
+                if matches!(image.element.tag.as_str(), "image" | "tiledimage") =>
+            {
+                Ok(Some((image, channel)))
+            }
+            Ok(_) | Err(AccessError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The image channel to pack into `metallic_roughness_texture` for
+    /// `input`, or else its constant value
+    fn packed<T>(
+        &mut self,
+        input: &'static str,
+        default: T,
+        pack: bool,
+    ) -> Result<(Option<Channel>, T), MaterialError>
+    where
+        T: TryFrom<DataTypeAndValue, Error = ValueParseError> + Copy,
+    {
+        if pack {
+            if let Some((node, index)) = self.image_channel(input)? {
+                if let Some((path, sampling)) = self.image_file(input, &node)? {
+                    let channel = Channel {
+                        path,
+                        index,
+                        sampling,
+                    };
+                    return Ok((Some(channel), default));
+                }
+            }
+        }
+        Ok((None, self.value(input, default)?))
+    }
+
+    /// The constant value of the input `name` of the image node `node`
+    fn image_input(&self, node: &UpstreamNode, name: &str) -> Option<DataTypeAndValue> {
+        match self
+            .def
+            .resolve(&node.element, node.parent(self.def), name.into())
+        {
+            Ok(ResolvedInput::Value(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Where the image node `node` connected to `input` is read on the mesh
+    ///
+    /// That's the UV set picked by a `texcoord` node, tiled and offset for
+    /// `tiledimage` nodes.
+    fn placement(
+        &mut self,
+        input: &'static str,
+        node: &UpstreamNode,
+    ) -> Result<Placement, MaterialError> {
+        let parent = node.parent(self.def);
+        let mut transform = Affine2::IDENTITY;
+        if node.element.tag == "tiledimage" {
+            let mut vec2 =
+                |name: &str, default| match self.def.resolve(&node.element, parent, name.into()) {
+                    Ok(ResolvedInput::Value(DataTypeAndValue::Vector2(Vector2([x, y])))) => {
+                        Ok(Vec2::new(x as f32, y as f32))
+                    }
+                    Ok(ResolvedInput::Node(_)) => {
+                        self.note(
+                            input,
+                            Fidelity::Dropped,
+                            "image tiling can only be constant",
+                        );
+                        Ok(default)
+                    }
+                    Ok(ResolvedInput::Value(_)) | Err(AccessError::NotFound { .. }) => Ok(default),
+                    Err(e) => Err(MaterialError::from(e)),
+                };
+            let tiling = vec2("uvtiling", Vec2::ONE)?;
+            let offset = vec2("uvoffset", Vec2::ZERO)?;
+            // MaterialX's `v` goes up, Bevy's goes down, so `v` is flipped
+            // before tiling and back after
+            let translation = Vec2::new(-offset.x, 1.0 - tiling.y + offset.y);
+            transform = Affine2::from_scale_angle_translation(tiling, 0.0, translation);
+        }
+
+        let channel = match self.def.resolve(&node.element, parent, "texcoord".into()) {
+            Err(AccessError::NotFound { .. }) => UvChannel::Uv0,
+            Ok(ResolvedInput::Node(texcoord)) if texcoord.element.tag == "texcoord" => {
+                match self.image_input(&texcoord, "index") {
+                    None | Some(DataTypeAndValue::Integer(0)) => UvChannel::Uv0,
+                    Some(DataTypeAndValue::Integer(1)) => UvChannel::Uv1,
+                    Some(_) => {
+                        self.note(input, Fidelity::Dropped, "meshes only have UV sets 0 and 1");
+                        UvChannel::Uv0
+                    }
+                }
+            }
+            Ok(_) => {
+                self.note(
+                    input,
+                    Fidelity::Approximated,
+                    "computed texture coordinates are replaced by the mesh's UVs",
+                );
+                UvChannel::Uv0
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Placement { transform, channel })
+    }
+
+    /// The image read by the `normalmap` node `node` connected to `input`
+    fn normal_texture(
+        &mut self,
+        input: &'static str,
+        node: &UpstreamNode,
+        loader: &mut LoadContext<'_>,
+    ) -> Result<Option<Handle<Image>>, MaterialError> {
+        if node.element.tag != "normalmap" {
+            return Ok(None);
+        }
+        match self
+            .def
+            .resolve(&node.element, node.parent(self.def), "in".into())?
+        {
+            ResolvedInput::Node(image) => self.texture(input, &image, false, loader),
+            ResolvedInput::Value(_) => Ok(None),
+        }
+    }
+}
+
+/// Convert `surface` to a `StandardMaterial`, with notes about the inputs that
+/// were approximated or dropped
+///
+/// The textures it reads are recorded in `textures`.
+#[instrument(skip_all, fields(%material.name))]
+pub(crate) fn build_material(
+    surface: &standard_surface,
+    material: &surfacematerial,
+    def: &MaterialX,
+    path: &AssetPath,
+    settings: &MaterialXLoaderSettings,
+    textures: &mut Textures,
+    loader: &mut LoadContext<'_>,
+) -> Result<(StandardMaterial, Vec<Note>, Packing), MaterialError> {
+    let mut res = StandardMaterial::default();
+    let mut inputs = Inputs {
+        def,
+        surface,
+        path,
+        settings,
+        notes: Vec::new(),
+        placement: None,
+        textures,
+    };
+
+    // Diffuse
+    let base: f32 = inputs.value("base", 1.0)?;
+    let opacity_image = inputs.image_channel("opacity")?;
+    // A base color image to pack with the opacity
+    let mut color_file = None;
+    let mut baked_color = false;
+    let mut color = match inputs.get("base_color", LinearRgba::rgb(0.8, 0.8, 0.8))? {
+        Source::Value(color) => Some(color),
+        Source::Image(node) if opacity_image.is_some() => {
+            color_file = inputs.texture_file("base_color", &node, true)?;
+            None
+        }
+        Source::Image(node) => {
+            res.base_color_texture = inputs.texture("base_color", &node, true, loader)?;
+            None
+        }
+        Source::Node => {
+            baked_color = settings.bake.is_some();
+            inputs.connected("base_color");
+            None
+        }
+    };
+    // Factors applied on top of the base color, also when it's a texture
+    let mut tint = LinearRgba::rgb(base, base, base);
+    if inputs.value("diffuse_roughness", 0.0f32)? != 0.0 {
+        inputs.note(
+            "diffuse_roughness",
+            Fidelity::Dropped,
+            "StandardMaterial only has Lambertian diffuse",
+        );
+    }
+
+    // Specular
+    // With baking, an image next to a procedural input is baked with it
+    let procedural = |input| -> Result<bool, MaterialError> {
+        Ok(inputs.node(input)?.is_some() && inputs.image_channel(input)?.is_none())
+    };
+    let pack =
+        settings.bake.is_none() || !(procedural("metalness")? || procedural("specular_roughness")?);
+    let (metalness, metallic) = inputs.packed("metalness", 0.0, pack)?;
+    let (roughness, perceptual_roughness) = inputs.packed("specular_roughness", 0.2, pack)?;
+    res.metallic = metallic;
+    res.perceptual_roughness = perceptual_roughness;
+    let mut packing = Packing {
+        metallic_roughness: MetallicRoughness {
+            metalness,
+            roughness,
+        },
+        opacity: None,
+    };
+    let specular: f32 = inputs.value("specular", 1.0)?;
+    let ior: f32 = inputs.value("specular_IOR", 1.5)?;
+    let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2) * specular;
+    // `reflectance` 0.5 means an F0 of 4%
+    res.reflectance = (f0 / 0.16).sqrt().min(1.0);
+    res.ior = ior;
+    if inputs.value("specular_color", LinearRgba::WHITE)? != LinearRgba::WHITE {
+        inputs.note(
+            "specular_color",
+            Fidelity::Dropped,
+            "StandardMaterial has no specular tint",
+        );
+    }
+    res.anisotropy_strength = inputs.value("specular_anisotropy", 0.0)?;
+    res.anisotropy_rotation = inputs.value::<f32>("specular_rotation", 0.0)? * TAU;
+
+    // Transmission
+    let thin_walled: bool = inputs.value("thin_walled", false)?;
+    if thin_walled {
+        res.double_sided = true;
+        res.cull_mode = None;
+    }
+    match inputs.get("transmission", 0.0)? {
+        Source::Value(transmission) => res.specular_transmission = transmission,
+        #[cfg(feature = "pbr_transmission_textures")]
+        Source::Image(node) => {
+            res.specular_transmission = 1.0;
+            res.specular_transmission_texture =
+                inputs.texture("transmission", &node, false, loader)?;
+        }
+        _ => inputs.connected("transmission"),
+    }
+    if res.specular_transmission > 0.0 {
+        let transmission_color = inputs.value("transmission_color", LinearRgba::WHITE)?;
+        let depth: f32 = inputs.value("transmission_depth", 0.0)?;
+        if thin_walled {
+            if transmission_color != LinearRgba::WHITE {
+                inputs.note(
+                    "transmission_color",
+                    Fidelity::Dropped,
+                    "thin-walled transmission can't be tinted",
+                );
+            }
+        } else if depth > 0.0 {
+            res.attenuation_color = transmission_color.into();
+            res.attenuation_distance = depth;
+            res.thickness = depth;
+            inputs.note(
+                "transmission_depth",
+                Fidelity::Approximated,
+                "also used as the thickness of the object",
+            );
+        } else if transmission_color != LinearRgba::WHITE {
+            res.attenuation_color = transmission_color.into();
+            res.attenuation_distance = 1.0;
+            res.thickness = 1.0;
+            inputs.note(
+                "transmission_color",
+                Fidelity::Approximated,
+                "applied as attenuation through one unit of volume",
+            );
+        }
+        for input in [
+            "transmission_scatter_anisotropy",
+            "transmission_dispersion",
+            "transmission_extra_roughness",
+        ] {
+            if inputs.value(input, 0.0f32)? != 0.0 {
+                inputs.note(
+                    input,
+                    Fidelity::Dropped,
+                    "not supported by StandardMaterial",
+                );
+            }
+        }
+        if inputs.value("transmission_scatter", LinearRgba::BLACK)? != LinearRgba::BLACK {
+            inputs.note(
+                "transmission_scatter",
+                Fidelity::Dropped,
+                "StandardMaterial has no volume scattering",
+            );
+        }
+    }
+
+    // Subsurface
+    let subsurface: f32 = inputs.value("subsurface", 0.0)?;
+    if subsurface > 0.0 {
+        res.diffuse_transmission = subsurface;
+        let subsurface_color = inputs.value("subsurface_color", LinearRgba::WHITE)?;
+        if let Some(color) = &mut color {
+            *color = color.mix(&subsurface_color, subsurface);
+        }
+        inputs.note(
+            "subsurface",
+            Fidelity::Approximated,
+            "as diffuse transmission tinted by `subsurface_color`, without scattering radius",
+        );
+    }
+
+    // Sheen and thin film
+    if inputs.value("sheen", 0.0f32)? > 0.0 {
+        inputs.note("sheen", Fidelity::Dropped, "StandardMaterial has no sheen");
+    }
+    if inputs.value("thin_film_thickness", 0.0f32)? > 0.0 {
+        inputs.note(
+            "thin_film_thickness",
+            Fidelity::Dropped,
+            "StandardMaterial has no thin-film iridescence",
+        );
+    }
+
+    // Coat
+    match inputs.get("coat", 0.0)? {
+        Source::Value(coat) => res.clearcoat = coat,
+        #[cfg(feature = "pbr_multi_layer_material_textures")]
+        Source::Image(node) => {
+            res.clearcoat = 1.0;
+            res.clearcoat_texture = inputs.texture("coat", &node, false, loader)?;
+        }
+        _ => inputs.connected("coat"),
+    }
+    match inputs.get("coat_roughness", 0.1)? {
+        Source::Value(roughness) => res.clearcoat_perceptual_roughness = roughness,
+        #[cfg(feature = "pbr_multi_layer_material_textures")]
+        Source::Image(node) => {
+            res.clearcoat_perceptual_roughness = 1.0;
+            res.clearcoat_roughness_texture =
+                inputs.texture("coat_roughness", &node, false, loader)?;
+        }
+        _ => inputs.connected("coat_roughness"),
+    }
+    if res.clearcoat > 0.0 {
+        let coat_color = inputs.value("coat_color", LinearRgba::WHITE)?;
+        if coat_color != LinearRgba::WHITE {
+            tint = multiply(tint, LinearRgba::WHITE.mix(&coat_color, res.clearcoat));
+            inputs.note(
+                "coat_color",
+                Fidelity::Approximated,
+                "multiplied into the base color",
+            );
+        }
+        if inputs.value("coat_IOR", 1.5f32)? != 1.5 {
+            inputs.note(
+                "coat_IOR",
+                Fidelity::Dropped,
+                "the clearcoat IOR is fixed at 1.5",
+            );
+        }
+        for input in [
+            "coat_anisotropy",
+            "coat_affect_color",
+            "coat_affect_roughness",
+        ] {
+            if inputs.value(input, 0.0f32)? != 0.0 {
+                inputs.note(
+                    input,
+                    Fidelity::Dropped,
+                    "not supported by StandardMaterial",
+                );
+            }
+        }
+        if let Some(node) = inputs.node("coat_normal")? {
+            let texture = inputs.normal_texture("coat_normal", &node, loader)?;
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            {
+                res.clearcoat_normal_texture = texture;
+            }
+            #[cfg(not(feature = "pbr_multi_layer_material_textures"))]
+            if texture.is_some() {
+                inputs.note(
+                    "coat_normal",
+                    Fidelity::Dropped,
+                    "needs the `pbr_multi_layer_material_textures` feature",
+                );
+            }
+        }
+    }
+
+    // Emission
+    let emission: f32 = inputs.value("emission", 0.0)?;
+    if emission > 0.0 {
+        match inputs.get("emission_color", LinearRgba::WHITE)? {
+            Source::Value(emission_color) => res.emissive = emission_color * emission,
+            Source::Image(node) => {
+                res.emissive = LinearRgba::WHITE * emission;
+                res.emissive_texture = inputs.texture("emission_color", &node, true, loader)?;
+            }
+            Source::Node => inputs.connected("emission_color"),
+        }
+    }
+
+    // Opacity
+    let mut alpha = 1.0;
+    match opacity_image {
+        Some(_) if baked_color => inputs.note(
+            "opacity",
+            Fidelity::Dropped,
+            "can't be packed into a baked base color",
+        ),
+        Some((node, index)) => match inputs.image_file("opacity", &node)? {
+            Some((path, sampling)) => {
+                // Rather than an `extract` or `separate` node picking a channel
+                let whole = inputs
+                    .node("opacity")?
+                    .is_some_and(|n| n.element.name == node.element.name);
+                let r#type = node.element.attributes.get("type");
+                if whole && r#type.is_some_and(|t| t != "float") {
+                    inputs.note(
+                        "opacity",
+                        Fidelity::Approximated,
+                        "the first channel of the image becomes the alpha",
+                    );
+                }
+                packing.opacity = Some(Opacity {
+                    color: color_file.take(),
+                    opacity: Channel {
+                        path,
+                        index,
+                        sampling,
+                    },
+                });
+            }
+            None => inputs.connected("opacity"),
+        },
+        None => {
+            let opacity = inputs.value("opacity", LinearRgba::WHITE)?;
+            alpha = (opacity.red + opacity.green + opacity.blue) / 3.0;
+            if alpha < 1.0 {
+                res.alpha_mode = AlphaMode::Blend;
+                if opacity.red != opacity.green || opacity.green != opacity.blue {
+                    inputs.note(
+                        "opacity",
+                        Fidelity::Approximated,
+                        "colored opacity is averaged into a single alpha value",
+                    );
+                }
+            }
+        }
+    }
+    // The base color image if the opacity isn't packed into it after all
+    if let Some(file) = color_file {
+        res.base_color_texture = Some(inputs.load(file, loader));
+    }
+
+    // Geometry
+    if let Some(node) = inputs.node("normal")? {
+        match inputs.normal_texture("normal", &node, loader)? {
+            Some(texture) => res.normal_map_texture = Some(texture),
+            None => inputs.connected("normal"),
+        }
+    }
+    if let Ok(ResolvedInput::Node(node)) = def.resolve(material, None, "displacementshader".into())
+    {
+        if let Some(texture) = displacement_texture(&mut inputs, &node, loader)? {
+            res.depth_map = Some(texture);
+            inputs.note(
+                "displacementshader",
+                Fidelity::Approximated,
+                "rendered with parallax mapping",
+            );
+        }
+    }
+
+    let color = multiply(color.unwrap_or(LinearRgba::WHITE), tint);
+    res.base_color = Color::LinearRgba(color.with_alpha(alpha));
+    if let Some(placement) = inputs.placement {
+        res.uv_transform = placement.transform;
+        set_uv_channel(&mut res, placement.channel);
+    }
+    Ok((res, inputs.notes, packing))
+}
+
+/// The image read by the `displacement` node `node`
+fn displacement_texture(
+    inputs: &mut Inputs,
+    node: &UpstreamNode,
+    loader: &mut LoadContext<'_>,
+) -> Result<Option<Handle<Image>>, MaterialError> {
+    if node.element.tag != "displacement" {
+        return Ok(None);
+    }
+    match inputs.def.resolve(
+        &node.element,
+        node.parent(inputs.def),
+        "displacement".into(),
+    )? {
+        ResolvedInput::Node(image) => inputs.texture("displacementshader", &image, false, loader),
+        ResolvedInput::Value(_) => Ok(None),
+    }
+}
+
+/// Read all textures of `material` from `channel`
+fn set_uv_channel(material: &mut StandardMaterial, channel: UvChannel) {
+    #[cfg(feature = "pbr_transmission_textures")]
+    {
+        material.specular_transmission_channel = channel.clone();
+        material.thickness_channel = channel.clone();
+        material.diffuse_transmission_channel = channel.clone();
+    }
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    {
+        material.clearcoat_channel = channel.clone();
+        material.clearcoat_roughness_channel = channel.clone();
+        material.clearcoat_normal_channel = channel.clone();
+    }
+    material.base_color_channel = channel.clone();
+    material.emissive_channel = channel.clone();
+    material.metallic_roughness_channel = channel.clone();
+    material.normal_map_channel = channel.clone();
+    material.occlusion_channel = channel;
+}
+
+/// Component-wise product of the colors, with the alpha of `a`
+pub(crate) fn multiply(a: LinearRgba, b: LinearRgba) -> LinearRgba {
+    LinearRgba::new(a.red * b.red, a.green * b.green, a.blue * b.blue, a.alpha)
+}
@@ -1,25 +1,113 @@
-#![allow(clippy::single_match)]
-
+use crate::{
+    bake::{self, bake_material},
+    settings::{MaterialXLoaderSettings, UnsupportedInputs},
+    texture::Textures,
+};
 use bevy_asset::{AssetPath, LoadContext};
 use bevy_pbr::StandardMaterial;
 use materialx_parser::{
-    ast::Element,
+    ast::ColorSpace,
     data_types::{DataTypeAndValue, ValueParseError},
-    nodes::{AccessError, InputData},
+    eval::Evaluator,
+    nodes::{AccessError, InputData, ResolvedInput, UpstreamNode},
     wrap_node, GetAllByType, GetByTypeAndName as _, Input, MaterialX,
 };
 use smol_str::SmolStr;
-use tracing::{debug, instrument, warn};
+use tracing::{debug, warn};
 use StandardMaterialTransformError as Error;
 
+mod mapping;
+pub(crate) use mapping::multiply;
+use mapping::{build_material, Fidelity};
+mod packing;
+use packing::pack_textures;
 mod processor;
+pub use processor::{
+    MaterialXProcessor, MaterialXToStandardMaterial, ProcessError, ProcessedMaterial,
+    StandardMaterialLoader, StandardMaterialSaver,
+};
 
-pub fn material_to_pbr(
+/// Convert a `surfacematerial` (the first one if `material` is `None`) to a
+/// `StandardMaterial`
+///
+/// With [`bake`](MaterialXLoaderSettings::bake) set, procedural inputs are
+/// rasterized into textures added to `loader` as sub-assets.
+pub async fn material_to_pbr(
     def: &MaterialX,
     material: Option<SmolStr>,
-    path: &AssetPath,
+    path: &AssetPath<'_>,
+    settings: &MaterialXLoaderSettings,
+    loader: &mut LoadContext<'_>,
+) -> Result<StandardMaterial, Error> {
+    let mut textures = Textures::default();
+    let mut evaluator = bake::evaluator(def, settings, path);
+    convert_material(
+        def,
+        material,
+        path,
+        settings,
+        &mut textures,
+        evaluator.as_mut(),
+        loader,
+    )
+    .await
+}
+
+/// [`material_to_pbr`], recording the textures the material reads in
+/// `textures`, and baking with the `evaluator` shared by the materials of
+/// `def`
+pub(crate) async fn convert_material(
+    def: &MaterialX,
+    material: Option<SmolStr>,
+    path: &AssetPath<'_>,
+    settings: &MaterialXLoaderSettings,
+    textures: &mut Textures,
+    evaluator: Option<&mut Evaluator>,
     loader: &mut LoadContext<'_>,
 ) -> Result<StandardMaterial, Error> {
+    let (material, surface) = find_material(def, material)?;
+    let mapping_error = |e| Error::MaterialMapping {
+        name: material.name.clone(),
+        source: Box::new(e),
+    };
+    let (mut res, notes, packing) =
+        build_material(&surface, &material, def, path, settings, textures, loader)
+            .map_err(mapping_error)?;
+    for note in notes {
+        match (note.fidelity, settings.unsupported) {
+            (Fidelity::Approximated, _) => debug!(material = %material.name, "Approximated {note}"),
+            (Fidelity::Dropped, UnsupportedInputs::Warn) => {
+                warn!(material = %material.name, "Ignoring {note}");
+            }
+            (Fidelity::Dropped, UnsupportedInputs::Error) => {
+                return Err(Error::Unsupported {
+                    reason: note.to_string(),
+                    node: surface.name.clone(),
+                });
+            }
+        }
+    }
+    pack_textures(&packing, &mut res, textures, loader).await;
+    bake_material(
+        evaluator,
+        &surface.name,
+        &material.name,
+        &mut res,
+        settings,
+        path,
+        loader,
+    )
+    .await
+    .map_err(mapping_error)?;
+    Ok(res)
+}
+
+/// The `surfacematerial` named `material` (or the first one) and its
+/// `standard_surface`
+pub(crate) fn find_material(
+    def: &MaterialX,
+    material: Option<SmolStr>,
+) -> Result<(surfacematerial, standard_surface), Error> {
     let material = if let Some(name) = material {
         def.get(name.clone()).map_err(|e| Error::MaterialNotFound {
             name,
@@ -41,138 +129,43 @@ pub fn material_to_pbr(
             })
         }
     })?;
-
-    build_material(&surface, &material, def, path, loader).map_err(|e| Error::MaterialMapping {
-        name: material.name.clone(),
-        source: Box::new(e),
-    })
+    Ok((material, surface))
 }
 
-#[instrument(skip_all, fields(%material.name))]
-fn build_material(
-    surface: &standard_surface,
-    material: &surfacematerial,
+/// Path of the texture file read by an image node
+///
+/// Returns `None` if the node is not an image node.
+fn texture_path(
     def: &MaterialX,
+    node: &UpstreamNode,
     path: &AssetPath,
-    loader: &mut LoadContext<'_>,
-) -> Result<StandardMaterial, MaterialError> {
-    let mut res = StandardMaterial::default();
-    {
-        match surface.get::<Input>("base_color".into()) {
-            Ok(input) => match input.data {
-                InputData::Value(val) => {
-                    let val = DataTypeAndValue::from_tag_and_value(&input.r#type, &val)?;
-                    res.base_color = val.try_into()?;
-                }
-                InputData::NodeReference { node_name } => {
-                    debug!("Found node ref to {node_name}");
-                    if let Ok(tiled) = def.get::<tiledimage>(node_name) {
-                        let filename = tiled.get::<Element>("file".into())?.attr("value")?;
-                        let path = path.resolve_embed(&filename)?;
-                        res.base_color_texture = Some(loader.load(&path));
-                        debug!("Loaded base color texture {path}");
-                    }
-                }
-                _ => {}
-            },
-            Err(AccessError::NotFound { .. }) | Err(AccessError::Unimplemented(..)) => {}
-            Err(e) => return Err(MaterialError::from(e)),
-        }
-    }
-
-    #[cfg(feature = "bevy/pbr_multi_layer_material_textures")]
-    {
-        match surface.get::<Input>("coat_roughness".into()) {
-            Ok(input) => match input.data {
-                InputData::NodeReference { node_name } => {
-                    debug!("Found node ref to {node_name}");
-                    if let Ok(tiled) = def.get::<tiledimage>(node_name) {
-                        let filename = tiled.get::<Element>("file".into())?.attr("value")?;
-                        let path = path.resolve_embed(&filename)?;
-                        res.clearcoat_roughness_texture = Some(loader.load(&path));
-                        debug!("Loaded coat_roughness texture {path}");
-                    }
-                }
-                _ => {}
-            },
-            Err(AccessError::NotFound { .. }) | Err(AccessError::Unimplemented(..)) => {}
-            Err(e) => return Err(MaterialError::from(e)),
-        }
-    }
-
-    {
-        match surface.get::<Input>("normal".into()) {
-            Ok(input) => match input.data {
-                InputData::NodeReference { node_name } => {
-                    debug!("Found node ref to {node_name}");
-                    if let Ok(normal) = def.get::<normalmap>(node_name) {
-                        let input = normal.get::<Element>("in".into())?.attr("nodename")?;
-
-                        if let Ok(tiled) = def.get::<tiledimage>(input) {
-                            let filename = tiled.get::<Element>("file".into())?.attr("value")?;
-                            let path = path.resolve_embed(&filename)?;
-                            res.normal_map_texture = Some(loader.load(&path));
-                            debug!("Loaded normal texture {path}");
-                        }
-                    }
-                }
-                _ => {}
-            },
-            Err(AccessError::NotFound { .. }) | Err(AccessError::Unimplemented(..)) => {}
-            Err(e) => return Err(MaterialError::from(e)),
-        }
+) -> Result<Option<AssetPath<'static>>, MaterialError> {
+    if !matches!(node.element.tag.as_str(), "image" | "tiledimage") {
+        return Ok(None);
     }
-
-    {
-        match material.get::<Input>("displacementshader".into()) {
-            Ok(input) => match input.data {
-                InputData::NodeReference { node_name } => {
-                    debug!("Found node ref to {node_name}");
-                    if let Ok(normal) = def.get::<displacement>(node_name) {
-                        let input = normal
-                            .get::<Element>("displacement".into())?
-                            .attr("nodename")?;
-
-                        if let Ok(tiled) = def.get::<tiledimage>(input) {
-                            let filename = tiled.get::<Element>("file".into())?.attr("value")?;
-                            let path = path.resolve_embed(&filename)?;
-                            res.depth_map = Some(loader.load(&path));
-                            debug!("Loaded displacement {path}");
-                        }
-                    }
-                }
-                _ => {}
-            },
-            Err(AccessError::NotFound { .. }) | Err(AccessError::Unimplemented(..)) => {}
-            Err(e) => return Err(MaterialError::from(e)),
+    match def.resolve(&node.element, node.parent(def), "file".into())? {
+        ResolvedInput::Value(DataTypeAndValue::Filename(filename)) => {
+            Ok(Some(path.resolve_embed(&filename)?))
         }
+        _ => Ok(None),
     }
+}
 
-    match def.resolve_input::<f32>(surface, None, "base".into()) {
-        Ok(x) => res.diffuse_transmission = 1.0 - x,
-        Err(AccessError::NotFound { .. }) | Err(AccessError::Unimplemented(..)) => {}
-        Err(e) => return Err(MaterialError::from(e)),
-    }
-
-    macro_rules! set {
-        ($field:ident, $input:expr) => {
-            match def.resolve_input(&surface, None, $input.into()) {
-                Ok(x) => res.$field = x,
-                Err(AccessError::NotFound { .. }) | Err(AccessError::Unimplemented(..)) => {}
-                Err(e) => return Err(MaterialError::from(e)),
-            }
-        };
-    }
-
-    set!(emissive, "emissive");
-    set!(perceptual_roughness, "specular_roughness");
-    set!(metallic, "metalness");
-    set!(reflectance, "specular");
-    set!(ior, "specular_IOR");
-    set!(clearcoat, "coat");
-    set!(clearcoat_perceptual_roughness, "coat_roughness");
-
-    Ok(res)
+/// Color space of the file read by an image node
+///
+/// That's the `colorspace` attribute of its `file` input, or else of the
+/// closest element containing it, or else `default`.
+pub(crate) fn texture_colorspace(
+    def: &MaterialX,
+    node: &UpstreamNode,
+    default: &ColorSpace,
+) -> ColorSpace {
+    let file = node.element.children.get("file");
+    let scope = file
+        .into_iter()
+        .chain([&node.element])
+        .chain(node.parent(def));
+    def.colorspace_in(scope).unwrap_or_else(|| default.clone())
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -188,9 +181,6 @@ pub enum MaterialError {
 
 wrap_node!(surfacematerial);
 wrap_node!(standard_surface);
-wrap_node!(tiledimage);
-wrap_node!(normalmap);
-wrap_node!(displacement);
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
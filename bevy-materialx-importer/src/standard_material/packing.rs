@@ -0,0 +1,401 @@
+//! Packing channels of several images into one texture
+//!
+//! `StandardMaterial` reads `metalness` and `specular_roughness` from one
+//! `metallic_roughness_texture`, roughness from the green channel and
+//! metalness from the blue one (the glTF convention), while MaterialX files
+//! usually read them from separate grayscale images, or from channels of one
+//! image picked with `extract` or `separate` nodes. Likewise, opacity is the
+//! alpha channel of `base_color_texture`, while MaterialX reads it from an
+//! image of its own.
+//!
+//! The images are loaded along with the material and their channels copied
+//! into a new texture, added as a sub-asset labeled
+//! `metallic_roughness/{hash}`, or `base_color_opacity/{hash}` (and
+//! `base_color_mask/{hash}` if the opacity is only ever 0 or 1). The hash
+//! covers the sources, so materials of a file reading the same images share
+//! one texture. Sub-assets belong to the file that adds them, so other files
+//! reading the same images pack them again. An input that doesn't read an
+//! image is filled in with its constant value.
+//!
+//! Texels are read on the CPU, which Bevy can't do for compressed formats
+//! (like most KTX2 and DDS files). Textures reading those aren't packed.
+//!
+//! `standard_surface` has no ambient occlusion input, so the red channel
+//! (and `occlusion_texture`) stays empty.
+
+use crate::texture::{Sampling, Textures};
+use bevy_asset::{AssetPath, Handle, LoadContext, RenderAssetUsages};
+use bevy_color::{ColorToComponents as _, LinearRgba};
+use bevy_image::{Image, ImageLoaderSettings};
+use bevy_pbr::StandardMaterial;
+use bevy_render::alpha::AlphaMode;
+use materialx_parser::filename::has_udim;
+use std::hash::{DefaultHasher, Hash, Hasher as _};
+use tracing::{debug, warn};
+use wgpu_types::{Extent3d, TextureDimension, TextureFormat};
+
+/// One channel of an image file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Channel {
+    pub path: AssetPath<'static>,
+    /// 0 for red, up to 3 for alpha
+    pub index: usize,
+    pub sampling: Sampling,
+}
+
+/// The channels read by `metalness` and `specular_roughness`, `None` for
+/// constant inputs
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetallicRoughness {
+    pub metalness: Option<Channel>,
+    pub roughness: Option<Channel>,
+}
+
+/// The channel read by `opacity`, and the base color image it goes with
+#[derive(Debug, Clone, Hash)]
+pub(crate) struct Opacity {
+    /// The file of `base_color`, whether it's sRGB, and how it's sampled
+    pub color: Option<(AssetPath<'static>, bool, Sampling)>,
+    pub opacity: Channel,
+}
+
+/// The textures to pack for a material
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Packing {
+    pub metallic_roughness: MetallicRoughness,
+    pub opacity: Option<Opacity>,
+}
+
+/// Set the packed textures of `res`, see [`pack_metallic_roughness`] and
+/// [`pack_opacity`]
+pub(crate) async fn pack_textures(
+    packing: &Packing,
+    res: &mut StandardMaterial,
+    textures: &mut Textures,
+    loader: &mut LoadContext<'_>,
+) {
+    pack_metallic_roughness(&packing.metallic_roughness, res, textures, loader).await;
+    if let Some(opacity) = &packing.opacity {
+        pack_opacity(opacity, res, textures, loader).await;
+    }
+}
+
+/// Set `metallic_roughness_texture` of `res` to the channels of `sources`
+///
+/// The constant `metallic` and `perceptual_roughness` of `res` fill in the
+/// channel of an input without an image, and both become 1.0 since the
+/// texture holds the final values. Images that can't be loaded leave `res`
+/// as it is. An image used as is is recorded in `textures`.
+async fn pack_metallic_roughness(
+    sources: &MetallicRoughness,
+    res: &mut StandardMaterial,
+    textures: &mut Textures,
+    loader: &mut LoadContext<'_>,
+) {
+    if let Some(texture) = packed_metallic_roughness(sources, res, textures, loader).await {
+        res.metallic_roughness_texture = Some(texture);
+        res.metallic = 1.0;
+        res.perceptual_roughness = 1.0;
+    }
+}
+
+async fn packed_metallic_roughness(
+    sources: &MetallicRoughness,
+    res: &StandardMaterial,
+    textures: &mut Textures,
+    loader: &mut LoadContext<'_>,
+) -> Option<Handle<Image>> {
+    let first = sources.roughness.as_ref().or(sources.metalness.as_ref())?;
+    let sampling = first.sampling;
+
+    // An image already packed the glTF way can be used as is
+    if let (Some(roughness), Some(metalness)) = (&sources.roughness, &sources.metalness) {
+        if roughness.path == metalness.path && roughness.index == 1 && metalness.index == 2 {
+            let path = roughness.path.clone();
+            return Some(textures.load(loader, path, false, sampling));
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    (&sources.roughness, &sources.metalness).hash(&mut hasher);
+    if sources.roughness.is_none() {
+        res.perceptual_roughness.to_bits().hash(&mut hasher);
+    }
+    if sources.metalness.is_none() {
+        res.metallic.to_bits().hash(&mut hasher);
+    }
+    let label = format!("metallic_roughness/{:016x}", hasher.finish());
+    if loader.has_labeled_asset(label.clone()) {
+        return Some(loader.get_label_handle(label));
+    }
+
+    let roughness = load_channel(&sources.roughness, loader).await?;
+    let metalness = load_channel(&sources.metalness, loader).await?;
+    let (width, height, data) = metallic_roughness_data(
+        (roughness.as_ref(), res.perceptual_roughness),
+        (metalness.as_ref(), res.metallic),
+    );
+
+    let image = packed_image(width, height, data, TextureFormat::Rgba8Unorm, sampling);
+    debug!("Packed metalness and roughness at {width}x{height} into {label}");
+    Some(loader.add_labeled_asset(label, image))
+}
+
+/// Set `base_color_texture` of `res` to the base color image of `sources`
+/// (or white) with the opacity in its alpha channel
+///
+/// Opacities of only 0 and 1 make a cutout with [`AlphaMode::Mask`], others
+/// are blended. If the images can't be loaded, the base color image is used
+/// without the opacity.
+async fn pack_opacity(
+    sources: &Opacity,
+    res: &mut StandardMaterial,
+    textures: &mut Textures,
+    loader: &mut LoadContext<'_>,
+) {
+    let Some((texture, mask)) = packed_opacity(sources, loader).await else {
+        // Keep the base color texture without the opacity
+        if let Some((path, srgb, sampling)) = &sources.color {
+            res.base_color_texture = Some(textures.load(loader, path.clone(), *srgb, *sampling));
+        }
+        return;
+    };
+    res.base_color_texture = Some(texture);
+    res.alpha_mode = if mask {
+        AlphaMode::Mask(0.5)
+    } else {
+        AlphaMode::Blend
+    };
+}
+
+/// The packed texture, and whether it's a cutout
+async fn packed_opacity(
+    sources: &Opacity,
+    loader: &mut LoadContext<'_>,
+) -> Option<(Handle<Image>, bool)> {
+    let mut hasher = DefaultHasher::new();
+    sources.hash(&mut hasher);
+    let hash = hasher.finish();
+    let mask_label = format!("base_color_mask/{hash:016x}");
+    let blend_label = format!("base_color_opacity/{hash:016x}");
+    if loader.has_labeled_asset(mask_label.clone()) {
+        return Some((loader.get_label_handle(mask_label), true));
+    }
+    if loader.has_labeled_asset(blend_label.clone()) {
+        return Some((loader.get_label_handle(blend_label), false));
+    }
+
+    let color = match &sources.color {
+        Some((path, _, _)) => Some(load_file(path, loader).await?),
+        None => None,
+    };
+    let opacity = load_file(&sources.opacity.path, loader).await?;
+    let (width, height, data) = opacity_data(color.as_ref(), &opacity, sources.opacity.index);
+    let mask = is_mask(&data);
+
+    let srgb = sources.color.as_ref().is_none_or(|(_, srgb, _)| *srgb);
+    let format = if srgb {
+        TextureFormat::Rgba8UnormSrgb
+    } else {
+        TextureFormat::Rgba8Unorm
+    };
+    let sampling = match &sources.color {
+        Some((_, _, sampling)) => *sampling,
+        None => sources.opacity.sampling,
+    };
+    let image = packed_image(width, height, data, format, sampling);
+    let label = if mask { mask_label } else { blend_label };
+    debug!("Packed base color and opacity at {width}x{height} into {label}");
+    Some((loader.add_labeled_asset(label, image), mask))
+}
+
+/// The size and RGBA texels of a texture with the roughness in green and the
+/// metalness in blue
+///
+/// Each input is the image and channel it reads, and the constant filling in
+/// the channel without one.
+fn metallic_roughness_data(
+    roughness: (Option<&(Image, usize)>, f32),
+    metalness: (Option<&(Image, usize)>, f32),
+) -> (u32, u32, Vec<u8>) {
+    let (width, height) = size(
+        [roughness.0, metalness.0]
+            .into_iter()
+            .flatten()
+            .map(|(i, _)| i),
+    );
+    let channel = |(source, constant): (Option<&(Image, usize)>, f32), x: u32, y: u32| {
+        let Some((image, index)) = source else {
+            return unorm(constant);
+        };
+        unorm(texel(image, x, y, width, height)[*index])
+    };
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| [0, channel(roughness, x, y), channel(metalness, x, y), 255])
+        .collect();
+    (width, height, data)
+}
+
+/// The size and RGBA texels of `color` (or white) with channel `index` of
+/// `opacity` in the alpha channel
+fn opacity_data(color: Option<&Image>, opacity: &Image, index: usize) -> (u32, u32, Vec<u8>) {
+    let (width, height) = size(color.into_iter().chain([opacity]));
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            // The color is loaded as linear, so these are its encoded values
+            let [r, g, b, _] = match color {
+                Some(color) => texel(color, x, y, width, height),
+                None => [1.0; 4],
+            };
+            let alpha = texel(opacity, x, y, width, height)[index];
+            [unorm(r), unorm(g), unorm(b), unorm(alpha)]
+        })
+        .collect();
+    (width, height, data)
+}
+
+/// Whether the alpha channel of RGBA texels `data` is only ever 0 or 1, for
+/// [`AlphaMode::Mask`]
+fn is_mask(data: &[u8]) -> bool {
+    data.chunks(4).all(|texel| matches!(texel[3], 0 | 255))
+}
+
+/// The larger of the sizes of `images`, at least 1x1
+fn size<'a>(images: impl IntoIterator<Item = &'a Image>) -> (u32, u32) {
+    images.into_iter().fold((1, 1), |(w, h), image| {
+        (w.max(image.width()), h.max(image.height()))
+    })
+}
+
+/// The texel of `image` nearest to texel (`x`, `y`) of an image `width` by
+/// `height` texels large
+///
+/// [`load_file`] checks that the format can be read.
+fn texel(image: &Image, x: u32, y: u32, width: u32, height: u32) -> [f32; 4] {
+    let x = x * image.width() / width;
+    let y = y * image.height() / height;
+    image
+        .get_color_at(x, y)
+        .map_or([0.0; 4], |color| LinearRgba::from(color).to_f32_array())
+}
+
+fn packed_image(
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    format: TextureFormat,
+    sampling: Sampling,
+) -> Image {
+    let mut image = Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = sampling.sampler();
+    image
+}
+
+/// Load the image of `source` right away, `Some(None)` without a source
+async fn load_channel(
+    source: &Option<Channel>,
+    loader: &mut LoadContext<'_>,
+) -> Option<Option<(Image, usize)>> {
+    let Some(source) = source else {
+        return Some(None);
+    };
+    let image = load_file(&source.path, loader).await?;
+    Some(Some((image, source.index)))
+}
+
+/// Load the image at `path` right away, as linear
+///
+/// `None` with a warning if it can't be loaded, or its texels can't be read.
+async fn load_file(path: &AssetPath<'static>, loader: &mut LoadContext<'_>) -> Option<Image> {
+    if has_udim(&path.to_string()) {
+        warn!(%path, "Can't pack UDIM sets");
+        return None;
+    }
+    let image = loader
+        .loader()
+        .with_settings(|settings: &mut ImageLoaderSettings| settings.is_srgb = false)
+        .immediate()
+        .load::<Image>(path)
+        .await;
+    match image.map(|image| image.take()) {
+        Ok(image) => match image.get_color_at(0, 0) {
+            Ok(_) => Some(image),
+            Err(e) => {
+                let format = image.texture_descriptor.format;
+                warn!(%path, "Can't pack texture with format {format:?}: {e}");
+                None
+            }
+        },
+        Err(e) => {
+            warn!(%path, "Failed to load texture for packing: {e}");
+            None
+        }
+    }
+}
+
+fn unorm(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, data: Vec<u8>) -> Image {
+        Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::default(),
+        )
+    }
+
+    #[test]
+    fn metallic_roughness() {
+        let roughness = (image(2, 1, vec![10, 0, 0, 255, 20, 0, 0, 255]), 0);
+        let metalness = (image(1, 1, vec![0, 0, 30, 40]), 3);
+        let (width, height, data) =
+            metallic_roughness_data((Some(&roughness), 0.5), (Some(&metalness), 0.0));
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(data, [0, 10, 40, 255, 0, 20, 40, 255]);
+
+        // A constant input fills in its channel
+        let (width, height, data) = metallic_roughness_data((Some(&roughness), 0.5), (None, 1.0));
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(data, [0, 10, 255, 255, 0, 20, 255, 255]);
+        let (_, _, data) = metallic_roughness_data((None, 0.2), (None, 1.0));
+        assert_eq!(data, [0, 51, 255, 255]);
+    }
+
+    #[test]
+    fn opacity() {
+        let color = image(1, 2, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        let opacity = image(1, 1, vec![0, 255, 128, 0]);
+
+        let (width, height, data) = opacity_data(Some(&color), &opacity, 1);
+        assert_eq!((width, height), (1, 2));
+        assert_eq!(data, [1, 2, 3, 255, 5, 6, 7, 255]);
+        assert!(is_mask(&data));
+
+        let (_, _, data) = opacity_data(None, &opacity, 2);
+        assert_eq!(data, [255, 255, 255, 128]);
+        assert!(!is_mask(&data));
+    }
+}
@@ -1,23 +1,679 @@
-use crate::MaterialX;
-use bevy_asset::transformer::{AssetTransformer, TransformedAsset};
-use bevy_pbr::StandardMaterial;
+//! Converting `.mtlx` files into `StandardMaterial`s when processing assets
+//!
+//! [`MaterialXProcessor`] loads a `.mtlx` file with the
+//! [`MaterialXLoader`], keeps the converted `StandardMaterial`s, and saves them
+//! as RON. The [`StandardMaterialLoader`] reads them back without parsing any
+//! MaterialX. The root asset is the file's first material, every
+//! `surfacematerial` is a sub-asset labeled with its name, just like when
+//! loading the `.mtlx` file directly.
+//!
+//! Image files are referenced by their asset paths, so the processor tracks
+//! them as dependencies and processes them like any other image. They are
+//! loaded with the color space and sampler the `.mtlx` file gives them.
+//! Textures that only exist in memory (packed, baked and UDIM atlases) are
+//! embedded in the RON file, since a saver writes a single file, and become
+//! sub-assets with the same labels again. All textures are read from the
+//! first UV channel.
 
+use crate::{texture::TextureFiles, MaterialX, MaterialXLoader};
+use bevy_asset::{
+    io::{Reader, Writer},
+    processor::LoadTransformAndSave,
+    saver::{AssetSaver, SavedAsset},
+    transformer::{AssetTransformer, TransformedAsset},
+    Asset, AssetLoader, AssetPath, AsyncWriteExt as _, Handle, LoadContext, RenderAssetUsages,
+};
+use bevy_color::{Color, ColorToComponents as _, LinearRgba};
+use bevy_image::{Image, ImageLoaderSettings, ImageSampler};
+use bevy_math::{Affine2, Mat2, Vec2};
+use bevy_pbr::{StandardMaterial, UvChannel};
+use bevy_reflect::TypePath;
+use bevy_render::alpha::AlphaMode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+};
+use tracing::warn;
+use wgpu_types::{Extent3d, Face, TextureDimension, TextureFormat};
+
+/// Processes `.mtlx` files into `StandardMaterial`s saved as RON, which the
+/// [`StandardMaterialLoader`] reads back with their sub-assets and textures
+pub type MaterialXProcessor =
+    LoadTransformAndSave<MaterialXLoader, MaterialXToStandardMaterial, StandardMaterialSaver>;
+
+/// A `StandardMaterial` and the settings its image files are loaded with
+#[derive(Debug, Asset, TypePath)]
+pub struct ProcessedMaterial {
+    pub material: StandardMaterial,
+    files: TextureFiles,
+}
+
+/// Keeps the [`MaterialX::material`] of a file, its labeled sub-assets, and
+/// the settings of the image files they read
+#[derive(Debug, Clone, Copy, Default)]
 pub struct MaterialXToStandardMaterial;
 
 impl AssetTransformer for MaterialXToStandardMaterial {
     type AssetInput = MaterialX;
-    type AssetOutput = StandardMaterial;
+    type AssetOutput = ProcessedMaterial;
     type Settings = ();
-    type Error = super::StandardMaterialTransformError;
+    type Error = Infallible;
 
     async fn transform<'a>(
         &'a self,
-        _asset: TransformedAsset<Self::AssetInput>,
+        mut asset: TransformedAsset<Self::AssetInput>,
         _settings: &'a Self::Settings,
     ) -> Result<TransformedAsset<Self::AssetOutput>, Self::Error> {
-        // FIXME: How to get the material name from fragment?
-        // FIXME: How to load images referred to by the MaterialX file?
+        let processed = ProcessedMaterial {
+            material: asset.material.clone(),
+            files: std::mem::take(&mut asset.files),
+        };
+        Ok(asset.replace_asset(processed))
+    }
+}
+
+/// Saves a `StandardMaterial`, its labeled `StandardMaterial` sub-assets and
+/// the images in memory they read as RON
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardMaterialSaver;
+
+impl AssetSaver for StandardMaterialSaver {
+    type Asset = ProcessedMaterial;
+    type Settings = ();
+    type OutputLoader = StandardMaterialLoader;
+    type Error = ProcessError;
+
+    async fn save(
+        &self,
+        writer: &mut Writer,
+        asset: SavedAsset<'_, Self::Asset>,
+        _settings: &Self::Settings,
+    ) -> Result<(), Self::Error> {
+        let mut embedded = BTreeSet::new();
+        let material = MaterialDescription::new(&asset.material, &asset.files, &mut embedded);
+        let mut labeled = BTreeMap::new();
+        for label in asset.iter_labels() {
+            if let Some(material) = asset.get_labeled::<StandardMaterial, _>(label) {
+                let material = MaterialDescription::new(&material, &asset.files, &mut embedded);
+                labeled.insert(label.to_string(), material);
+            }
+        }
+        let images = embedded
+            .into_iter()
+            .filter_map(|label| {
+                let Some(image) = asset.get_labeled::<Image, _>(label.as_str()) else {
+                    warn!(label, "Dropping texture that isn't a sub-asset");
+                    return None;
+                };
+                SavedImage::new(&image, &label).map(|image| (label, image))
+            })
+            .collect();
+        let file = MaterialFile {
+            material,
+            labeled,
+            images,
+        };
+        let ron = ron::ser::to_string_pretty(&file, Default::default())?;
+        writer.write_all(ron.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+/// Loads `StandardMaterial`s saved by the [`StandardMaterialSaver`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardMaterialLoader;
+
+impl AssetLoader for StandardMaterialLoader {
+    type Asset = StandardMaterial;
+    type Settings = ();
+    type Error = ProcessError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file: MaterialFile = ron::de::from_bytes(&bytes)?;
+        for (label, image) in file.images {
+            load_context.add_labeled_asset(label, image.build());
+        }
+        for (label, material) in file.labeled {
+            let material = material.build(load_context);
+            load_context.add_labeled_asset(label, material);
+        }
+        Ok(file.material.build(load_context))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["material.ron"]
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MaterialFile {
+    material: MaterialDescription,
+    labeled: BTreeMap<String, MaterialDescription>,
+    /// The textures in memory, by label
+    #[serde(default)]
+    images: BTreeMap<String, SavedImage>,
+}
+
+/// A texture of a saved material
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SavedTexture {
+    /// An image file, by asset path
+    File {
+        path: String,
+        is_srgb: bool,
+        sampler: ImageSampler,
+    },
+    /// A [`SavedImage`], by label
+    Embedded(String),
+}
+
+/// A texture in memory, with 8-bit RGBA texels
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedImage {
+    width: u32,
+    height: u32,
+    is_srgb: bool,
+    sampler: ImageSampler,
+    #[serde(with = "bytes")]
+    data: Vec<u8>,
+}
+
+impl SavedImage {
+    /// `None` with a warning for other formats, which the textures this crate
+    /// makes don't have
+    fn new(image: &Image, label: &str) -> Option<Self> {
+        let is_srgb = match image.texture_descriptor.format {
+            TextureFormat::Rgba8Unorm => false,
+            TextureFormat::Rgba8UnormSrgb => true,
+            format => {
+                warn!(label, "Dropping texture with format {format:?}");
+                return None;
+            }
+        };
+        Some(SavedImage {
+            width: image.width(),
+            height: image.height(),
+            is_srgb,
+            sampler: image.sampler.clone(),
+            data: image.data.clone(),
+        })
+    }
+
+    fn build(self) -> Image {
+        let format = if self.is_srgb {
+            TextureFormat::Rgba8UnormSrgb
+        } else {
+            TextureFormat::Rgba8Unorm
+        };
+        let size = Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        };
+        let mut image = Image::new(
+            size,
+            TextureDimension::D2,
+            self.data,
+            format,
+            RenderAssetUsages::default(),
+        );
+        image.sampler = self.sampler;
+        image
+    }
+}
+
+/// Texels as bytes, which RON writes as base64 rather than a list of numbers
+mod bytes {
+    use serde::{de, Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(data)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl de::Visitor<'_> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+    }
+}
+
+/// The parts of a `StandardMaterial` that can be serialized
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct MaterialDescription {
+    base_color: [f32; 4],
+    base_color_channel: SavedUvChannel,
+    base_color_texture: Option<SavedTexture>,
+    emissive: [f32; 4],
+    emissive_channel: SavedUvChannel,
+    emissive_texture: Option<SavedTexture>,
+    perceptual_roughness: f32,
+    metallic: f32,
+    metallic_roughness_channel: SavedUvChannel,
+    metallic_roughness_texture: Option<SavedTexture>,
+    reflectance: f32,
+    diffuse_transmission: f32,
+    #[cfg(feature = "pbr_transmission_textures")]
+    diffuse_transmission_channel: SavedUvChannel,
+    specular_transmission: f32,
+    #[cfg(feature = "pbr_transmission_textures")]
+    specular_transmission_channel: SavedUvChannel,
+    #[cfg(feature = "pbr_transmission_textures")]
+    specular_transmission_texture: Option<SavedTexture>,
+    thickness: f32,
+    #[cfg(feature = "pbr_transmission_textures")]
+    thickness_channel: SavedUvChannel,
+    ior: f32,
+    attenuation_distance: f32,
+    attenuation_color: [f32; 4],
+    normal_map_channel: SavedUvChannel,
+    normal_map_texture: Option<SavedTexture>,
+    flip_normal_map_y: bool,
+    occlusion_channel: SavedUvChannel,
+    occlusion_texture: Option<SavedTexture>,
+    clearcoat: f32,
+    clearcoat_perceptual_roughness: f32,
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    clearcoat_channel: SavedUvChannel,
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    clearcoat_texture: Option<SavedTexture>,
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    clearcoat_roughness_channel: SavedUvChannel,
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    clearcoat_roughness_texture: Option<SavedTexture>,
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    clearcoat_normal_channel: SavedUvChannel,
+    #[cfg(feature = "pbr_multi_layer_material_textures")]
+    clearcoat_normal_texture: Option<SavedTexture>,
+    anisotropy_strength: f32,
+    anisotropy_rotation: f32,
+    double_sided: bool,
+    /// `None` for no culling
+    cull_mode: Option<SavedFace>,
+    unlit: bool,
+    alpha_mode: SavedAlphaMode,
+    depth_map: Option<SavedTexture>,
+    parallax_depth_scale: f32,
+    max_parallax_layer_count: f32,
+    /// Columns of the matrix, then the translation
+    uv_transform: [f32; 6],
+}
+
+/// Serializable [`UvChannel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum SavedUvChannel {
+    Uv0,
+    Uv1,
+}
+
+impl From<&UvChannel> for SavedUvChannel {
+    fn from(channel: &UvChannel) -> Self {
+        match channel {
+            UvChannel::Uv0 => SavedUvChannel::Uv0,
+            UvChannel::Uv1 => SavedUvChannel::Uv1,
+        }
+    }
+}
+
+impl From<SavedUvChannel> for UvChannel {
+    fn from(channel: SavedUvChannel) -> Self {
+        match channel {
+            SavedUvChannel::Uv0 => UvChannel::Uv0,
+            SavedUvChannel::Uv1 => UvChannel::Uv1,
+        }
+    }
+}
+
+/// Serializable [`Face`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum SavedFace {
+    Front,
+    Back,
+}
+
+/// Serializable [`AlphaMode`]
+#[derive(Debug, Serialize, Deserialize)]
+enum SavedAlphaMode {
+    Opaque,
+    Mask(f32),
+    Blend,
+    Premultiplied,
+    AlphaToCoverage,
+    Add,
+    Multiply,
+}
+
+impl Default for MaterialDescription {
+    fn default() -> Self {
+        MaterialDescription::new(
+            &StandardMaterial::default(),
+            &TextureFiles::new(),
+            &mut BTreeSet::new(),
+        )
+    }
+}
+
+impl MaterialDescription {
+    /// The description of `material`, whose image files are loaded like
+    /// `files` says
+    ///
+    /// The labels of the textures in memory are added to `embedded`.
+    fn new(
+        material: &StandardMaterial,
+        files: &TextureFiles,
+        embedded: &mut BTreeSet<String>,
+    ) -> Self {
+        let mut texture =
+            |texture: &Option<Handle<Image>>| saved_texture(texture.as_ref()?, files, embedded);
+        let uv = material.uv_transform;
+        MaterialDescription {
+            base_color: LinearRgba::from(material.base_color).to_f32_array(),
+            base_color_channel: (&material.base_color_channel).into(),
+            base_color_texture: texture(&material.base_color_texture),
+            emissive: material.emissive.to_f32_array(),
+            emissive_channel: (&material.emissive_channel).into(),
+            emissive_texture: texture(&material.emissive_texture),
+            perceptual_roughness: material.perceptual_roughness,
+            metallic: material.metallic,
+            metallic_roughness_channel: (&material.metallic_roughness_channel).into(),
+            metallic_roughness_texture: texture(&material.metallic_roughness_texture),
+            reflectance: material.reflectance,
+            diffuse_transmission: material.diffuse_transmission,
+            #[cfg(feature = "pbr_transmission_textures")]
+            diffuse_transmission_channel: (&material.diffuse_transmission_channel).into(),
+            specular_transmission: material.specular_transmission,
+            #[cfg(feature = "pbr_transmission_textures")]
+            specular_transmission_channel: (&material.specular_transmission_channel).into(),
+            #[cfg(feature = "pbr_transmission_textures")]
+            specular_transmission_texture: texture(&material.specular_transmission_texture),
+            thickness: material.thickness,
+            #[cfg(feature = "pbr_transmission_textures")]
+            thickness_channel: (&material.thickness_channel).into(),
+            ior: material.ior,
+            attenuation_distance: material.attenuation_distance,
+            attenuation_color: LinearRgba::from(material.attenuation_color).to_f32_array(),
+            normal_map_channel: (&material.normal_map_channel).into(),
+            normal_map_texture: texture(&material.normal_map_texture),
+            flip_normal_map_y: material.flip_normal_map_y,
+            occlusion_channel: (&material.occlusion_channel).into(),
+            occlusion_texture: texture(&material.occlusion_texture),
+            clearcoat: material.clearcoat,
+            clearcoat_perceptual_roughness: material.clearcoat_perceptual_roughness,
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_channel: (&material.clearcoat_channel).into(),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_texture: texture(&material.clearcoat_texture),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_roughness_channel: (&material.clearcoat_roughness_channel).into(),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_roughness_texture: texture(&material.clearcoat_roughness_texture),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_normal_channel: (&material.clearcoat_normal_channel).into(),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_normal_texture: texture(&material.clearcoat_normal_texture),
+            anisotropy_strength: material.anisotropy_strength,
+            anisotropy_rotation: material.anisotropy_rotation,
+            double_sided: material.double_sided,
+            cull_mode: material.cull_mode.map(|face| match face {
+                Face::Front => SavedFace::Front,
+                Face::Back => SavedFace::Back,
+            }),
+            unlit: material.unlit,
+            alpha_mode: match material.alpha_mode {
+                AlphaMode::Opaque => SavedAlphaMode::Opaque,
+                AlphaMode::Mask(cutoff) => SavedAlphaMode::Mask(cutoff),
+                AlphaMode::Blend => SavedAlphaMode::Blend,
+                AlphaMode::Premultiplied => SavedAlphaMode::Premultiplied,
+                AlphaMode::AlphaToCoverage => SavedAlphaMode::AlphaToCoverage,
+                AlphaMode::Add => SavedAlphaMode::Add,
+                AlphaMode::Multiply => SavedAlphaMode::Multiply,
+            },
+            depth_map: texture(&material.depth_map),
+            parallax_depth_scale: material.parallax_depth_scale,
+            max_parallax_layer_count: material.max_parallax_layer_count,
+            uv_transform: [
+                uv.matrix2.x_axis.x,
+                uv.matrix2.x_axis.y,
+                uv.matrix2.y_axis.x,
+                uv.matrix2.y_axis.y,
+                uv.translation.x,
+                uv.translation.y,
+            ],
+        }
+    }
+
+    fn build(self, loader: &mut LoadContext<'_>) -> StandardMaterial {
+        let mut texture =
+            |texture: Option<SavedTexture>| texture.map(|texture| load_texture(loader, texture));
+        let [a, b, c, d, x, y] = self.uv_transform;
+        StandardMaterial {
+            base_color: Color::LinearRgba(LinearRgba::from_f32_array(self.base_color)),
+            base_color_channel: self.base_color_channel.into(),
+            base_color_texture: texture(self.base_color_texture),
+            emissive: LinearRgba::from_f32_array(self.emissive),
+            emissive_channel: self.emissive_channel.into(),
+            emissive_texture: texture(self.emissive_texture),
+            perceptual_roughness: self.perceptual_roughness,
+            metallic: self.metallic,
+            metallic_roughness_channel: self.metallic_roughness_channel.into(),
+            metallic_roughness_texture: texture(self.metallic_roughness_texture),
+            reflectance: self.reflectance,
+            diffuse_transmission: self.diffuse_transmission,
+            #[cfg(feature = "pbr_transmission_textures")]
+            diffuse_transmission_channel: self.diffuse_transmission_channel.into(),
+            specular_transmission: self.specular_transmission,
+            #[cfg(feature = "pbr_transmission_textures")]
+            specular_transmission_channel: self.specular_transmission_channel.into(),
+            #[cfg(feature = "pbr_transmission_textures")]
+            specular_transmission_texture: texture(self.specular_transmission_texture),
+            thickness: self.thickness,
+            #[cfg(feature = "pbr_transmission_textures")]
+            thickness_channel: self.thickness_channel.into(),
+            ior: self.ior,
+            attenuation_distance: self.attenuation_distance,
+            attenuation_color: Color::LinearRgba(LinearRgba::from_f32_array(
+                self.attenuation_color,
+            )),
+            normal_map_channel: self.normal_map_channel.into(),
+            normal_map_texture: texture(self.normal_map_texture),
+            flip_normal_map_y: self.flip_normal_map_y,
+            occlusion_channel: self.occlusion_channel.into(),
+            occlusion_texture: texture(self.occlusion_texture),
+            clearcoat: self.clearcoat,
+            clearcoat_perceptual_roughness: self.clearcoat_perceptual_roughness,
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_channel: self.clearcoat_channel.into(),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_texture: texture(self.clearcoat_texture),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_roughness_channel: self.clearcoat_roughness_channel.into(),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_roughness_texture: texture(self.clearcoat_roughness_texture),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_normal_channel: self.clearcoat_normal_channel.into(),
+            #[cfg(feature = "pbr_multi_layer_material_textures")]
+            clearcoat_normal_texture: texture(self.clearcoat_normal_texture),
+            anisotropy_strength: self.anisotropy_strength,
+            anisotropy_rotation: self.anisotropy_rotation,
+            double_sided: self.double_sided,
+            cull_mode: self.cull_mode.map(|face| match face {
+                SavedFace::Front => Face::Front,
+                SavedFace::Back => Face::Back,
+            }),
+            unlit: self.unlit,
+            alpha_mode: match self.alpha_mode {
+                SavedAlphaMode::Opaque => AlphaMode::Opaque,
+                SavedAlphaMode::Mask(cutoff) => AlphaMode::Mask(cutoff),
+                SavedAlphaMode::Blend => AlphaMode::Blend,
+                SavedAlphaMode::Premultiplied => AlphaMode::Premultiplied,
+                SavedAlphaMode::AlphaToCoverage => AlphaMode::AlphaToCoverage,
+                SavedAlphaMode::Add => AlphaMode::Add,
+                SavedAlphaMode::Multiply => AlphaMode::Multiply,
+            },
+            depth_map: texture(self.depth_map),
+            parallax_depth_scale: self.parallax_depth_scale,
+            max_parallax_layer_count: self.max_parallax_layer_count,
+            uv_transform: Affine2::from_mat2_translation(
+                Mat2::from_cols_array(&[a, b, c, d]),
+                Vec2::new(x, y),
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+/// How `texture` is saved, `None` for textures without a path
+///
+/// Sub-assets are embedded, files missing from `files` get the default
+/// settings.
+fn saved_texture(
+    texture: &Handle<Image>,
+    files: &TextureFiles,
+    embedded: &mut BTreeSet<String>,
+) -> Option<SavedTexture> {
+    let Some(path) = texture.path() else {
+        warn!("Dropping texture without a path");
+        return None;
+    };
+    if let Some(label) = path.label() {
+        embedded.insert(label.to_string());
+        return Some(SavedTexture::Embedded(label.to_string()));
+    }
+    let (is_srgb, sampler) = match files.get(path) {
+        Some((srgb, sampling)) => (*srgb, sampling.sampler()),
+        None => (true, ImageSampler::Default),
+    };
+    Some(SavedTexture::File {
+        path: path.to_string(),
+        is_srgb,
+        sampler,
+    })
+}
+
+fn load_texture(loader: &mut LoadContext<'_>, texture: SavedTexture) -> Handle<Image> {
+    match texture {
+        SavedTexture::File {
+            path,
+            is_srgb,
+            sampler,
+        } => loader
+            .loader()
+            .with_settings(move |settings: &mut ImageLoaderSettings| {
+                settings.is_srgb = is_srgb;
+                settings.sampler = sampler.clone();
+            })
+            .load(AssetPath::from(path)),
+        SavedTexture::Embedded(label) => loader.get_label_handle(label),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ProcessError {
+    #[error("Failed to read or write material: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to serialize material: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("Failed to parse material: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_image::{ImageAddressMode, ImageSamplerDescriptor};
+
+    #[test]
+    fn ron_round_trip() {
+        let sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: ImageAddressMode::MirrorRepeat,
+            ..Default::default()
+        });
+        let mut image = Image::new(
+            Extent3d {
+                width: 2,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![0, 64, 128, 255, 1, 2, 3, 4],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.sampler = sampler.clone();
+        let material = MaterialDescription {
+            base_color_texture: Some(SavedTexture::File {
+                path: "textures/wood.png".into(),
+                is_srgb: false,
+                sampler,
+            }),
+            metallic_roughness_texture: Some(SavedTexture::Embedded("packed".into())),
+            #[cfg(feature = "pbr_transmission_textures")]
+            specular_transmission_texture: Some(SavedTexture::Embedded("packed".into())),
+            normal_map_channel: SavedUvChannel::Uv1,
+            cull_mode: None,
+            ..Default::default()
+        };
+        let file = MaterialFile {
+            material,
+            labeled: BTreeMap::new(),
+            images: [("packed".into(), SavedImage::new(&image, "packed").unwrap())].into(),
+        };
 
-        todo!()
+        let ron = ron::ser::to_string_pretty(&file, Default::default()).unwrap();
+        let file: MaterialFile = ron::de::from_str(&ron).unwrap();
+        let Some(SavedTexture::File {
+            is_srgb: false,
+            sampler: ImageSampler::Descriptor(descriptor),
+            ..
+        }) = file.material.base_color_texture
+        else {
+            panic!("{:?}", file.material.base_color_texture);
+        };
+        assert!(matches!(
+            descriptor.address_mode_u,
+            ImageAddressMode::MirrorRepeat
+        ));
+        assert_eq!(file.material.normal_map_channel, SavedUvChannel::Uv1);
+        assert_eq!(file.material.base_color_channel, SavedUvChannel::Uv0);
+        assert_eq!(file.material.cull_mode, None);
+        #[cfg(feature = "pbr_transmission_textures")]
+        assert!(matches!(
+            file.material.specular_transmission_texture,
+            Some(SavedTexture::Embedded(_))
+        ));
+        let loaded = file.images.into_values().next().unwrap().build();
+        assert_eq!(loaded.data, image.data);
+        assert_eq!(loaded.size(), image.size());
+        assert_eq!(
+            loaded.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert!(matches!(loaded.sampler, ImageSampler::Descriptor(_)));
     }
 }
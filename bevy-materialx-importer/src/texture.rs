@@ -0,0 +1,178 @@
+//! Sampling the files of `image` and `tiledimage` nodes
+//!
+//! Both nodes read their file with the same sampler settings: `image` takes
+//! them from its `uaddressmode`, `vaddressmode` and `filtertype` inputs,
+//! `tiledimage` always repeats and filters linearly. The sampler is part of
+//! the loaded [`Image`], so a file read by several nodes is sampled like the
+//! first of them says.
+
+use crate::udim::{udim_label, UdimSets};
+use bevy_asset::{AssetPath, Handle, LoadContext};
+use bevy_image::{
+    Image, ImageAddressMode, ImageFilterMode, ImageLoaderSettings, ImageSampler,
+    ImageSamplerDescriptor,
+};
+use materialx_parser::{data_types::DataTypeAndValue, filename::has_udim};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// What an image reads outside of `0..1` along one axis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) enum AddressMode {
+    /// The node's `default` value
+    Constant,
+    /// The closest edge texel
+    Clamp,
+    #[default]
+    Periodic,
+    Mirror,
+}
+
+impl AddressMode {
+    fn from_value(value: Option<DataTypeAndValue>) -> Self {
+        match value {
+            Some(DataTypeAndValue::String(mode)) => match mode.as_str() {
+                "constant" => AddressMode::Constant,
+                "clamp" => AddressMode::Clamp,
+                "mirror" => AddressMode::Mirror,
+                _ => AddressMode::Periodic,
+            },
+            _ => AddressMode::Periodic,
+        }
+    }
+
+    /// Clamping to a border color needs a GPU feature that not all devices
+    /// have, so `Constant` clamps to the edge
+    fn to_bevy(self) -> ImageAddressMode {
+        match self {
+            AddressMode::Constant | AddressMode::Clamp => ImageAddressMode::ClampToEdge,
+            AddressMode::Periodic => ImageAddressMode::Repeat,
+            AddressMode::Mirror => ImageAddressMode::MirrorRepeat,
+        }
+    }
+
+    /// Where texel `i` of a row or column with `size` texels is read from,
+    /// `None` if it is outside of the image
+    pub fn texel(self, i: f64, size: f64) -> Option<f64> {
+        match self {
+            AddressMode::Constant if i < 0.0 || i >= size => None,
+            AddressMode::Constant | AddressMode::Clamp => Some(i.clamp(0.0, size - 1.0)),
+            AddressMode::Periodic => Some(i.rem_euclid(size)),
+            AddressMode::Mirror => {
+                let i = i.rem_euclid(2.0 * size);
+                Some(if i < size { i } else { 2.0 * size - 1.0 - i })
+            }
+        }
+    }
+}
+
+/// How an image node samples its file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Sampling {
+    pub u: AddressMode,
+    pub v: AddressMode,
+    /// `filtertype` is `closest` rather than `linear` or `cubic`
+    pub closest: bool,
+}
+
+impl Sampling {
+    /// The sampling of the image node `category`, with `input` returning the
+    /// constant value of one of its inputs
+    pub fn new(category: &str, input: impl Fn(&str) -> Option<DataTypeAndValue>) -> Self {
+        if category != "image" {
+            return Sampling::default();
+        }
+        Sampling {
+            u: AddressMode::from_value(input("uaddressmode")),
+            v: AddressMode::from_value(input("vaddressmode")),
+            closest: matches!(
+                input("filtertype"),
+                Some(DataTypeAndValue::String(filter)) if filter == "closest"
+            ),
+        }
+    }
+
+    pub fn has_constant(self) -> bool {
+        self.u == AddressMode::Constant || self.v == AddressMode::Constant
+    }
+
+    pub fn sampler(self) -> ImageSampler {
+        ImageSampler::Descriptor(self.descriptor())
+    }
+
+    fn descriptor(self) -> ImageSamplerDescriptor {
+        let filter = if self.closest {
+            ImageFilterMode::Nearest
+        } else {
+            ImageFilterMode::Linear
+        };
+        ImageSamplerDescriptor {
+            address_mode_u: self.u.to_bevy(),
+            address_mode_v: self.v.to_bevy(),
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..Default::default()
+        }
+    }
+}
+
+/// Whether the image files of a material are loaded as sRGB, and how they are
+/// sampled, by asset path
+pub(crate) type TextureFiles = HashMap<AssetPath<'static>, (bool, Sampling)>;
+
+/// The textures the `StandardMaterial`s of a document read
+#[derive(Debug, Default)]
+pub(crate) struct Textures {
+    pub files: TextureFiles,
+    pub udims: UdimSets,
+}
+
+impl Textures {
+    /// Load the image at `path` like [`load_image`], recording it in `files`
+    ///
+    /// UDIM sets are read from an atlas of their tiles, see [`crate::udim`].
+    pub fn load(
+        &mut self,
+        loader: &mut LoadContext<'_>,
+        path: AssetPath<'static>,
+        srgb: bool,
+        sampling: Sampling,
+    ) -> Handle<Image> {
+        if has_udim(&path.to_string()) {
+            if let Some(atlas) = self.udims.atlas(&path, srgb, sampling, loader) {
+                return atlas;
+            }
+        }
+        // Like the asset server, keep the settings of the first load
+        self.files.entry(path.clone()).or_insert((srgb, sampling));
+        load_image(loader, path, srgb, sampling)
+    }
+}
+
+/// Load the image at `path` to be sampled like `sampling` says
+///
+/// UDIM sets are read from their atlas, if a material of the file made it
+/// (see [`Textures::load`]).
+pub(crate) fn load_image(
+    loader: &mut LoadContext<'_>,
+    path: AssetPath<'static>,
+    srgb: bool,
+    sampling: Sampling,
+) -> Handle<Image> {
+    if has_udim(&path.to_string()) {
+        let label = udim_label(&path, srgb, sampling);
+        if loader.has_labeled_asset(label.clone()) {
+            return loader.get_label_handle(label);
+        }
+        warn!(%path, "UDIM set wasn't loaded with the file");
+    }
+    let sampler = sampling.sampler();
+    loader
+        .loader()
+        .with_settings(move |settings: &mut ImageLoaderSettings| {
+            settings.is_srgb = srgb;
+            settings.sampler = sampler.clone();
+        })
+        .load(path)
+}
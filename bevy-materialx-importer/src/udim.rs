@@ -0,0 +1,295 @@
+//! UDIM texture sets
+//!
+//! A file name with `<UDIM>` or `<UVTILE>` names one image per UV tile.
+//! `StandardMaterial` reads each texture from a single image, so while the
+//! `.mtlx` file loads, the tiles of every set are loaded, and copied into an
+//! atlas for each way a material reads the set, added as a sub-asset labeled
+//! `udim/{hash}`. The material's `uv_transform` maps UV tiles onto its cells
+//! (see [`atlas_transform`]). Atlases larger than the
+//! [default limits](Limits::default) of wgpu have their cells scaled down.
+//!
+//! All atlases of a file share one grid, given by the document's
+//! [`udimset`](MaterialX::udimset), or else by the tiles found next to the
+//! file, which are added to the document as a `udimset`. Texels of
+//! neighboring tiles bleed into each other when filtering, and generated
+//! shaders and baking don't read atlases.
+
+use crate::texture::Sampling;
+use bevy_asset::{AssetPath, Handle, LoadContext, RenderAssetUsages};
+use bevy_color::{ColorToComponents as _, LinearRgba};
+use bevy_image::{Image, ImageLoaderSettings};
+use bevy_math::{Affine2, Vec2};
+use materialx_parser::{
+    data_types::DataTypeAndValue,
+    filename::{has_udim, Udim},
+    nodes::ResolvedInput,
+    MaterialX,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash as _, Hasher as _},
+};
+use tracing::{debug, warn};
+use wgpu_types::{Extent3d, Limits, TextureDimension, TextureFormat};
+
+/// Rows of tiles looked for when the document has no `udimset`
+const MAX_ROWS: u32 = 10;
+
+/// Label of the atlas of the UDIM set `path`, decoded and sampled as given
+pub(crate) fn udim_label(path: &AssetPath<'_>, srgb: bool, sampling: Sampling) -> String {
+    let mut hasher = DefaultHasher::new();
+    (path, srgb, sampling).hash(&mut hasher);
+    format!("udim/{:016x}", hasher.finish())
+}
+
+/// Maps Bevy's UVs onto the cells of an atlas of `udims`
+///
+/// Tile rows go up in `v` while Bevy's `v` goes down, so the first row is
+/// at the bottom of the atlas.
+pub(crate) fn atlas_transform(udims: &[Udim]) -> Affine2 {
+    let (columns, rows) = grid(udims);
+    let (columns, rows) = (columns as f32, rows as f32);
+    Affine2::from_scale_angle_translation(
+        Vec2::new(1.0 / columns, 1.0 / rows),
+        0.0,
+        Vec2::new(0.0, (rows - 1.0) / rows),
+    )
+}
+
+/// Columns and rows of tiles up to the last of `udims`
+fn grid(udims: &[Udim]) -> (u32, u32) {
+    udims.iter().fold((1, 1), |(columns, rows), udim| {
+        let (u, v) = udim.tile();
+        (columns.max(u + 1), rows.max(v + 1))
+    })
+}
+
+/// The tiles of the UDIM sets read by the image nodes of a document, copied
+/// into atlases when a material reads them
+#[derive(Debug, Default)]
+pub(crate) struct UdimSets {
+    tiles: HashMap<AssetPath<'static>, Vec<(Udim, Image)>>,
+    columns: u32,
+    rows: u32,
+}
+
+impl UdimSets {
+    /// The atlas of the UDIM set `path`, decoded and sampled as given
+    ///
+    /// `None` if none of its tiles were loaded.
+    pub fn atlas(
+        &self,
+        path: &AssetPath<'static>,
+        srgb: bool,
+        sampling: Sampling,
+        loader: &mut LoadContext<'_>,
+    ) -> Option<Handle<Image>> {
+        let label = udim_label(path, srgb, sampling);
+        if loader.has_labeled_asset(label.clone()) {
+            return Some(loader.get_label_handle(label));
+        }
+        let tiles = self.tiles.get(path).filter(|tiles| !tiles.is_empty())?;
+        let max = Limits::default().max_texture_dimension_2d;
+        let (width, height, data) = atlas(path, tiles, (self.columns, self.rows), max);
+        // Tiles are read as stored, atlases decoded as sRGB or not by their format
+        let format = if srgb {
+            TextureFormat::Rgba8UnormSrgb
+        } else {
+            TextureFormat::Rgba8Unorm
+        };
+        let mut image = Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            format,
+            RenderAssetUsages::default(),
+        );
+        image.sampler = sampling.sampler();
+        debug!(%path, "Loaded {} UDIM tiles into {label}", tiles.len());
+        Some(loader.add_labeled_asset(label, image))
+    }
+}
+
+/// Load the tiles of all UDIM sets read by image nodes of `def`
+///
+/// Adds a `udimset` to `def` if it has none, listing the tiles found.
+pub(crate) async fn load_udim_sets(
+    def: &mut MaterialX,
+    path: &AssetPath<'_>,
+    loader: &mut LoadContext<'_>,
+) -> UdimSets {
+    let sets = udim_sets(def, path);
+    if sets.is_empty() {
+        return UdimSets::default();
+    }
+
+    let udimset = def.udimset();
+    let mut tiles = HashMap::new();
+    for set in sets {
+        let pattern = set.to_string();
+        let mut found = Vec::new();
+        let candidates = match &udimset {
+            Some(udims) => udims.clone(),
+            None => Udim::candidates(MAX_ROWS).collect(),
+        };
+        for udim in candidates {
+            let tile = AssetPath::parse(&udim.filename(&pattern)).into_owned();
+            let image = loader
+                .loader()
+                .with_settings(|settings: &mut ImageLoaderSettings| settings.is_srgb = false)
+                .immediate()
+                .load::<Image>(&tile)
+                .await;
+            match image {
+                Ok(image) => found.push((udim, image.take())),
+                Err(e) if udimset.is_some() => warn!(%tile, "Failed to load UDIM tile: {e}"),
+                Err(_) => {}
+            }
+        }
+        if found.is_empty() {
+            warn!(path = %set, "No tiles of UDIM set found");
+        }
+        tiles.insert(set, found);
+    }
+
+    let udims = match udimset {
+        Some(udims) => udims,
+        None => {
+            let mut udims = tiles
+                .values()
+                .flatten()
+                .map(|(udim, _)| *udim)
+                .collect::<Vec<_>>();
+            udims.sort();
+            udims.dedup();
+            def.add_udimset("udimset_found", &udims);
+            udims
+        }
+    };
+    let (columns, rows) = grid(&udims);
+    UdimSets {
+        tiles,
+        columns,
+        rows,
+    }
+}
+
+/// The files of the UDIM sets read by image nodes of `def`
+fn udim_sets(def: &MaterialX, path: &AssetPath<'_>) -> HashSet<AssetPath<'static>> {
+    let nodes = def.elements.values().flat_map(|element| {
+        let children = (element.tag == "nodegraph").then_some(element);
+        children
+            .into_iter()
+            .flat_map(|graph| graph.children.values().map(move |e| (e, Some(graph))))
+            .chain([(element, None)])
+    });
+    let mut sets = HashSet::new();
+    for (element, parent) in nodes {
+        if !matches!(element.tag.as_str(), "image" | "tiledimage") {
+            continue;
+        }
+        let Ok(ResolvedInput::Value(DataTypeAndValue::Filename(file))) =
+            def.resolve(element, parent, "file".into())
+        else {
+            continue;
+        };
+        if !has_udim(&file) {
+            continue;
+        }
+        if let Ok(file) = path.resolve_embed(&file) {
+            sets.insert(file);
+        }
+    }
+    sets
+}
+
+/// Copy `tiles` into a `columns`×`rows` grid of cells as large as the
+/// largest tile, reading smaller tiles at the nearest texel
+///
+/// Cells are scaled down to keep the atlas within `max` texels along both
+/// axes.
+fn atlas(
+    path: &AssetPath<'_>,
+    tiles: &[(Udim, Image)],
+    (columns, rows): (u32, u32),
+    max: u32,
+) -> (u32, u32, Vec<u8>) {
+    let (mut cell_width, mut cell_height) = tiles.iter().fold((1, 1), |(w, h), (_, image)| {
+        (w.max(image.width()), h.max(image.height()))
+    });
+    if cell_width * columns > max || cell_height * rows > max {
+        let (width, height) = (cell_width, cell_height);
+        cell_width = (max / columns).clamp(1, cell_width);
+        cell_height = (max / rows).clamp(1, cell_height);
+        warn!(
+            %path,
+            "Scaling UDIM tiles down from {width}x{height} to {cell_width}x{cell_height} \
+             to fit a {columns}x{rows} atlas into {max}x{max} texels"
+        );
+    }
+    let (width, height) = (cell_width * columns, cell_height * rows);
+    let mut data = vec![0; (width * height * 4) as usize];
+    for (udim, image) in tiles {
+        let (u, v) = udim.tile();
+        let (left, top) = (u * cell_width, (rows - 1 - v) * cell_height);
+        for y in 0..cell_height {
+            for x in 0..cell_width {
+                let source_x = x * image.width() / cell_width;
+                let source_y = y * image.height() / cell_height;
+                let Ok(color) = image.get_color_at(source_x, source_y) else {
+                    continue;
+                };
+                let texel = LinearRgba::from(color)
+                    .to_f32_array()
+                    .map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+                let i = (((top + y) * width + left + x) * 4) as usize;
+                data[i..i + 4].copy_from_slice(&texel);
+            }
+        }
+    }
+    (width, height, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(number: u32, value: u8) -> (Udim, Image) {
+        let image = Image::new_fill(
+            Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[value, value, value, 255],
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::default(),
+        );
+        (Udim::new(number).unwrap(), image)
+    }
+
+    #[test]
+    fn atlas_limit() {
+        let tiles = [tile(1001, 10), tile(1002, 20), tile(1011, 30)];
+        let path = AssetPath::from("tile.<UDIM>.png");
+        let (width, height, _) = atlas(&path, &tiles, (2, 2), 16);
+        assert_eq!((width, height), (8, 8));
+
+        // Cells of 2x2 texels, the first row of tiles at the bottom
+        let (width, height, data) = atlas(&path, &tiles, (2, 2), 4);
+        assert_eq!((width, height), (4, 4));
+        let red = data.chunks(4).map(|texel| texel[0]).collect::<Vec<_>>();
+        #[rustfmt::skip]
+        assert_eq!(red, [
+            30, 30, 0, 0,
+            30, 30, 0, 0,
+            10, 10, 20, 20,
+            10, 10, 20, 20,
+        ]);
+    }
+}
@@ -125,9 +125,9 @@ fn spawn_balls(
                 Ball {
                     name,
                     label: asset
-                        .material_name
-                        .as_ref()
-                        .map(|x| x.to_string())
+                        .materials
+                        .first()
+                        .map(|x| x.name.to_string())
                         .unwrap_or_default(),
                 },
                 Transform::from_translation(position),
@@ -5,7 +5,7 @@ use std::{
 
 use anyhow::{Context as _, Result};
 use bevy::{prelude::*, utils::HashMap};
-use bevy_materialx_importer::{MaterialX, MaterialXPlugin};
+use bevy_materialx_importer::{BakeSettings, MaterialX, MaterialXLoaderSettings, MaterialXPlugin};
 
 pub struct LoadFilesPlugin;
 
@@ -13,7 +13,7 @@ impl Plugin for LoadFilesPlugin {
     fn build(&self, app: &mut App) {
         let filter = MaterialFilter(std::env::args().nth(1));
 
-        app.add_plugins((MaterialXPlugin,))
+        app.add_plugins((MaterialXPlugin::default(),))
             .insert_resource(filter)
             .register_type::<ExampleFiles>()
             .add_systems(Startup, (load_example_files,));
@@ -75,7 +75,12 @@ fn load_example_files(
         res.insert(
             name,
             MaterialAsset {
-                material: assets.load(path.strip_prefix("assets").unwrap().to_path_buf()),
+                material: assets.load_with_settings(
+                    path.strip_prefix("assets").unwrap().to_path_buf(),
+                    |settings: &mut MaterialXLoaderSettings| {
+                        settings.bake = Some(BakeSettings::default());
+                    },
+                ),
                 meta,
                 path,
             },
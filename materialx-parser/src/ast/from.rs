@@ -3,80 +3,217 @@ use indexmap::IndexMap;
 use roxmltree::Document;
 use smol_str::SmolStr;
 
-use super::{meta::VersionError, AstError, Element};
+use super::{
+    include::IncludeError,
+    location::{Location, SourceMap},
+    meta::VersionError,
+    raw::{RawDocument, RawElement, RawNode},
+    AstError, Comment, Element, Include,
+};
 
 impl<'xml> TryFrom<Document<'xml>> for MaterialX {
     type Error = Error;
 
     fn try_from(ast: Document) -> Result<Self, Self::Error> {
-        if !ast.root_element().has_children() {
-            return Err(Error::Empty);
-        }
+        parse_document(&ast, None, &mut |_| Err(Error::IncludesNotSupported))
+    }
+}
 
-        let element = ast.root_element();
-        let mut res = MaterialX {
-            version: element
-                .attribute("version")
-                .ok_or(AstError::InvalidVersion(VersionError::NoVersion))?
-                .parse()
-                .map_err(AstError::InvalidVersion)?,
-            colorspace: element.attribute("colorspace").map(|s| s.parse().unwrap()),
-            elements: IndexMap::new(),
-        };
+impl TryFrom<&RawDocument> for MaterialX {
+    type Error = Error;
+
+    /// Keep the elements of `raw` by name
+    ///
+    /// Fails with [`Error::IncludesNotSupported`] on include elements, like
+    /// parsing a string; use [`MaterialX::load_with_resolver`] for those.
+    fn try_from(raw: &RawDocument) -> Result<Self, Self::Error> {
+        from_raw(raw, &mut |_| Err(Error::IncludesNotSupported))
+    }
+}
+
+/// Build a [`MaterialX`] from a parsed XML document
+///
+/// `source_file` is recorded in the [`Location`] of every element. Each
+/// `include` element is handed to `include` with its `href`, and the elements
+/// of the returned document are merged in. Elements of the including document
+/// take precedence.
+pub(super) fn parse_document(
+    ast: &Document,
+    source_file: Option<&SmolStr>,
+    include: &mut dyn FnMut(&str) -> Result<MaterialX, Error>,
+) -> Result<MaterialX, Error> {
+    if !ast.root_element().has_children() {
+        return Err(Error::Empty);
+    }
+    from_raw(&RawDocument::from_document(ast, source_file), include)
+}
+
+fn from_raw(
+    raw: &RawDocument,
+    include: &mut dyn FnMut(&str) -> Result<MaterialX, Error>,
+) -> Result<MaterialX, Error> {
+    let root = &raw.root;
+    let mut res = MaterialX {
+        version: root
+            .attribute("version")
+            .ok_or(AstError::InvalidVersion(VersionError::NoVersion))?
+            .parse()
+            .map_err(AstError::InvalidVersion)?,
+        colorspace: root.attribute("colorspace").map(|s| s.parse().unwrap()),
+        attributes: attributes(root),
+        elements: IndexMap::new(),
+        comments: Vec::new(),
+        includes: Vec::new(),
+        duplicates: Vec::new(),
+    };
 
-        let mut children = IndexMap::new();
-        for (index, child) in element.children().enumerate() {
-            if !child.is_element() {
+    let mut children = IndexMap::new();
+    for child in &root.children {
+        let child = match child {
+            RawNode::Comment(text) => {
+                res.comments.push(Comment {
+                    position: children.len(),
+                    text: text.clone(),
+                });
                 continue;
             }
-            if child.tag_name().name() == "include" {
-                return Err(Error::IncludesNotSupported);
+            RawNode::Element(child) => child,
+        };
+        if child.tag.rsplit(':').next() == Some("include") {
+            let href = child.attribute("href").ok_or(IncludeError::NoHref)?;
+            let included = include(href)?;
+            let mut names = Vec::new();
+            let position = children.len();
+            for (name, element) in included.elements {
+                if !children.contains_key(&name) {
+                    names.push(name.clone());
+                    children.insert(name, element);
+                }
             }
-
-            let child: Element = child.try_into().map_err(|e| AstError::Build {
-                parent: MaterialX::NAME,
-                index,
-                source: Box::new(e),
-            })?;
-            children.insert(child.name.clone(), child);
+            res.includes.push(Include {
+                position,
+                tag: child.tag.clone(),
+                href: href.clone(),
+                elements: names,
+            });
+            res.duplicates.extend(included.duplicates);
+            continue;
         }
-        res.elements = children;
 
-        Ok(res)
+        let child = element_from_raw(child, &mut res.duplicates);
+        // Elements of the including document replace included ones, which
+        // aren't duplicates
+        let mut included = false;
+        for include in &mut res.includes {
+            let len = include.elements.len();
+            include.elements.retain(|name| *name != child.name);
+            included |= include.elements.len() != len;
+        }
+        if included {
+            if let Some((index, _, _)) = children.shift_remove_full(&child.name) {
+                let positions = res.comments.iter_mut().map(|c| &mut c.position);
+                let positions = positions.chain(res.includes.iter_mut().map(|i| &mut i.position));
+                positions.filter(|p| **p > index).for_each(|p| *p -= 1);
+            }
+        }
+        insert(&mut children, child, &mut res.duplicates);
     }
+    res.elements = children;
+
+    Ok(res)
 }
 
 impl<'node, 'xml> TryFrom<roxmltree::Node<'node, 'xml>> for Element {
     type Error = AstError;
 
     fn try_from(node: roxmltree::Node) -> Result<Self, Self::Error> {
-        let tag = node.tag_name().name().into();
-        let name: SmolStr = node
-            .attribute("name")
-            .ok_or_else(|| AstError::NoName)?
-            .into();
-
-        let mut children = IndexMap::new();
-        for (index, child) in node.children().enumerate() {
-            if !child.is_element() {
-                continue;
+        let source = SourceMap::new(node.document().input_text(), None);
+        let raw = RawElement::from_node(node, &source);
+        Ok(element_from_raw(&raw, &mut Vec::new()))
+    }
+}
+
+fn element_from_raw(raw: &RawElement, duplicates: &mut Vec<Location>) -> Element {
+    let mut children = IndexMap::new();
+    let mut comments = Vec::new();
+    for child in &raw.children {
+        match child {
+            RawNode::Comment(text) => comments.push(Comment {
+                position: children.len(),
+                text: text.clone(),
+            }),
+            RawNode::Element(child) => {
+                let child = element_from_raw(child, duplicates);
+                insert(&mut children, child, duplicates);
             }
-            let child_element: Element = child.try_into().map_err(|e| AstError::Build {
-                parent: name.clone(),
-                index,
-                source: Box::new(e),
-            })?;
-            children.insert(child_element.name.clone(), child_element);
         }
+    }
+
+    Element {
+        tag: raw.tag.clone(),
+        // Unnamed elements are named when inserted into their parent
+        name: raw.name.clone().unwrap_or_default(),
+        attributes: attributes(raw),
+        children,
+        location: raw.location.clone(),
+        comments,
+    }
+}
+
+/// Namespace declarations of `raw` followed by its attributes
+fn attributes(raw: &RawElement) -> IndexMap<SmolStr, SmolStr> {
+    let namespaces = raw.namespaces.iter().map(|(prefix, uri)| {
+        let name = match prefix {
+            Some(prefix) => format!("xmlns:{prefix}").into(),
+            None => "xmlns".into(),
+        };
+        (name, uri.clone())
+    });
+    namespaces.chain(raw.attributes.iter().cloned()).collect()
+}
+
+/// Insert `element` into `children`
+///
+/// Unnamed elements get a name that MaterialX names can't clash with, like
+/// `output~3`. A named element replaces an earlier sibling with the same
+/// name, whose location is recorded in `duplicates`.
+fn insert(
+    children: &mut IndexMap<SmolStr, Element>,
+    mut element: Element,
+    duplicates: &mut Vec<Location>,
+) {
+    if element.name.is_empty() {
+        element.name = format!("{}~{}", element.tag, children.len()).into();
+    } else if children.contains_key(&element.name) {
+        duplicates.push(element.location.clone());
+    }
+    children.insert(element.name.clone(), element);
+}
+
+/// Position of the start tag of `node`, and the names of it and its ancestors
+/// below the `materialx` root (or the tag, for elements without a name)
+pub(super) fn location(node: roxmltree::Node, source: &SourceMap) -> Location {
+    let mut names = node
+        .ancestors()
+        .filter(|n| n.is_element() && n.parent().is_some_and(|p| p.is_element()))
+        .map(|n| n.attribute("name").unwrap_or(n.tag_name().name()))
+        .collect::<Vec<_>>();
+    names.reverse();
+    Location {
+        file: source.file.cloned(),
+        span: Some(source.span(node.range().start)),
+        path: names.join("/").into(),
+    }
+}
 
-        Ok(Element {
-            tag,
-            name,
-            attributes: node
-                .attributes()
-                .map(|a| (a.name().into(), a.value().into()))
-                .collect(),
-            children,
-        })
+/// `name` with the prefix bound to `namespace` at `node`, if any
+pub(super) fn qualified_name(
+    node: roxmltree::Node,
+    namespace: Option<&str>,
+    name: &str,
+) -> SmolStr {
+    match namespace.and_then(|ns| node.lookup_prefix(ns)) {
+        Some(prefix) if !prefix.is_empty() => format!("{prefix}:{name}").into(),
+        _ => name.into(),
     }
 }
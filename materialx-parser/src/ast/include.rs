@@ -0,0 +1,300 @@
+//! Resolving `<xi:include>` elements
+//!
+//! MaterialX documents can pull in other documents using XInclude:
+//!
+//! ```xml
+//! <materialx version="1.39" xmlns:xi="http://www.w3.org/2001/XInclude">
+//!   <xi:include href="standard_surface_brass_tiled.mtlx" />
+//! </materialx>
+//! ```
+//!
+//! Parsing a string with [`FromStr`](std::str::FromStr) doesn't know where to
+//! look for these files, so it fails with [`Error::IncludesNotSupported`].
+//! Use [`MaterialX::load_with_resolver`] with a [`FileResolver`] instead.
+
+use super::{from::parse_document, MaterialX};
+use crate::Error;
+use roxmltree::Document;
+use smol_str::SmolStr;
+use std::path::{Component, Path, PathBuf};
+
+/// Locates and reads files referenced by `include` elements
+pub trait FileResolver {
+    /// Resolve `href` as seen from the file `base` (or from nowhere, for the
+    /// initial document)
+    ///
+    /// The returned identifier is used to read the file and to detect include
+    /// cycles, so the same file should always resolve to the same identifier.
+    fn resolve(&self, base: Option<&str>, href: &str) -> Result<SmolStr, std::io::Error>;
+
+    /// Read the contents of a previously resolved file
+    fn read(&self, path: &str) -> Result<String, std::io::Error>;
+}
+
+/// Resolves includes relative to the including file on the local file system
+#[derive(Debug, Clone, Default)]
+pub struct FsResolver;
+
+impl FileResolver for FsResolver {
+    fn resolve(&self, base: Option<&str>, href: &str) -> Result<SmolStr, std::io::Error> {
+        let path = match base.and_then(|base| Path::new(base).parent()) {
+            Some(dir) => dir.join(href),
+            None => PathBuf::from(href),
+        };
+        Ok(normalize(&path).to_string_lossy().into())
+    }
+
+    fn read(&self, path: &str) -> Result<String, std::io::Error> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Lexically remove `.` and `..` segments so that cycles are detected even
+/// when files refer to each other through different relative paths
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !res.pop() {
+                    res.push("..");
+                }
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum IncludeError {
+    #[error("Include element has no `href` attribute")]
+    NoHref,
+    #[error("Failed to resolve include `{href}`: {source}")]
+    Resolve {
+        href: SmolStr,
+        source: std::io::Error,
+    },
+    #[error("Failed to read included file `{path}`: {source}")]
+    Read {
+        path: SmolStr,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse included file `{path}`")]
+    Parse { path: SmolStr, source: Box<Error> },
+    #[error("Include cycle detected: {}", chain.join(" -> "))]
+    Cycle { chain: Vec<SmolStr> },
+}
+
+impl MaterialX {
+    /// Load a document and recursively resolve all its includes
+    ///
+    /// Elements from included files are merged into [`MaterialX::elements`].
+    /// Elements of the including document take precedence over included ones
+    /// with the same name, and earlier includes over later ones.
+    /// Every element records the file it was read from in
+    /// its [`Location`](super::Location).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use materialx_parser::{ast::FsResolver, MaterialX};
+    ///
+    /// let mat = MaterialX::load_with_resolver(
+    ///     "../assets/materialx-examples/StandardSurface/standard_surface_look_brass_tiled.mtlx",
+    ///     &FsResolver,
+    /// )?;
+    /// assert!(mat.element("Tiled_Brass").is_ok());
+    /// # Ok::<(), materialx_parser::Error>(())
+    /// ```
+    pub fn load_with_resolver(path: &str, resolver: &impl FileResolver) -> Result<Self, Error> {
+        let path = resolver
+            .resolve(None, path)
+            .map_err(|source| IncludeError::Resolve {
+                href: path.into(),
+                source,
+            })?;
+        load(path, resolver, &mut Vec::new())
+    }
+}
+
+fn load(
+    path: SmolStr,
+    resolver: &impl FileResolver,
+    stack: &mut Vec<SmolStr>,
+) -> Result<MaterialX, Error> {
+    if stack.contains(&path) {
+        let mut chain = stack.clone();
+        chain.push(path);
+        return Err(IncludeError::Cycle { chain }.into());
+    }
+
+    let xml = resolver.read(&path).map_err(|source| IncludeError::Read {
+        path: path.clone(),
+        source,
+    })?;
+    let doc = Document::parse(&xml)?;
+
+    stack.push(path.clone());
+    let res = parse_document(&doc, Some(&path), &mut |href| {
+        let included =
+            resolver
+                .resolve(Some(&path), href)
+                .map_err(|source| IncludeError::Resolve {
+                    href: href.into(),
+                    source,
+                })?;
+        load(included.clone(), resolver, stack).map_err(|e| match e {
+            // Don't wrap cycles so the full chain is reported at the top
+            e @ Error::Include(IncludeError::Cycle { .. }) => e,
+            e => IncludeError::Parse {
+                path: included,
+                source: Box::new(e),
+            }
+            .into(),
+        })
+    });
+    stack.pop();
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::Code;
+    use std::collections::HashMap;
+
+    struct Memory(HashMap<&'static str, &'static str>);
+
+    impl FileResolver for Memory {
+        fn resolve(&self, _base: Option<&str>, href: &str) -> Result<SmolStr, std::io::Error> {
+            Ok(href.into())
+        }
+
+        fn read(&self, path: &str) -> Result<String, std::io::Error> {
+            self.0
+                .get(path)
+                .map(|s| s.to_string())
+                .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    #[test]
+    fn brass_look() {
+        let mat = MaterialX::load_with_resolver(
+            "../assets/materialx-examples/StandardSurface/standard_surface_look_brass_tiled.mtlx",
+            &FsResolver,
+        )
+        .unwrap();
+
+        let brass = mat.element("Tiled_Brass").unwrap();
+        assert!(brass
+            .location
+            .file
+            .as_deref()
+            .unwrap()
+            .ends_with("standard_surface_brass_tiled.mtlx"));
+        let look = mat.element("Brass_Look").unwrap();
+        assert!(look
+            .location
+            .file
+            .as_deref()
+            .unwrap()
+            .ends_with("standard_surface_look_brass_tiled.mtlx"));
+        assert!(mat.element("Greysphere_Calibration").is_ok());
+    }
+
+    #[test]
+    fn cycle() {
+        let resolver = Memory(HashMap::from([
+            (
+                "a.mtlx",
+                r#"<materialx version="1.39"><xi:include href="b.mtlx" xmlns:xi="http://www.w3.org/2001/XInclude" /></materialx>"#,
+            ),
+            (
+                "b.mtlx",
+                r#"<materialx version="1.39"><xi:include href="a.mtlx" xmlns:xi="http://www.w3.org/2001/XInclude" /></materialx>"#,
+            ),
+        ]));
+        let err = MaterialX::load_with_resolver("a.mtlx", &resolver).unwrap_err();
+        let Error::Include(IncludeError::Cycle { chain }) = err else {
+            panic!("expected cycle, got {err:?}");
+        };
+        assert_eq!(chain, ["a.mtlx", "b.mtlx", "a.mtlx"]);
+    }
+
+    #[test]
+    fn first_definition_wins() {
+        let resolver = Memory(HashMap::from([
+            (
+                "main.mtlx",
+                r#"<materialx version="1.39">
+                    <constant name="c" type="float"><input name="value" type="float" value="1" /></constant>
+                    <include href="lib.mtlx" />
+                </materialx>"#,
+            ),
+            (
+                "lib.mtlx",
+                r#"<materialx version="1.39">
+                    <constant name="c" type="float"><input name="value" type="float" value="2" /></constant>
+                    <constant name="d" type="float"><input name="value" type="float" value="3" /></constant>
+                </materialx>"#,
+            ),
+        ]));
+        let mat = MaterialX::load_with_resolver("main.mtlx", &resolver).unwrap();
+        let c = mat.element("c").unwrap();
+        assert_eq!(c.location.file.as_deref(), Some("main.mtlx"));
+        let d = mat.element("d").unwrap();
+        assert_eq!(d.location.file.as_deref(), Some("lib.mtlx"));
+    }
+
+    #[test]
+    fn override_is_not_duplicate() {
+        let resolver = Memory(HashMap::from([
+            (
+                "main.mtlx",
+                r#"<materialx version="1.39">
+                    <include href="lib.mtlx" />
+                    <constant name="c" type="float"><input name="value" type="float" value="1" /></constant>
+                    <constant name="e" type="float" />
+                    <constant name="e" type="float" />
+                </materialx>"#,
+            ),
+            (
+                "lib.mtlx",
+                r#"<materialx version="1.39">
+                    <constant name="c" type="float"><input name="value" type="float" value="2" /></constant>
+                    <constant name="d" type="float"><input name="value" type="float" value="3" /></constant>
+                </materialx>"#,
+            ),
+        ]));
+        let mat = MaterialX::load_with_resolver("main.mtlx", &resolver).unwrap();
+        let c = mat.element("c").unwrap();
+        assert_eq!(c.location.file.as_deref(), Some("main.mtlx"));
+        assert_eq!(mat.includes[0].elements, ["d"]);
+        assert_eq!(mat.elements.keys().collect::<Vec<_>>(), ["d", "c", "e"]);
+
+        let duplicates = mat
+            .validate()
+            .into_iter()
+            .filter(|d| d.code == Code::DuplicateName)
+            .map(|d| d.location.path.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(duplicates, ["e"]);
+    }
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(
+            FsResolver.resolve(Some("a/b/c.mtlx"), "../d.mtlx").unwrap(),
+            "a/d.mtlx"
+        );
+        assert_eq!(
+            FsResolver.resolve(Some("c.mtlx"), "./d.mtlx").unwrap(),
+            "d.mtlx"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use smol_str::SmolStr;
+use std::fmt;
+
+/// Position of an element's start tag in its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
+pub struct Span {
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column number
+    pub column: u32,
+}
+
+/// Where an element is defined
+///
+/// Displayed as `file:line:col (path)`, leaving out the parts that are
+/// unknown (e.g. for documents created in code).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
+pub struct Location {
+    /// File the element was read from, if it was loaded using a
+    /// [`FileResolver`](super::FileResolver)
+    pub file: Option<SmolStr>,
+    pub span: Option<Span>,
+    /// Names of the element and its ancestors, e.g.
+    /// `NG_BrickPattern/node_add_16/in1`
+    pub path: SmolStr,
+}
+
+impl Location {
+    /// Location of a child element created without a position (e.g. when
+    /// filling in default inputs)
+    pub fn child(&self, name: &str) -> Location {
+        Location {
+            file: self.file.clone(),
+            span: None,
+            path: format!("{}/{name}", self.path).into(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, &self.span) {
+            (Some(file), Some(span)) => write!(f, "{file}:{}:{} ", span.line, span.column)?,
+            (None, Some(span)) => write!(f, "{}:{} ", span.line, span.column)?,
+            (Some(file), None) => write!(f, "{file} ")?,
+            (None, None) => {}
+        }
+        write!(f, "({})", self.path)
+    }
+}
+
+/// Line and column lookup for the elements of one file
+///
+/// The line starts are found once per file, so finding the span of every
+/// element doesn't scan all the text before it.
+pub(super) struct SourceMap<'a> {
+    text: &'a str,
+    pub file: Option<&'a SmolStr>,
+    /// Byte offsets of the first character of each line
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(text: &'a str, file: Option<&'a SmolStr>) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceMap {
+            text,
+            file,
+            line_starts,
+        }
+    }
+
+    /// Line and column of the byte `offset`, counting columns in characters
+    pub fn span(&self, offset: usize) -> Span {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let start = self.line_starts[line - 1];
+        let column = self.text[start..offset].chars().count() + 1;
+        Span {
+            line: line as u32,
+            column: column as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceMap;
+    use crate::{nodes::AccessError, MaterialX};
+    use std::str::FromStr as _;
+
+    const XML: &str = r#"<?xml version="1.0"?>
+<materialx version="1.39">
+  <nodegraph name="NG">
+    <multiply name="mul" type="float">
+      <input name="in1" type="float" value="zero" />
+    </multiply>
+  </nodegraph>
+</materialx>"#;
+
+    #[test]
+    fn element_locations() {
+        let mat = MaterialX::from_str(XML).unwrap();
+        let graph = mat.element("NG").unwrap();
+        let input = &graph.children["mul"].children["in1"];
+        assert_eq!(input.location.path, "NG/mul/in1");
+        let span = input.location.span.unwrap();
+        assert_eq!((span.line, span.column), (5, 7));
+        assert_eq!(input.location.to_string(), "5:7 (NG/mul/in1)");
+    }
+
+    #[test]
+    fn spans_match_roxmltree() {
+        let xml = std::fs::read_to_string(
+            "../assets/materialx-examples/StandardSurface/standard_surface_chess_set.mtlx",
+        )
+        .unwrap();
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let source = SourceMap::new(&xml, None);
+        for node in doc.descendants().filter(|n| n.is_element()) {
+            let pos = doc.text_pos_at(node.range().start);
+            let span = source.span(node.range().start);
+            assert_eq!((span.line, span.column), (pos.row, pos.col));
+        }
+    }
+
+    #[test]
+    fn error_locations() {
+        let mat = MaterialX::from_str(XML).unwrap();
+        let graph = mat.element("NG").unwrap();
+        let err = mat
+            .resolve(&graph.children["mul"], Some(graph), "in1".into())
+            .unwrap_err();
+        assert!(matches!(err, AccessError::ValueParseError { .. }));
+        assert!(err.to_string().starts_with("5:7 (NG/mul/in1): "), "{err}");
+
+        // Unnamed elements are kept under a name derived from their tag
+        let mat = MaterialX::from_str(
+            r#"<materialx version="1.39">
+  <nodegraph name="NG">
+    <output type="float" />
+  </nodegraph>
+</materialx>"#,
+        )
+        .unwrap();
+        let output = &mat.element("NG").unwrap().children["output~0"];
+        assert_eq!(output.name, "output~0");
+        assert_eq!(output.location.to_string(), "3:5 (NG/output)");
+    }
+}
@@ -1,4 +1,4 @@
-use std::{num::ParseIntError, str::FromStr};
+use std::{fmt, num::ParseIntError, str::FromStr};
 
 use smol_str::SmolStr;
 
@@ -35,6 +35,12 @@ impl FromStr for Version {
     }
 }
 
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum VersionError {
@@ -49,7 +55,7 @@ pub enum VersionError {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
 pub enum ColorSpace {
     SrgbTexture,
@@ -92,3 +98,30 @@ impl FromStr for ColorSpace {
         }
     }
 }
+
+impl ColorSpace {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ColorSpace::SrgbTexture => "srgb_texture",
+            ColorSpace::LinRec709 => "lin_rec709",
+            ColorSpace::G22Rec709 => "g22_rec709",
+            ColorSpace::G18Rec709 => "g18_rec709",
+            ColorSpace::AcesCG => "acescg",
+            ColorSpace::LinAp1 => "lin_ap1",
+            ColorSpace::G22Ap1 => "g22_ap1",
+            ColorSpace::G18Ap1 => "g18_ap1",
+            ColorSpace::LinSrgb => "lin_srgb",
+            ColorSpace::AdobeRGB => "adobergb",
+            ColorSpace::LinAdobeRGB => "lin_adobergb",
+            ColorSpace::SrgbDisplayP3 => "srgb_displayp3",
+            ColorSpace::LinDisplayP3 => "lin_displayp3",
+            ColorSpace::Unknown(s) => s,
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -4,20 +4,51 @@ use smol_str::SmolStr;
 use std::str::FromStr;
 
 mod from;
+mod include;
+mod location;
 mod meta;
+mod raw;
+mod write;
+pub use include::{FileResolver, FsResolver, IncludeError};
+pub use location::{Location, Span};
 pub use meta::{ColorSpace, Version};
+pub use raw::{RawDocument, RawElement, RawNode};
 
 #[derive(Debug)]
 #[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
 pub struct MaterialX {
     pub version: Version,
     pub colorspace: Option<ColorSpace>,
+    /// Attributes of the root element in document order, after its namespace
+    /// declarations (e.g. `xmlns:xi` or `fileprefix`)
+    ///
+    /// Parsed documents also list `version` and `colorspace` here to keep
+    /// their position when written, but their values are taken from the
+    /// fields above.
+    #[cfg_attr(feature = "bevy", reflect(ignore))]
+    // FIXME: bevy_reflect doesn't support IndexMap
+    pub attributes: IndexMap<SmolStr, SmolStr>,
     #[cfg_attr(feature = "bevy", reflect(ignore))] // FIXME: bevy_reflect doesn't support IndexMap
     pub elements: IndexMap<SmolStr, Element>,
+    /// Comments between the elements
+    pub comments: Vec<Comment>,
+    /// Include elements, see [`MaterialX::load_with_resolver`]
+    pub includes: Vec<Include>,
+    /// Elements that had the same name as an earlier sibling and replaced it
+    /// while parsing, reported by [`MaterialX::validate`]
+    pub duplicates: Vec<Location>,
 }
 
 impl MaterialX {
     pub(crate) const NAME: SmolStr = SmolStr::new_inline("<root>");
+
+    /// Location used for errors about the document root
+    pub(crate) fn location() -> Location {
+        Location {
+            path: Self::NAME,
+            ..Location::default()
+        }
+    }
 }
 
 impl FromStr for MaterialX {
@@ -32,6 +63,8 @@ impl FromStr for MaterialX {
 #[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
 pub struct Element {
     pub tag: SmolStr,
+    /// The `name` attribute, or `{tag}~{index}` for unnamed elements like
+    /// `output~0`, which can't clash with valid MaterialX names
     pub name: SmolStr,
     #[cfg_attr(feature = "bevy", reflect(ignore))]
     // FIXME: bevy_reflect doesn't support IndexMap -- also it'd be recursive
@@ -39,19 +72,40 @@ pub struct Element {
     #[cfg_attr(feature = "bevy", reflect(ignore))]
     // FIXME: bevy_reflect doesn't support IndexMap -- also it'd be recursive
     pub children: IndexMap<SmolStr, Element>,
+    /// Where the element is defined, for error messages
+    pub location: Location,
+    /// Comments between the child elements
+    pub comments: Vec<Comment>,
+}
+
+/// A comment, kept to write documents back with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
+pub struct Comment {
+    /// Number of child elements before the comment
+    pub position: usize,
+    pub text: SmolStr,
+}
+
+/// An `xi:include` element
+///
+/// The elements it pulled in are part of [`MaterialX::elements`], but written
+/// back as the include element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "bevy", derive(bevy_reflect::Reflect))]
+pub struct Include {
+    /// Number of elements before the include element
+    pub position: usize,
+    /// Tag including its namespace prefix, e.g. `xi:include`
+    pub tag: SmolStr,
+    pub href: SmolStr,
+    /// Names of the elements added by the include
+    pub elements: Vec<SmolStr>,
 }
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum AstError {
-    #[error("Failed to build Ast")]
-    Build {
-        parent: SmolStr,
-        index: usize,
-        source: Box<AstError>,
-    },
-    #[error("No name attribute found")]
-    NoName,
     #[error("Invalid version attribute on materialx element")]
     InvalidVersion(#[from] meta::VersionError),
 }
@@ -0,0 +1,492 @@
+//! Lossless view of a `.mtlx` file
+//!
+//! [`MaterialX`](super::MaterialX) keys elements by name, so it can't
+//! represent unnamed elements or several siblings with the same name.
+//! [`RawDocument`] keeps every element in document order together with
+//! comments and namespace declarations (e.g. `xmlns:xi`), and looks up
+//! elements by name through a separate index. It's meant for tools that need
+//! to inspect or rewrite files without losing anything, like linters and
+//! formatters.
+//!
+//! Converting to [`MaterialX`] keeps the last of several siblings with the
+//! same name, converting back writes its comments and include elements in
+//! place.
+
+use super::{
+    from::{location, qualified_name},
+    location::SourceMap,
+    write::{write_attribute, INDENT},
+    Element, Location, MaterialX,
+};
+use crate::validate::{Code, Diagnostic};
+use indexmap::IndexMap;
+use roxmltree::Document;
+use smol_str::SmolStr;
+use std::io::{self, Write};
+
+/// A parsed `.mtlx` file, with all elements and comments in document order
+///
+/// # Examples
+///
+/// ```
+/// use materialx_parser::ast::RawDocument;
+///
+/// let doc = RawDocument::parse(r#"<materialx version="1.39" xmlns:xi="http://www.w3.org/2001/XInclude">
+///   <!-- shared definitions -->
+///   <xi:include href="lib.mtlx" />
+///   <constant name="c" type="float" />
+///   <constant name="c" type="color3" />
+/// </materialx>"#)?;
+/// assert_eq!(doc.root.elements().next().unwrap().tag, "xi:include");
+/// assert_eq!(doc.root.children_named("c").count(), 2);
+/// assert_eq!(doc.validate().len(), 1);
+/// # Ok::<(), materialx_parser::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RawDocument {
+    /// The `materialx` element
+    pub root: RawElement,
+    /// Comments before and after the root element
+    pub prolog: Vec<SmolStr>,
+    pub epilog: Vec<SmolStr>,
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // Comments are rare, no need to box elements
+pub enum RawNode {
+    Element(RawElement),
+    Comment(SmolStr),
+}
+
+#[derive(Debug, Clone)]
+pub struct RawElement {
+    /// Tag including its namespace prefix, e.g. `xi:include`
+    pub tag: SmolStr,
+    pub name: Option<SmolStr>,
+    /// Namespace declarations on this element, as `(prefix, uri)`
+    /// (the prefix is `None` for a default namespace)
+    pub namespaces: Vec<(Option<SmolStr>, SmolStr)>,
+    /// Attributes in document order, with their namespace prefixes
+    pub attributes: Vec<(SmolStr, SmolStr)>,
+    pub children: Vec<RawNode>,
+    pub location: Location,
+    /// Indices into `children` of the named elements, by name
+    index: IndexMap<SmolStr, Vec<usize>>,
+}
+
+impl RawDocument {
+    pub fn parse(xml: &str) -> Result<Self, crate::Error> {
+        let doc = Document::parse(xml)?;
+        Ok(Self::from_document(&doc, None))
+    }
+
+    /// Build from a parsed XML document, recording `file` in all locations
+    pub fn from_document(doc: &Document, file: Option<&SmolStr>) -> Self {
+        let root = doc.root_element();
+        let mut prolog = comments(root.prev_siblings().skip(1));
+        prolog.reverse();
+        RawDocument {
+            root: RawElement::from_node(root, &SourceMap::new(doc.input_text(), file)),
+            prolog,
+            epilog: comments(root.next_siblings().skip(1)),
+        }
+    }
+
+    /// Report elements that have the same name as an earlier sibling
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+        self.root.check_duplicates(&mut res);
+        res
+    }
+
+    /// Write the document as XML, including comments
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        writeln!(w, r#"<?xml version="1.0"?>"#)?;
+        for comment in &self.prolog {
+            writeln!(w, "<!--{comment}-->")?;
+        }
+        self.root.write_to(&mut w, 0)?;
+        for comment in &self.epilog {
+            writeln!(w, "<!--{comment}-->")?;
+        }
+        Ok(())
+    }
+
+    pub fn to_xml_string(&self) -> String {
+        let mut res = Vec::new();
+        self.write_to(&mut res)
+            .expect("writing to a Vec never fails");
+        String::from_utf8(res).expect("only valid UTF-8 is written")
+    }
+}
+
+impl RawElement {
+    pub(super) fn from_node(node: roxmltree::Node, source: &SourceMap) -> Self {
+        let parent_namespaces = node
+            .parent_element()
+            .map(|p| p.namespaces().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let namespaces = node
+            .namespaces()
+            .filter(|ns| !parent_namespaces.contains(ns))
+            .filter(|ns| ns.name() != Some("xml"))
+            .map(|ns| (ns.name().map(SmolStr::from), ns.uri().into()))
+            .collect();
+
+        let children = node
+            .children()
+            .filter_map(|child| {
+                if child.is_comment() {
+                    Some(RawNode::Comment(child.text().unwrap_or_default().into()))
+                } else if child.is_element() {
+                    Some(RawNode::Element(RawElement::from_node(child, source)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        RawElement::new(
+            qualified_name(node, node.tag_name().namespace(), node.tag_name().name()),
+            namespaces,
+            node.attributes()
+                .map(|a| {
+                    (
+                        qualified_name(node, a.namespace(), a.name()),
+                        a.value().into(),
+                    )
+                })
+                .collect(),
+            children,
+            location(node, source),
+        )
+    }
+
+    /// Element with the given attributes (and the `name` among them), with
+    /// `xmlns` attributes as namespace declarations
+    fn new(
+        tag: SmolStr,
+        namespaces: Vec<(Option<SmolStr>, SmolStr)>,
+        attributes: Vec<(SmolStr, SmolStr)>,
+        children: Vec<RawNode>,
+        location: Location,
+    ) -> Self {
+        let mut index = IndexMap::<SmolStr, Vec<usize>>::new();
+        for (i, child) in children.iter().enumerate() {
+            if let RawNode::Element(RawElement {
+                name: Some(name), ..
+            }) = child
+            {
+                index.entry(name.clone()).or_default().push(i);
+            }
+        }
+        let (xmlns, attributes): (Vec<_>, Vec<_>) = attributes
+            .into_iter()
+            .partition(|(name, _)| name == "xmlns" || name.starts_with("xmlns:"));
+        let namespaces = namespaces
+            .into_iter()
+            .chain(
+                xmlns
+                    .into_iter()
+                    .map(|(name, uri)| (name.strip_prefix("xmlns:").map(SmolStr::from), uri)),
+            )
+            .collect();
+
+        RawElement {
+            tag,
+            name: attributes
+                .iter()
+                .find(|(n, _)| n == "name")
+                .map(|(_, v)| v.clone()),
+            namespaces,
+            attributes,
+            children,
+            location,
+            index,
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&SmolStr> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// Child elements in document order, skipping comments
+    pub fn elements(&self) -> impl Iterator<Item = &RawElement> {
+        self.children.iter().filter_map(|c| match c {
+            RawNode::Element(e) => Some(e),
+            RawNode::Comment(_) => None,
+        })
+    }
+
+    /// All child elements with the given name, in document order
+    pub fn children_named(&self, name: &str) -> impl Iterator<Item = &RawElement> {
+        self.index
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|&i| match &self.children[i] {
+                RawNode::Element(e) => Some(e),
+                RawNode::Comment(_) => None,
+            })
+    }
+
+    /// The first child element with the given name
+    pub fn child(&self, name: &str) -> Option<&RawElement> {
+        self.children_named(name).next()
+    }
+
+    fn check_duplicates(&self, res: &mut Vec<Diagnostic>) {
+        for (name, indices) in &self.index {
+            for &i in &indices[1..] {
+                let RawNode::Element(element) = &self.children[i] else {
+                    continue;
+                };
+                res.push(Diagnostic::new(
+                    Code::DuplicateName,
+                    &element.location,
+                    format!("`{name}` is defined more than once in the same scope"),
+                ));
+            }
+        }
+        for child in self.elements() {
+            child.check_duplicates(res);
+        }
+    }
+
+    fn write_to(&self, w: &mut impl Write, depth: usize) -> io::Result<()> {
+        let indent = INDENT.repeat(depth);
+        write!(w, "{indent}<{}", self.tag)?;
+        for (prefix, uri) in &self.namespaces {
+            match prefix {
+                Some(prefix) => write_attribute(w, &format!("xmlns:{prefix}"), uri)?,
+                None => write_attribute(w, "xmlns", uri)?,
+            }
+        }
+        for (name, value) in &self.attributes {
+            write_attribute(w, name, value)?;
+        }
+        if self.children.is_empty() {
+            return writeln!(w, " />");
+        }
+        writeln!(w, ">")?;
+        for child in &self.children {
+            match child {
+                RawNode::Element(e) => e.write_to(w, depth + 1)?,
+                RawNode::Comment(c) => writeln!(w, "{indent}{}<!--{c}-->", INDENT)?,
+            }
+        }
+        writeln!(w, "{indent}</{}>", self.tag)
+    }
+}
+
+impl From<&MaterialX> for RawDocument {
+    /// Elements added by an include are written as the include element
+    fn from(mat: &MaterialX) -> Self {
+        let mut attributes = Vec::new();
+        // Documents built in code don't list these in `attributes`
+        if !mat.attributes.contains_key("version") {
+            attributes.push(("version".into(), mat.version.to_string().into()));
+        }
+        if let (false, Some(colorspace)) =
+            (mat.attributes.contains_key("colorspace"), &mat.colorspace)
+        {
+            attributes.push(("colorspace".into(), colorspace.as_str().into()));
+        }
+        for (name, value) in &mat.attributes {
+            match name.as_str() {
+                "version" => attributes.push((name.clone(), mat.version.to_string().into())),
+                "colorspace" => {
+                    if let Some(colorspace) = &mat.colorspace {
+                        attributes.push((name.clone(), colorspace.as_str().into()));
+                    }
+                }
+                _ => attributes.push((name.clone(), value.clone())),
+            }
+        }
+
+        let len = mat.elements.len();
+        let mut children = Vec::new();
+        for position in 0..=len {
+            for comment in mat
+                .comments
+                .iter()
+                .filter(|c| c.position.min(len) == position)
+            {
+                children.push(RawNode::Comment(comment.text.clone()));
+            }
+            for include in mat
+                .includes
+                .iter()
+                .filter(|i| i.position.min(len) == position)
+            {
+                children.push(RawNode::Element(RawElement::new(
+                    include.tag.clone(),
+                    Vec::new(),
+                    vec![("href".into(), include.href.clone())],
+                    Vec::new(),
+                    MaterialX::location(),
+                )));
+            }
+            let Some((name, element)) = mat.elements.get_index(position) else {
+                continue;
+            };
+            if !mat.includes.iter().any(|i| i.elements.contains(name)) {
+                children.push(RawNode::Element(element.into()));
+            }
+        }
+
+        RawDocument {
+            root: RawElement::new(
+                "materialx".into(),
+                Vec::new(),
+                attributes,
+                children,
+                MaterialX::location(),
+            ),
+            prolog: Vec::new(),
+            epilog: Vec::new(),
+        }
+    }
+}
+
+impl From<&Element> for RawElement {
+    fn from(element: &Element) -> Self {
+        let len = element.children.len();
+        let mut children = Vec::new();
+        for position in 0..=len {
+            for comment in element
+                .comments
+                .iter()
+                .filter(|c| c.position.min(len) == position)
+            {
+                children.push(RawNode::Comment(comment.text.clone()));
+            }
+            if let Some(child) = element.children.get_index(position) {
+                children.push(RawNode::Element(child.1.into()));
+            }
+        }
+
+        RawElement::new(
+            element.tag.clone(),
+            Vec::new(),
+            element
+                .attributes
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            children,
+            element.location.clone(),
+        )
+    }
+}
+
+fn comments<'a, 'input: 'a>(
+    nodes: impl Iterator<Item = roxmltree::Node<'a, 'input>>,
+) -> Vec<SmolStr> {
+    nodes
+        .filter(|n| n.is_comment())
+        .filter_map(|n| n.text())
+        .map(SmolStr::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0"?>
+<!-- Copyright notice -->
+<materialx xmlns:xi="http://www.w3.org/2001/XInclude" version="1.39">
+  <xi:include href="lib.mtlx" />
+  <!-- the brick -->
+  <nodegraph name="NG">
+    <input type="float" value="1" />
+    <constant name="c" type="float" />
+    <constant name="c" type="color3" />
+  </nodegraph>
+  <constant name="c" type="float" />
+</materialx>
+"#;
+
+    #[test]
+    fn keeps_everything() {
+        let doc = RawDocument::parse(XML).unwrap();
+        assert_eq!(doc.prolog, [" Copyright notice "]);
+        assert_eq!(
+            doc.root.namespaces,
+            [(Some("xi".into()), "http://www.w3.org/2001/XInclude".into())]
+        );
+        let tags = doc
+            .root
+            .elements()
+            .map(|e| e.tag.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, ["xi:include", "nodegraph", "constant"]);
+        assert!(matches!(&doc.root.children[1], RawNode::Comment(c) if c == " the brick "));
+
+        let graph = doc.root.child("NG").unwrap();
+        let unnamed = graph.elements().next().unwrap();
+        assert_eq!(unnamed.name, None);
+        assert_eq!(unnamed.location.path, "NG/input");
+        let types = graph
+            .children_named("c")
+            .map(|c| c.attribute("type").unwrap().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["float", "color3"]);
+    }
+
+    #[test]
+    fn duplicates() {
+        let doc = RawDocument::parse(XML).unwrap();
+        let diagnostics = doc.validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::DuplicateName);
+        assert_eq!(diagnostics[0].location.path, "NG/c");
+        assert_eq!(diagnostics[0].location.span.unwrap().line, 9);
+    }
+
+    #[test]
+    fn round_trip() {
+        let doc = RawDocument::parse(XML).unwrap();
+        assert_eq!(doc.to_xml_string(), XML);
+    }
+
+    #[test]
+    fn to_materialx() {
+        let doc = RawDocument::parse(XML).unwrap();
+        let err = MaterialX::try_from(&doc).unwrap_err();
+        assert!(matches!(err, crate::Error::IncludesNotSupported));
+
+        let xml = XML.replace(
+            r#"  <xi:include href="lib.mtlx" />
+"#,
+            "",
+        );
+        let doc = RawDocument::parse(&xml).unwrap();
+        let mat = MaterialX::try_from(&doc).unwrap();
+        let graph = mat.element("NG").unwrap();
+        let names = graph.children.keys().collect::<Vec<_>>();
+        assert_eq!(names, ["input~0", "c"]);
+        assert_eq!(graph.children["c"].attributes["type"], "color3");
+        assert_eq!(mat.duplicates.len(), 1);
+
+        let back = RawDocument::from(&mat);
+        let graph = back.root.child("NG").unwrap();
+        assert_eq!(graph.elements().next().unwrap().name, None);
+        assert_eq!(graph.children_named("c").count(), 1);
+        let tags = back
+            .root
+            .children
+            .iter()
+            .map(|c| match c {
+                RawNode::Element(e) => e.tag.as_str(),
+                RawNode::Comment(_) => "<!---->",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tags, ["<!---->", "nodegraph", "constant"]);
+        assert_eq!(back.root.namespaces, doc.root.namespaces);
+    }
+}
@@ -0,0 +1,140 @@
+//! Serializing documents back to `.mtlx`
+
+use super::{MaterialX, RawDocument};
+use std::io::{self, Write};
+
+pub(super) const INDENT: &str = "  ";
+
+impl MaterialX {
+    /// Write the document as MaterialX XML
+    ///
+    /// Namespace declarations come first. Other attributes, child elements
+    /// and comments are written in the order they are stored in, which is the
+    /// document order for parsed files. Elements added by an
+    /// [`Include`](super::Include) are written as the include element.
+    pub fn write_to(&self, w: impl Write) -> io::Result<()> {
+        RawDocument::from(self).write_to(w)
+    }
+
+    /// Serialize the document as MaterialX XML
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use materialx_parser::MaterialX;
+    ///
+    /// let mat = MaterialX::from_str(r#"<materialx version="1.39"><surfacematerial name="M" type="material" /></materialx>"#)?;
+    /// assert_eq!(
+    ///     mat.to_xml_string(),
+    ///     "<?xml version=\"1.0\"?>\n<materialx version=\"1.39\">\n  <surfacematerial name=\"M\" type=\"material\" />\n</materialx>\n"
+    /// );
+    /// # Ok::<(), materialx_parser::Error>(())
+    /// ```
+    pub fn to_xml_string(&self) -> String {
+        let mut res = Vec::new();
+        self.write_to(&mut res)
+            .expect("writing to a Vec never fails");
+        String::from_utf8(res).expect("only valid UTF-8 is written")
+    }
+}
+
+pub(super) fn write_attribute(w: &mut impl Write, name: &str, value: &str) -> io::Result<()> {
+    write!(w, r#" {name}=""#)?;
+    for c in value.chars() {
+        match c {
+            '&' => write!(w, "&amp;")?,
+            '<' => write!(w, "&lt;")?,
+            '>' => write!(w, "&gt;")?,
+            '"' => write!(w, "&quot;")?,
+            '\n' => write!(w, "&#10;")?,
+            c => write!(w, "{c}")?,
+        }
+    }
+    write!(w, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::FsResolver, Error, MaterialX};
+    use std::str::FromStr as _;
+
+    /// Element names, namespaces, attributes, children and comments in
+    /// document order, ignoring whitespace
+    fn structure(node: roxmltree::Node) -> String {
+        let mut res = format!("<{}", node.tag_name().name());
+        for ns in node.namespaces() {
+            res.push_str(&format!(" xmlns:{:?}={:?}", ns.name(), ns.uri()));
+        }
+        for attr in node.attributes() {
+            res.push_str(&format!(" {}={:?}", attr.name(), attr.value()));
+        }
+        res.push('>');
+        for child in node.children() {
+            if child.is_element() {
+                res.push_str(&structure(child));
+            } else if child.is_comment() {
+                res.push_str(&format!("<!--{}-->", child.text().unwrap_or_default()));
+            }
+        }
+        res.push_str("</>");
+        res
+    }
+
+    #[test]
+    fn round_trip() {
+        for example in glob::glob("../assets/materialx-examples/**/*.mtlx").unwrap() {
+            let path = example.unwrap();
+            let xml = std::fs::read_to_string(&path).unwrap();
+            let mat = match MaterialX::from_str(&xml) {
+                Err(Error::IncludesNotSupported) => {
+                    MaterialX::load_with_resolver(path.to_str().unwrap(), &FsResolver)
+                }
+                res => res,
+            }
+            .unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+            let written = mat.to_xml_string();
+
+            let original = roxmltree::Document::parse(&xml).unwrap();
+            let written = roxmltree::Document::parse(&written)
+                .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            assert_eq!(
+                structure(original.root_element()),
+                structure(written.root_element()),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn root_attributes() {
+        let xml = r#"<materialx fileprefix="tex/" colorspace="acescg" version="1.39" xmlns:xi="http://www.w3.org/2001/XInclude">
+  <!-- only a comment -->
+</materialx>"#;
+        let mat = MaterialX::from_str(xml).unwrap();
+        let written = mat.to_xml_string();
+        assert!(
+            written.contains(r#"<materialx xmlns:xi="http://www.w3.org/2001/XInclude" fileprefix="tex/" colorspace="acescg" version="1.39">"#),
+            "{written}"
+        );
+        assert!(written.contains("  <!-- only a comment -->\n"), "{written}");
+    }
+
+    #[test]
+    fn escaping() {
+        let xml = r#"<materialx version="1.39">
+            <image name="img" type="color3">
+                <input name="file" type="filename" value="a&amp;b_&lt;UDIM&gt;.png" />
+            </image>
+        </materialx>"#;
+        let mat = MaterialX::from_str(xml).unwrap();
+        let written = mat.to_xml_string();
+        assert!(written.contains(r#"value="a&amp;b_&lt;UDIM&gt;.png""#));
+        let reparsed = MaterialX::from_str(&written).unwrap();
+        assert_eq!(
+            reparsed.element("img").unwrap().children["file"].attributes["value"],
+            "a&b_<UDIM>.png"
+        );
+    }
+}
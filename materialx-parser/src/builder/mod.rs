@@ -0,0 +1,652 @@
+//! Creating documents in code
+//!
+//! [`DocumentBuilder`] creates nodes and nodegraphs and wires up their
+//! connections. Connected inputs declare their type like any other input, and
+//! all references and the types on both ends of each connection are checked
+//! when calling [`DocumentBuilder::build`].
+//!
+//! # Examples
+//!
+//! ```
+//! use materialx_parser::{
+//!     data_types::{DataTypeAndValue, Vector3},
+//!     MaterialX,
+//! };
+//!
+//! let mat = MaterialX::builder()
+//!     .nodegraph("NG_gold", |graph| {
+//!         graph
+//!             .node("image", "roughness", "float")
+//!             .input("file", DataTypeAndValue::Filename("gold_roughness.png".into()))
+//!             .output("out_roughness", "float", "roughness")
+//!     })
+//!     .node("standard_surface", "SR_gold", "surfaceshader")
+//!     .input("base_color", DataTypeAndValue::Color3(Vector3([0.94, 0.78, 0.37])))
+//!     .input("metalness", 1.0)
+//!     .connect_output("specular_roughness", "float", "NG_gold", "out_roughness")
+//!     .node("surfacematerial", "M_gold", "material")
+//!     .connect("surfaceshader", "surfaceshader", "SR_gold")
+//!     .build()?;
+//!
+//! assert_eq!(mat.element("M_gold")?.children["surfaceshader"].attributes["type"], "surfaceshader");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{
+    ast::{ColorSpace, Location, Version},
+    data_types::DataTypeAndValue,
+    nodedef::{NodeDefIssue, NodeDefRegistry},
+    Element, MaterialX,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum BuilderError {
+    #[error("Input `{input}` was added before any node (in `{scope}`)")]
+    NoNode { input: SmolStr, scope: SmolStr },
+    #[error("Element `{name}` is defined more than once (in `{scope}`)")]
+    DuplicateName { name: SmolStr, scope: SmolStr },
+    #[error("`{from}` refers to `{to}`, which does not exist")]
+    UnknownReference { from: SmolStr, to: SmolStr },
+    #[error("`{path}` has type `{found}` but is connected to `{to}` of type `{expected}`")]
+    TypeMismatch {
+        path: SmolStr,
+        to: SmolStr,
+        expected: SmolStr,
+        found: SmolStr,
+    },
+    #[error("Node does not match its definition: {0}")]
+    NodeDef(#[from] NodeDefIssue),
+}
+
+impl MaterialX {
+    /// Start building a new document
+    pub fn builder() -> DocumentBuilder {
+        DocumentBuilder::default()
+    }
+}
+
+/// Elements of a document or nodegraph, with the node currently being built
+#[derive(Debug)]
+struct Scope {
+    name: SmolStr,
+    elements: IndexMap<SmolStr, Element>,
+    current: Option<SmolStr>,
+    errors: Vec<BuilderError>,
+}
+
+impl Scope {
+    fn new(name: SmolStr) -> Self {
+        Scope {
+            name,
+            elements: IndexMap::new(),
+            current: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, mut element: Element) {
+        if self.name != MaterialX::NAME {
+            element.location.path = format!("{}/{}", self.name, element.name).into();
+        }
+        if self.elements.contains_key(&element.name) {
+            self.errors.push(BuilderError::DuplicateName {
+                name: element.name,
+                scope: self.name.clone(),
+            });
+            return;
+        }
+        self.elements.insert(element.name.clone(), element);
+    }
+
+    fn node(&mut self, category: &str, name: &str, r#type: &str) {
+        self.add(element(category, name, [("type", r#type)]));
+        self.current = Some(name.into());
+    }
+
+    fn attribute(&mut self, name: &str, value: &str) {
+        if let Some(current) = self.current() {
+            current.attributes.insert(name.into(), value.into());
+        }
+    }
+
+    /// Add an input to the current node
+    fn input<const N: usize>(&mut self, name: &str, attributes: [(&str, &str); N]) {
+        let mut input = element("input", name, attributes);
+        let scope = self.name.clone();
+        let Some(current) = self.current() else {
+            self.errors.push(BuilderError::NoNode {
+                input: name.into(),
+                scope,
+            });
+            return;
+        };
+        if current.children.contains_key(name) {
+            let name = format!("{}/{name}", current.name).into();
+            self.errors
+                .push(BuilderError::DuplicateName { name, scope });
+            return;
+        }
+        input.location = current.location.child(name);
+        current.children.insert(name.into(), input);
+    }
+
+    fn value(&mut self, name: &str, value: DataTypeAndValue) {
+        let r#type = value.tag().to_string();
+        self.input(name, [("type", &r#type), ("value", &value.value_string())]);
+    }
+
+    fn current(&mut self) -> Option<&mut Element> {
+        self.current
+            .as_ref()
+            .and_then(|name| self.elements.get_mut(name))
+    }
+}
+
+pub(crate) fn element<const N: usize>(
+    tag: &str,
+    name: &str,
+    attributes: [(&str, &str); N],
+) -> Element {
+    Element {
+        tag: tag.into(),
+        name: name.into(),
+        attributes: std::iter::once(("name".into(), name.into()))
+            .chain(attributes.map(|(k, v)| (k.into(), v.into())))
+            .collect(),
+        children: IndexMap::new(),
+        location: Location {
+            path: name.into(),
+            ..Location::default()
+        },
+        comments: Vec::new(),
+    }
+}
+
+/// Builder for a [`MaterialX`] document
+///
+/// Nodes are added with [`node`](Self::node), and all following calls to
+/// [`input`](Self::input) and the `connect` methods apply to the last
+/// added node.
+#[derive(Debug)]
+pub struct DocumentBuilder {
+    version: Version,
+    colorspace: Option<ColorSpace>,
+    attributes: IndexMap<SmolStr, SmolStr>,
+    scope: Scope,
+}
+
+impl Default for DocumentBuilder {
+    fn default() -> Self {
+        DocumentBuilder {
+            version: Version {
+                major: 1,
+                minor: 39,
+            },
+            colorspace: None,
+            attributes: IndexMap::new(),
+            scope: Scope::new(MaterialX::NAME),
+        }
+    }
+}
+
+impl DocumentBuilder {
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn colorspace(mut self, colorspace: ColorSpace) -> Self {
+        self.colorspace = Some(colorspace);
+        self
+    }
+
+    /// Set an attribute on the `materialx` root element (e.g. `fileprefix`)
+    pub fn document_attribute(mut self, name: &str, value: &str) -> Self {
+        self.attributes.insert(name.into(), value.into());
+        self
+    }
+
+    /// Add a node with the given category (tag), name, and output type
+    pub fn node(mut self, category: &str, name: &str, r#type: &str) -> Self {
+        self.scope.node(category, name, r#type);
+        self
+    }
+
+    /// Set an attribute on the current node
+    pub fn attribute(mut self, name: &str, value: &str) -> Self {
+        self.scope.attribute(name, value);
+        self
+    }
+
+    /// Add an input with a constant value to the current node
+    pub fn input(mut self, name: &str, value: impl Into<DataTypeAndValue>) -> Self {
+        self.scope.value(name, value.into());
+        self
+    }
+
+    /// Connect an input of the current node of type `type` to another node
+    /// in the document
+    pub fn connect(mut self, input: &str, r#type: &str, node: &str) -> Self {
+        self.scope
+            .input(input, [("type", r#type), ("nodename", node)]);
+        self
+    }
+
+    /// Connect an input of the current node of type `type` to the output of a
+    /// nodegraph
+    pub fn connect_output(
+        mut self,
+        input: &str,
+        r#type: &str,
+        nodegraph: &str,
+        output: &str,
+    ) -> Self {
+        self.scope.input(
+            input,
+            [
+                ("type", r#type),
+                ("nodegraph", nodegraph),
+                ("output", output),
+            ],
+        );
+        self
+    }
+
+    /// Add a nodegraph, built by `f`
+    pub fn nodegraph(
+        mut self,
+        name: &str,
+        f: impl FnOnce(NodeGraphBuilder) -> NodeGraphBuilder,
+    ) -> Self {
+        let graph = f(NodeGraphBuilder {
+            scope: Scope::new(name.into()),
+        });
+        let mut nodegraph = element::<0>("nodegraph", name, []);
+        nodegraph.children = graph.scope.elements;
+        self.scope.errors.extend(graph.scope.errors);
+        self.scope.add(nodegraph);
+        self.scope.current = None;
+        self
+    }
+
+    /// Check all references and connection types, and create the document
+    pub fn build(self) -> Result<MaterialX, BuilderError> {
+        let mut scope = self.scope;
+        if !scope.errors.is_empty() {
+            return Err(scope.errors.remove(0));
+        }
+
+        let elements = scope.elements;
+        for element in elements.values() {
+            if element.tag == "nodegraph" {
+                for child in element.children.values() {
+                    check_connections(&elements, Some(element), child)?;
+                }
+            } else {
+                check_connections(&elements, None, element)?;
+            }
+        }
+
+        Ok(MaterialX {
+            version: self.version,
+            colorspace: self.colorspace,
+            attributes: self.attributes,
+            elements,
+            comments: Vec::new(),
+            includes: Vec::new(),
+            duplicates: Vec::new(),
+        })
+    }
+
+    /// Check all references, and check all nodes against their definitions
+    pub fn build_checked(self, nodedefs: &NodeDefRegistry) -> Result<MaterialX, BuilderError> {
+        let doc = self.build()?;
+        if let Some(issue) = nodedefs.check(&doc).into_iter().next() {
+            return Err(issue.into());
+        }
+        Ok(doc)
+    }
+}
+
+/// Builder for the contents of a `<nodegraph>`
+///
+/// See [`DocumentBuilder::nodegraph`].
+#[derive(Debug)]
+pub struct NodeGraphBuilder {
+    scope: Scope,
+}
+
+impl NodeGraphBuilder {
+    /// Add an interface input to the nodegraph
+    pub fn interface(mut self, name: &str, value: impl Into<DataTypeAndValue>) -> Self {
+        let value = value.into();
+        let r#type = value.tag().to_string();
+        self.scope.add(element(
+            "input",
+            name,
+            [("type", &r#type), ("value", &value.value_string())],
+        ));
+        self.scope.current = None;
+        self
+    }
+
+    /// Add a node with the given category (tag), name, and output type
+    pub fn node(mut self, category: &str, name: &str, r#type: &str) -> Self {
+        self.scope.node(category, name, r#type);
+        self
+    }
+
+    /// Set an attribute on the current node
+    pub fn attribute(mut self, name: &str, value: &str) -> Self {
+        self.scope.attribute(name, value);
+        self
+    }
+
+    /// Add an input with a constant value to the current node
+    pub fn input(mut self, name: &str, value: impl Into<DataTypeAndValue>) -> Self {
+        self.scope.value(name, value.into());
+        self
+    }
+
+    /// Connect an input of the current node of type `type` to another node
+    /// in the nodegraph
+    pub fn connect(mut self, input: &str, r#type: &str, node: &str) -> Self {
+        self.scope
+            .input(input, [("type", r#type), ("nodename", node)]);
+        self
+    }
+
+    /// Connect an input of the current node of type `type` to an interface
+    /// input
+    pub fn connect_interface(mut self, input: &str, r#type: &str, interface: &str) -> Self {
+        self.scope
+            .input(input, [("type", r#type), ("interfacename", interface)]);
+        self
+    }
+
+    /// Add an output of the nodegraph, connected to a node in it
+    pub fn output(mut self, name: &str, r#type: &str, node: &str) -> Self {
+        self.scope.add(element(
+            "output",
+            name,
+            [("type", r#type), ("nodename", node)],
+        ));
+        self.scope.current = None;
+        self
+    }
+}
+
+/// Check that the connections of `node` refer to existing elements of the
+/// type they declare
+fn check_connections(
+    elements: &IndexMap<SmolStr, Element>,
+    graph: Option<&Element>,
+    node: &Element,
+) -> Result<(), BuilderError> {
+    let path: SmolStr = match graph {
+        Some(graph) => format!("{}/{}", graph.name, node.name).into(),
+        None => node.name.clone(),
+    };
+    let siblings = graph.map_or(elements, |g| &g.children);
+
+    // Nodegraph outputs are connected directly
+    if node.tag == "output" {
+        let Some(to) = node.attributes.get("nodename") else {
+            return Ok(());
+        };
+        let r#type = type_of(siblings, to, &path)?;
+        let declared = &node.attributes["type"];
+        if r#type != *declared {
+            return Err(BuilderError::TypeMismatch {
+                path,
+                to: to.clone(),
+                expected: r#type,
+                found: declared.clone(),
+            });
+        }
+        return Ok(());
+    }
+
+    for (name, input) in &node.children {
+        let path: SmolStr = format!("{path}/{name}").into();
+        let attrs = &input.attributes;
+        let (to, r#type) = if let Some(node) = attrs.get("nodename") {
+            (node.clone(), type_of(siblings, node, &path)?)
+        } else if let (Some(nodegraph), Some(output)) =
+            (attrs.get("nodegraph"), attrs.get("output"))
+        {
+            let nodegraph =
+                elements
+                    .get(nodegraph)
+                    .ok_or_else(|| BuilderError::UnknownReference {
+                        from: path.clone(),
+                        to: nodegraph.clone(),
+                    })?;
+            let to: SmolStr = format!("{}/{output}", nodegraph.name).into();
+            let r#type = type_of(&nodegraph.children, output, &path).map_err(|_| {
+                BuilderError::UnknownReference {
+                    from: path.clone(),
+                    to: to.clone(),
+                }
+            })?;
+            (to, r#type)
+        } else if let Some(interface) = attrs.get("interfacename") {
+            let to: SmolStr = interface.clone();
+            let interface = graph
+                .and_then(|g| g.children.get(interface))
+                .filter(|e| e.tag == "input")
+                .ok_or_else(|| BuilderError::UnknownReference {
+                    from: path.clone(),
+                    to: to.clone(),
+                })?;
+            (to, interface.attributes["type"].clone())
+        } else {
+            continue;
+        };
+        let declared = &attrs["type"];
+        if r#type != *declared {
+            return Err(BuilderError::TypeMismatch {
+                path,
+                to,
+                expected: r#type,
+                found: declared.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn type_of(
+    elements: &IndexMap<SmolStr, Element>,
+    name: &SmolStr,
+    from: &SmolStr,
+) -> Result<SmolStr, BuilderError> {
+    elements
+        .get(name)
+        .and_then(|e| e.attributes.get("type"))
+        .cloned()
+        .ok_or_else(|| BuilderError::UnknownReference {
+            from: from.clone(),
+            to: name.clone(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data_types::Vector3, nodes::ResolvedInput};
+    use std::str::FromStr as _;
+
+    #[test]
+    fn round_trip() {
+        let mat = MaterialX::builder()
+            .colorspace(ColorSpace::LinRec709)
+            .nodegraph("NG", |graph| {
+                graph
+                    .interface("scale", 2.0)
+                    .node("multiply", "mul", "float")
+                    .input("in1", 0.5)
+                    .connect_interface("in2", "float", "scale")
+                    .output("out", "float", "mul")
+            })
+            .node("standard_surface", "SR", "surfaceshader")
+            .input(
+                "base_color",
+                DataTypeAndValue::Color3(Vector3([1.0, 0.5, 0.0])),
+            )
+            .connect_output("specular_roughness", "float", "NG", "out")
+            .node("surfacematerial", "M", "material")
+            .connect("surfaceshader", "surfaceshader", "SR")
+            .build()
+            .unwrap();
+
+        let xml = mat.to_xml_string();
+        let parsed = MaterialX::from_str(&xml).unwrap();
+        let surface = parsed.element("SR").unwrap();
+        assert_eq!(
+            surface.children["base_color"].attributes["value"],
+            "1, 0.5, 0"
+        );
+        assert_eq!(
+            surface.children["specular_roughness"].attributes["type"],
+            "float"
+        );
+        let ResolvedInput::Node(node) = parsed
+            .resolve(surface, None, "specular_roughness".into())
+            .unwrap()
+        else {
+            panic!("expected node");
+        };
+        assert_eq!(node.element.name, "mul");
+        let scale: f32 = parsed
+            .resolve_input(&node.element, node.parent(&parsed), "in2".into())
+            .unwrap();
+        assert_eq!(scale, 2.0);
+    }
+
+    #[test]
+    fn unknown_reference() {
+        let err = MaterialX::builder()
+            .node("surfacematerial", "M", "material")
+            .connect("surfaceshader", "surfaceshader", "SR")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BuilderError::UnknownReference { from, to } if from == "M/surfaceshader" && to == "SR"
+        ));
+
+        let err = MaterialX::builder()
+            .nodegraph("NG", |graph| graph)
+            .node("standard_surface", "SR", "surfaceshader")
+            .connect_output("base_color", "color3", "NG", "out")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuilderError::UnknownReference { to, .. } if to == "NG/out"));
+    }
+
+    #[test]
+    fn output_type_mismatch() {
+        let err = MaterialX::builder()
+            .nodegraph("NG", |graph| {
+                graph
+                    .node("constant", "c", "float")
+                    .input("value", 1.0)
+                    .output("out", "color3", "c")
+            })
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BuilderError::TypeMismatch { expected, found, .. } if expected == "float" && found == "color3"
+        ));
+    }
+
+    #[test]
+    fn input_type_mismatch() {
+        let err = MaterialX::builder()
+            .nodegraph("NG", |graph| {
+                graph
+                    .interface("scale", 2.0)
+                    .node("multiply", "mul", "color3")
+                    .connect_interface("in2", "color3", "scale")
+                    .output("out", "color3", "mul")
+            })
+            .node("standard_surface", "SR", "surfaceshader")
+            .connect_output("specular_roughness", "float", "NG", "out")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BuilderError::TypeMismatch { path, expected, found, .. }
+                if path == "NG/mul/in2" && expected == "float" && found == "color3"
+        ));
+
+        let err = MaterialX::builder()
+            .node("constant", "c", "float")
+            .node("surfacematerial", "M", "material")
+            .connect("surfaceshader", "surfaceshader", "c")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BuilderError::TypeMismatch { path, to, .. } if path == "M/surfaceshader" && to == "c"
+        ));
+    }
+
+    #[test]
+    fn duplicates_and_orphans() {
+        let err = MaterialX::builder()
+            .node("constant", "c", "float")
+            .node("constant", "c", "float")
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuilderError::DuplicateName { name, .. } if name == "c"));
+
+        let err = MaterialX::builder()
+            .input("value", 1.0)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuilderError::NoNode { .. }));
+    }
+
+    #[test]
+    fn checked_against_nodedefs() {
+        let mut nodedefs = NodeDefRegistry::default();
+        nodedefs
+            .add_document(
+                &MaterialX::from_str(
+                    r#"
+                    <materialx version="1.39">
+                      <nodedef name="ND_surfacematerial" node="surfacematerial">
+                        <input name="surfaceshader" type="surfaceshader" value="" />
+                        <output name="out" type="material" />
+                      </nodedef>
+                      <nodedef name="ND_constant_float" node="constant">
+                        <input name="value" type="float" value="0.0" />
+                        <output name="out" type="float" />
+                      </nodedef>
+                    </materialx>
+                "#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        let err = MaterialX::builder()
+            .node("constant", "c", "float")
+            .input("value", 1.0)
+            .node("surfacematerial", "M", "material")
+            .connect("surfaceshader", "float", "c")
+            .build_checked(&nodedefs)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BuilderError::NodeDef(NodeDefIssue::TypeMismatch { expected, found, .. })
+                if expected == "surfaceshader" && found == "float"
+        ));
+    }
+}
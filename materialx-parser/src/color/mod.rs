@@ -0,0 +1,251 @@
+//! Color management
+//!
+//! Color values in a document are in the color space named by the closest
+//! `colorspace` attribute: on the `<input>` itself, on the node or nodegraph
+//! containing it, or on the document. Without any, they are in `lin_rec709`.
+//! [`MaterialX::resolve`] converts constant colors to linear Rec.709 (which
+//! has the same primaries as sRGB), so callers don't have to care.
+//!
+//! Textures are converted by the GPU while sampling, which can only undo the
+//! sRGB transfer function. [`ColorSpace::transfer`] tells whether an image is
+//! encoded at all.
+//!
+//! ```
+//! use materialx_parser::{ast::ColorSpace, data_types::DataTypeAndValue};
+//! # use materialx_parser::data_types::Vector3;
+//!
+//! let red = DataTypeAndValue::Color3(Vector3([1.0, 0.0, 0.0]));
+//! let DataTypeAndValue::Color3(Vector3([r, g, b])) = ColorSpace::AcesCG.linearize(red) else {
+//!     unreachable!()
+//! };
+//! assert!(r > 1.0 && g < 0.0 && b < 0.0);
+//! ```
+
+use crate::{
+    ast::ColorSpace,
+    data_types::{DataTypeAndValue, Vector3, Vector4},
+    Element, MaterialX,
+};
+
+/// Primaries and white point of a color space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primaries {
+    /// Rec.709 / sRGB, D65 white point
+    Rec709,
+    /// ACES AP1, D60 white point
+    Ap1,
+    /// Adobe RGB (1998), D65 white point
+    AdobeRgb,
+    /// Display P3, D65 white point
+    DisplayP3,
+}
+
+impl Primaries {
+    /// Matrix from linear values with these primaries to linear Rec.709
+    ///
+    /// AP1 is adapted from D60 to D65 with the Bradford transform.
+    fn to_rec709(self) -> Option<[[f64; 3]; 3]> {
+        Some(match self {
+            Primaries::Rec709 => return None,
+            Primaries::Ap1 => [
+                [1.7050509927, -0.6217921207, -0.0832588720],
+                [-0.1302564175, 1.1408047366, -0.0105483191],
+                [-0.0240033568, -0.1289689761, 1.1529723329],
+            ],
+            Primaries::AdobeRgb => [
+                [1.3983557440, -0.3983557440, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, -0.0429289893, 1.0429289893],
+            ],
+            Primaries::DisplayP3 => [
+                [1.2249401763, -0.2249401763, 0.0],
+                [-0.0420569547, 1.0420569547, 0.0],
+                [-0.0196375546, -0.0786360456, 1.0982736001],
+            ],
+        })
+    }
+}
+
+/// How values of a color space are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    Linear,
+    /// The piecewise sRGB curve
+    Srgb,
+    /// A pure power function with this exponent
+    Gamma(f64),
+}
+
+impl Transfer {
+    /// Decode an encoded value
+    pub fn to_linear(self, x: f64) -> f64 {
+        match self {
+            Transfer::Linear => x,
+            Transfer::Srgb if x <= 0.04045 => x / 12.92,
+            Transfer::Srgb => ((x + 0.055) / 1.055).powf(2.4),
+            Transfer::Gamma(gamma) => x.signum() * x.abs().powf(gamma),
+        }
+    }
+}
+
+impl ColorSpace {
+    /// `None` for unknown color spaces
+    pub fn primaries(&self) -> Option<Primaries> {
+        Some(match self {
+            ColorSpace::SrgbTexture
+            | ColorSpace::LinRec709
+            | ColorSpace::G22Rec709
+            | ColorSpace::G18Rec709
+            | ColorSpace::LinSrgb => Primaries::Rec709,
+            ColorSpace::AcesCG | ColorSpace::LinAp1 | ColorSpace::G22Ap1 | ColorSpace::G18Ap1 => {
+                Primaries::Ap1
+            }
+            ColorSpace::AdobeRGB | ColorSpace::LinAdobeRGB => Primaries::AdobeRgb,
+            ColorSpace::SrgbDisplayP3 | ColorSpace::LinDisplayP3 => Primaries::DisplayP3,
+            ColorSpace::Unknown(_) => return None,
+        })
+    }
+
+    /// `None` for unknown color spaces
+    pub fn transfer(&self) -> Option<Transfer> {
+        Some(match self {
+            ColorSpace::LinRec709
+            | ColorSpace::AcesCG
+            | ColorSpace::LinAp1
+            | ColorSpace::LinSrgb
+            | ColorSpace::LinAdobeRGB
+            | ColorSpace::LinDisplayP3 => Transfer::Linear,
+            ColorSpace::SrgbTexture | ColorSpace::SrgbDisplayP3 => Transfer::Srgb,
+            ColorSpace::G22Rec709 | ColorSpace::G22Ap1 => Transfer::Gamma(2.2),
+            ColorSpace::G18Rec709 | ColorSpace::G18Ap1 => Transfer::Gamma(1.8),
+            ColorSpace::AdobeRGB => Transfer::Gamma(563.0 / 256.0),
+            ColorSpace::Unknown(_) => return None,
+        })
+    }
+
+    /// Convert an RGB triple in this color space to linear Rec.709
+    ///
+    /// Returns `None` for unknown color spaces.
+    pub fn to_lin_rec709(&self, rgb: [f64; 3]) -> Option<[f64; 3]> {
+        let transfer = self.transfer()?;
+        let linear = rgb.map(|x| transfer.to_linear(x));
+        Some(match self.primaries()?.to_rec709() {
+            Some(m) => {
+                [0, 1, 2].map(|i| m[i][0] * linear[0] + m[i][1] * linear[1] + m[i][2] * linear[2])
+            }
+            None => linear,
+        })
+    }
+
+    /// Convert `color3` and `color4` values in this color space to linear
+    /// Rec.709
+    ///
+    /// Alpha and values of other types are returned unchanged, as are colors
+    /// in unknown color spaces.
+    pub fn linearize(&self, value: DataTypeAndValue) -> DataTypeAndValue {
+        match value {
+            DataTypeAndValue::Color3(Vector3(rgb)) => match self.to_lin_rec709(rgb) {
+                Some(rgb) => DataTypeAndValue::Color3(Vector3(rgb)),
+                None => value,
+            },
+            DataTypeAndValue::Color4(Vector4([r, g, b, a])) => {
+                match self.to_lin_rec709([r, g, b]) {
+                    Some([r, g, b]) => DataTypeAndValue::Color4(Vector4([r, g, b, a])),
+                    None => value,
+                }
+            }
+            value => value,
+        }
+    }
+}
+
+impl MaterialX {
+    /// The color space declared closest to the innermost of `scope`
+    ///
+    /// `scope` lists elements from the inside out, e.g. an `<input>`, its
+    /// node and the nodegraph containing that. Returns the document's color
+    /// space if none of them has a `colorspace` attribute, and `None` if the
+    /// document doesn't either, in which case colors are in `lin_rec709`.
+    pub fn colorspace_in<'a>(
+        &self,
+        scope: impl IntoIterator<Item = &'a Element>,
+    ) -> Option<ColorSpace> {
+        scope
+            .into_iter()
+            .find_map(|element| element.attributes.get("colorspace"))
+            .and_then(|name| name.parse().ok())
+            .or_else(|| self.colorspace.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::ResolvedInput;
+    use std::str::FromStr as _;
+
+    fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6)
+    }
+
+    #[test]
+    fn white_stays_white() {
+        for name in [
+            "acescg",
+            "g22_ap1",
+            "adobergb",
+            "srgb_displayp3",
+            "srgb_texture",
+        ] {
+            let space = ColorSpace::from_str(name).unwrap();
+            let white = space.to_lin_rec709([1.0; 3]).unwrap();
+            assert!(close(white, [1.0; 3]), "{name}: {white:?}");
+        }
+    }
+
+    #[test]
+    fn transfer_functions() {
+        let srgb = ColorSpace::SrgbTexture.to_lin_rec709([0.5; 3]).unwrap();
+        assert!(close(srgb, [0.21404114; 3]));
+        let gamma = ColorSpace::G22Rec709.to_lin_rec709([0.5; 3]).unwrap();
+        assert!(close(gamma, [0.5f64.powf(2.2); 3]));
+        assert!(ColorSpace::Unknown("xyz".into())
+            .to_lin_rec709([0.5; 3])
+            .is_none());
+    }
+
+    #[test]
+    fn inherited_colorspace() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39" colorspace="acescg">
+                <nodegraph name="graph" colorspace="srgb_texture">
+                    <constant name="c" type="color3">
+                        <input name="value" type="color3" value="0.5, 0.5, 0.5" />
+                    </constant>
+                    <constant name="d" type="color3">
+                        <input name="value" type="color3" value="0.5, 0.5, 0.5" colorspace="lin_rec709" />
+                    </constant>
+                </nodegraph>
+                <constant name="e" type="color3">
+                    <input name="value" type="color3" value="1, 0, 0" />
+                </constant>
+            </materialx>
+            "#,
+        )
+        .unwrap();
+        let graph = mat.element("graph").unwrap();
+        let color = |node: &Element, parent| match mat.resolve(node, parent, "value".into()) {
+            Ok(ResolvedInput::Value(DataTypeAndValue::Color3(Vector3(rgb)))) => rgb,
+            other => panic!("expected color, got {other:?}"),
+        };
+
+        assert!(close(
+            color(&graph.children["c"], Some(graph)),
+            [0.21404114; 3]
+        ));
+        assert!(close(color(&graph.children["d"], Some(graph)), [0.5; 3]));
+        let e = color(mat.element("e").unwrap(), None);
+        assert!(close(e, [1.7050509927, -0.1302564175, -0.0240033568]));
+    }
+}
@@ -1,6 +1,8 @@
 use super::{DataTypeAndValue, ValueParseError};
 use bevy_color::{Color as BevyColor, LinearRgba};
 
+/// Colors are read as linear Rec.709, which is what
+/// [`MaterialX::resolve`](crate::MaterialX::resolve) converts them to
 impl TryFrom<DataTypeAndValue> for BevyColor {
     type Error = ValueParseError;
 
@@ -58,3 +60,16 @@ impl TryFrom<DataTypeAndValue> for f64 {
         }
     }
 }
+
+impl TryFrom<DataTypeAndValue> for bool {
+    type Error = ValueParseError;
+
+    fn try_from(value: DataTypeAndValue) -> Result<Self, Self::Error> {
+        match value {
+            DataTypeAndValue::Boolean(b) => Ok(b),
+            _ => Err(ValueParseError::UnexpectedFormat {
+                format: value.tag(),
+            }),
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use std::str::FromStr;
+use crate::ast::Location;
+use std::{fmt, str::FromStr};
 
 mod convert;
 mod primitives;
@@ -59,6 +60,40 @@ impl FromStr for DataType {
     }
 }
 
+impl DataType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DataType::Integer => "integer",
+            DataType::Boolean => "boolean",
+            DataType::Float => "float",
+            DataType::Color3 => "color3",
+            DataType::Color4 => "color4",
+            DataType::Vector2 => "vector2",
+            DataType::Vector3 => "vector3",
+            DataType::Vector4 => "vector4",
+            DataType::Matrix3x3 => "matrix33",
+            DataType::Matrix4x4 => "matrix44",
+            DataType::String => "string",
+            DataType::Filename => "filename",
+            DataType::IntegerArray => "integerarray",
+            DataType::FloatArray => "floatarray",
+            DataType::Color3Array => "color3array",
+            DataType::Color4Array => "color4array",
+            DataType::Vector2Array => "vector2array",
+            DataType::Vector3Array => "vector3array",
+            DataType::Vector4Array => "vector4array",
+            DataType::StringArray => "stringarray",
+            DataType::Unknown(s) => s,
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DataTypeAndValue {
     Integer(u64),
@@ -192,6 +227,59 @@ impl DataTypeAndValue {
             DataTypeAndValue::Unknown { tag, .. } => DataType::Unknown(tag.to_string()),
         }
     }
+
+    /// The value as written in a `value` attribute
+    pub fn value_string(&self) -> String {
+        fn list<T: ToString>(values: &[T]) -> String {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        match self {
+            DataTypeAndValue::Integer(v) => v.to_string(),
+            DataTypeAndValue::Boolean(v) => v.to_string(),
+            DataTypeAndValue::Float(v) => v.to_string(),
+            DataTypeAndValue::Color3(v) => v.to_string(),
+            DataTypeAndValue::Color4(v) => v.to_string(),
+            DataTypeAndValue::Vector2(v) => v.to_string(),
+            DataTypeAndValue::Vector3(v) => v.to_string(),
+            DataTypeAndValue::Vector4(v) => v.to_string(),
+            DataTypeAndValue::Matrix3x3(v) => v.to_string(),
+            DataTypeAndValue::Matrix4x4(v) => v.to_string(),
+            DataTypeAndValue::String(v) => v.clone(),
+            DataTypeAndValue::Filename(v) => v.clone(),
+            DataTypeAndValue::IntegerArray(v) => list(v),
+            DataTypeAndValue::FloatArray(v) => list(v),
+            DataTypeAndValue::Color3Array(v) => list(v),
+            DataTypeAndValue::Color4Array(v) => list(v),
+            DataTypeAndValue::Vector2Array(v) => list(v),
+            DataTypeAndValue::Vector3Array(v) => list(v),
+            DataTypeAndValue::Vector4Array(v) => list(v),
+            DataTypeAndValue::StringArray(v) => list(v),
+            DataTypeAndValue::Unknown { value, .. } => value.clone(),
+        }
+    }
+}
+
+impl From<f64> for DataTypeAndValue {
+    fn from(value: f64) -> Self {
+        DataTypeAndValue::Float(value)
+    }
+}
+
+impl From<f32> for DataTypeAndValue {
+    fn from(value: f32) -> Self {
+        DataTypeAndValue::Float(value as f64)
+    }
+}
+
+impl From<bool> for DataTypeAndValue {
+    fn from(value: bool) -> Self {
+        DataTypeAndValue::Boolean(value)
+    }
 }
 
 pub type Color3 = Vector3;
@@ -219,6 +307,11 @@ pub enum ValueParseError {
     },
     #[error("Unexpected data format `{format:?}`")]
     UnexpectedFormat { format: DataType },
+    #[error("{location}: {source}")]
+    Located {
+        location: Box<Location>,
+        source: Box<ValueParseError>,
+    },
 }
 
 impl ValueParseError {
@@ -229,6 +322,17 @@ impl ValueParseError {
         }
     }
 
+    /// Annotate the error with the location of the input it came from
+    pub fn at(self, location: &Location) -> Self {
+        match self {
+            ValueParseError::Located { .. } => self,
+            e => ValueParseError::Located {
+                location: Box::new(location.clone()),
+                source: Box::new(e),
+            },
+        }
+    }
+
     pub fn assert_length(expected: usize, actual: usize) -> Result<(), Self> {
         if expected != actual {
             Err(ValueParseError::InvalidLength { expected, actual })
@@ -0,0 +1,631 @@
+//! Evaluating node graphs on the CPU
+//!
+//! An [`Evaluator`] lowers a document into a [`NodeGraph`] once, and compiles
+//! nodegraph outputs or node inputs into [`Program`]s: the nodes they depend
+//! on, in the order they need to run. A program can then be evaluated at any
+//! number of points described by a [`ShadingContext`].
+//!
+//! The math, logic, and vector nodes of the standard library are built in
+//! (see [`Evaluator::supports`]). Other nodes, like texture lookups, can be
+//! added with [`Evaluator::register`]. Inputs that are not set use the
+//! defaults of the standard library; apply a
+//! [`NodeDefRegistry`](crate::nodedef::NodeDefRegistry) first to use the
+//! defaults of other definitions.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{
+//!     data_types::{DataTypeAndValue, Vector2},
+//!     eval::ShadingContext,
+//!     MaterialX,
+//! };
+//!
+//! let mat = MaterialX::from_str(r#"<materialx version="1.39">
+//!   <nodegraph name="NG">
+//!     <texcoord name="uv" type="vector2" />
+//!     <multiply name="scaled" type="vector2">
+//!       <input name="in1" type="vector2" nodename="uv" />
+//!       <input name="in2" type="float" value="4" />
+//!     </multiply>
+//!     <output name="out" type="vector2" nodename="scaled" />
+//!   </nodegraph>
+//! </materialx>"#)?;
+//! let ctx = ShadingContext {
+//!     uv: Vector2([0.25, 0.5]),
+//!     ..Default::default()
+//! };
+//! let value = mat.evaluate("NG", "out", &ctx)?;
+//! assert!(matches!(value, DataTypeAndValue::Vector2(Vector2([1.0, 2.0]))));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{
+    data_types::{DataType, DataTypeAndValue, Vector2, Vector3},
+    graph::{GraphError, NodeGraph, NodeId},
+    nodes::{AccessError, ResolvedInput},
+    MaterialX,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+mod nodes;
+
+/// Where on a surface a graph is evaluated
+#[derive(Debug, Clone)]
+pub struct ShadingContext {
+    /// Texture coordinates (`texcoord`)
+    pub uv: Vector2,
+    /// Object space position (`position`)
+    pub position: Vector3,
+    /// Object space normal (`normal`)
+    pub normal: Vector3,
+    /// Object space tangent (`tangent`)
+    pub tangent: Vector3,
+    /// Time in seconds (`time`)
+    pub time: f64,
+}
+
+impl Default for ShadingContext {
+    fn default() -> Self {
+        ShadingContext {
+            uv: Vector2([0.0, 0.0]),
+            position: Vector3([0.0, 0.0, 0.0]),
+            normal: Vector3([0.0, 0.0, 1.0]),
+            tangent: Vector3([1.0, 0.0, 0.0]),
+            time: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EvalError {
+    #[error("Failed to build node graph")]
+    Graph(#[from] GraphError),
+    #[error("Failed to resolve output")]
+    Access(#[from] AccessError),
+    #[error("Node `{node}` has category `{category}`, which can't be evaluated")]
+    Unsupported { node: SmolStr, category: SmolStr },
+    #[error("Input `{input}` of node `{node}` has type `{found}`, expected {expected}")]
+    InvalidInput {
+        node: SmolStr,
+        input: SmolStr,
+        expected: &'static str,
+        found: DataType,
+    },
+    #[error("Node `{node}` has output type `{found}`, expected {expected}")]
+    InvalidOutput {
+        node: SmolStr,
+        expected: &'static str,
+        found: DataType,
+    },
+    #[error("Node `{node}` depends on itself")]
+    Cycle { node: SmolStr },
+}
+
+/// Implementation of a node category added with [`Evaluator::register`]
+pub type NodeFn =
+    dyn Fn(&Inputs, &ShadingContext) -> Result<DataTypeAndValue, EvalError> + Send + Sync;
+
+/// Compiles parts of a document into [`Program`]s
+#[derive(Clone)]
+pub struct Evaluator {
+    graph: NodeGraph,
+    /// What nodegraph outputs are connected to, by nodegraph and output name
+    outputs: IndexMap<(SmolStr, SmolStr), Source>,
+    custom: HashMap<SmolStr, Arc<NodeFn>>,
+}
+
+impl fmt::Debug for Evaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Evaluator")
+            .field("graph", &self.graph)
+            .field("outputs", &self.outputs)
+            .field("custom", &self.custom.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Value of a port: a constant or the result of an earlier step
+#[derive(Debug, Clone)]
+enum Source {
+    Value(DataTypeAndValue),
+    Node { id: NodeId, output: Option<SmolStr> },
+}
+
+impl Evaluator {
+    pub fn new(doc: &MaterialX) -> Result<Self, EvalError> {
+        let graph = NodeGraph::try_from(doc)?;
+
+        let mut outputs = IndexMap::new();
+        for nodegraph in doc.tags("nodegraph") {
+            for output in nodegraph.children.values().filter(|c| c.tag == "output") {
+                let source = match doc.resolve(nodegraph, Some(nodegraph), output.name.clone()) {
+                    Ok(ResolvedInput::Value(value)) => Source::Value(value),
+                    Ok(ResolvedInput::Node(node)) => Source::Node {
+                        id: graph
+                            .find(node.nodegraph.as_deref(), &node.element.name)
+                            .ok_or_else(|| GraphError::UnknownNode {
+                                name: node.element.name.clone(),
+                            })?,
+                        output: node.output,
+                    },
+                    // Outputs of functional nodegraphs passing through an
+                    // interface input without a default only make sense
+                    // together with a node instance
+                    Err(e) if nodegraph.attributes.contains_key("nodedef") && no_default(&e) => {
+                        continue
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                outputs.insert((nodegraph.name.clone(), output.name.clone()), source);
+            }
+        }
+
+        Ok(Evaluator {
+            graph,
+            outputs,
+            custom: HashMap::new(),
+        })
+    }
+
+    pub fn graph(&self) -> &NodeGraph {
+        &self.graph
+    }
+
+    /// Evaluate nodes of `category` with `f`
+    ///
+    /// Takes precedence over the built-in implementation, if any.
+    pub fn register(
+        &mut self,
+        category: impl Into<SmolStr>,
+        f: impl Fn(&Inputs, &ShadingContext) -> Result<DataTypeAndValue, EvalError>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.custom.insert(category.into(), Arc::new(f));
+    }
+
+    /// Whether nodes of `category` can be evaluated
+    pub fn supports(&self, category: &str) -> bool {
+        self.custom.contains_key(category) || nodes::BUILTIN.contains(&category)
+    }
+
+    /// Compile the output `output` of the nodegraph `nodegraph`
+    pub fn output(&self, nodegraph: &str, output: &str) -> Result<Program<'_>, EvalError> {
+        let source = self
+            .outputs
+            .get(&(SmolStr::from(nodegraph), SmolStr::from(output)))
+            .ok_or_else(|| AccessError::NotFound {
+                name: output.into(),
+                parent: nodegraph.into(),
+                location: Box::default(),
+            })?;
+        self.compile(source.clone())
+    }
+
+    /// Compile the input `input` of a node
+    ///
+    /// Returns a constant program if the input has a value, and a program
+    /// returning `None` from [`Program::eval`] if it isn't set.
+    pub fn input(&self, node: NodeId, input: &str) -> Result<Program<'_>, EvalError> {
+        if let Some(edge) = self
+            .graph
+            .upstream(node)
+            .find(|edge| edge.to_input == input)
+        {
+            return self.compile(Source::Node {
+                id: edge.from,
+                output: edge.from_output.clone(),
+            });
+        }
+        match self
+            .graph
+            .node(node)
+            .input(input)
+            .and_then(|p| p.value.clone())
+        {
+            Some(value) => self.compile(Source::Value(value)),
+            None => Ok(Program {
+                evaluator: self,
+                steps: Vec::new(),
+                result: None,
+            }),
+        }
+    }
+
+    /// Compile the output of a node
+    ///
+    /// `output` selects one output of nodes with multiple outputs (e.g.
+    /// `outr` of `separate3`).
+    pub fn node(&self, id: NodeId, output: Option<&str>) -> Result<Program<'_>, EvalError> {
+        self.compile(Source::Node {
+            id,
+            output: output.map(SmolStr::from),
+        })
+    }
+
+    fn compile(&self, result: Source) -> Result<Program<'_>, EvalError> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            New,
+            Visiting,
+            Done(usize),
+        }
+
+        fn visit(
+            evaluator: &Evaluator,
+            id: NodeId,
+            state: &mut [State],
+            steps: &mut Vec<Step>,
+        ) -> Result<usize, EvalError> {
+            match state[id.index()] {
+                State::Done(step) => return Ok(step),
+                State::Visiting => {
+                    return Err(EvalError::Cycle {
+                        node: evaluator.graph.node(id).name.clone(),
+                    })
+                }
+                State::New => {}
+            }
+            state[id.index()] = State::Visiting;
+
+            let node = evaluator.graph.node(id);
+            if !evaluator.supports(&node.category) {
+                return Err(EvalError::Unsupported {
+                    node: node.name.clone(),
+                    category: node.category.clone(),
+                });
+            }
+            let mut ports = Vec::with_capacity(node.inputs.len());
+            for port in &node.inputs {
+                let source = match evaluator
+                    .graph
+                    .upstream(id)
+                    .find(|edge| edge.to_input == port.name)
+                {
+                    Some(edge) => StepSource::Step {
+                        step: visit(evaluator, edge.from, state, steps)?,
+                        output: edge.from_output.clone(),
+                    },
+                    None => match &port.value {
+                        Some(value) => StepSource::Value(value.clone()),
+                        None => continue,
+                    },
+                };
+                ports.push((port.name.clone(), source));
+            }
+
+            steps.push(Step { id, ports });
+            state[id.index()] = State::Done(steps.len() - 1);
+            Ok(steps.len() - 1)
+        }
+
+        let mut steps = Vec::new();
+        let result = match result {
+            Source::Value(value) => StepSource::Value(value),
+            Source::Node { id, output } => {
+                let mut state = vec![State::New; self.graph.len()];
+                StepSource::Step {
+                    step: visit(self, id, &mut state, &mut steps)?,
+                    output,
+                }
+            }
+        };
+        Ok(Program {
+            evaluator: self,
+            steps,
+            result: Some(result),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum StepSource {
+    Value(DataTypeAndValue),
+    Step {
+        step: usize,
+        output: Option<SmolStr>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    id: NodeId,
+    ports: Vec<(SmolStr, StepSource)>,
+}
+
+/// A compiled output, see [`Evaluator`]
+#[derive(Debug, Clone)]
+pub struct Program<'e> {
+    evaluator: &'e Evaluator,
+    /// Nodes to evaluate, each after the nodes it depends on
+    steps: Vec<Step>,
+    result: Option<StepSource>,
+}
+
+impl Program<'_> {
+    /// Evaluate the program at one point
+    pub fn eval(&self, ctx: &ShadingContext) -> Result<Option<DataTypeAndValue>, EvalError> {
+        let mut values: Vec<DataTypeAndValue> = Vec::with_capacity(self.steps.len());
+        for step in &self.steps {
+            let node = self.evaluator.graph.node(step.id);
+            let ports = step
+                .ports
+                .iter()
+                .map(|(name, source)| (name, source.get(&values)))
+                .collect::<Vec<_>>();
+            let inputs = Inputs { node, ports };
+            let value = match self.evaluator.custom.get(&node.category) {
+                Some(f) => f(&inputs, ctx)?,
+                None => nodes::eval(&inputs, ctx)?,
+            };
+            values.push(value);
+        }
+        Ok(self.result.as_ref().map(|result| result.get(&values)))
+    }
+
+    /// Whether the result is the same at every point
+    ///
+    /// This is the case when no node reads from the shading context.
+    /// Nodes added with [`Evaluator::register`] are assumed to do so.
+    pub fn is_constant(&self) -> bool {
+        self.steps.iter().all(|step| {
+            let category = &self.evaluator.graph.node(step.id).category;
+            !self.evaluator.custom.contains_key(category)
+                && !nodes::VARYING.contains(&category.as_str())
+        })
+    }
+}
+
+impl StepSource {
+    fn get(&self, values: &[DataTypeAndValue]) -> DataTypeAndValue {
+        match self {
+            StepSource::Value(value) => value.clone(),
+            StepSource::Step { step, output } => {
+                let value = &values[*step];
+                match output {
+                    Some(output) => nodes::select_output(value, output),
+                    None => value.clone(),
+                }
+            }
+        }
+    }
+}
+
+/// Inputs of a node while it is evaluated
+#[derive(Debug)]
+pub struct Inputs<'a> {
+    pub node: &'a crate::graph::GraphNode,
+    ports: Vec<(&'a SmolStr, DataTypeAndValue)>,
+}
+
+impl Inputs<'_> {
+    /// Value of an input, `None` if it isn't set
+    pub fn get(&self, name: &str) -> Option<&DataTypeAndValue> {
+        self.ports
+            .iter()
+            .find(|(n, _)| n.as_str() == name)
+            .map(|(_, v)| v)
+    }
+
+    /// Value of a float input, or `default` if it isn't set
+    pub fn float(&self, name: &str, default: f64) -> Result<f64, EvalError> {
+        match self.get(name) {
+            None => Ok(default),
+            Some(DataTypeAndValue::Float(x)) => Ok(*x),
+            Some(DataTypeAndValue::Integer(x)) => Ok(*x as f64),
+            Some(other) => Err(self.invalid(name, "a float", other)),
+        }
+    }
+
+    fn invalid(&self, input: &str, expected: &'static str, found: &DataTypeAndValue) -> EvalError {
+        EvalError::InvalidInput {
+            node: self.node.name.clone(),
+            input: input.into(),
+            expected,
+            found: found.tag(),
+        }
+    }
+}
+
+impl MaterialX {
+    /// Evaluate the output `output` of the nodegraph `nodegraph` at one point
+    ///
+    /// To evaluate at many points, create an [`Evaluator`] and compile the
+    /// output once instead.
+    pub fn evaluate(
+        &self,
+        nodegraph: &str,
+        output: &str,
+        ctx: &ShadingContext,
+    ) -> Result<DataTypeAndValue, EvalError> {
+        let evaluator = Evaluator::new(self)?;
+        let program = evaluator.output(nodegraph, output)?;
+        Ok(program
+            .eval(ctx)?
+            .expect("outputs are always connected or have a value"))
+    }
+}
+
+/// Whether `err` is about a nodedef input without a default value
+fn no_default(err: &AccessError) -> bool {
+    match err {
+        AccessError::InputMissingData { .. } => true,
+        AccessError::ConversionError { source, .. }
+        | AccessError::SubElementAccess { source, .. } => no_default(source),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr as _;
+
+    fn float(value: DataTypeAndValue) -> f64 {
+        match value {
+            DataTypeAndValue::Float(x) => x,
+            other => panic!("expected float, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn math() {
+        let mat = MaterialX::from_str(
+            r#"<materialx version="1.39">
+  <nodegraph name="NG">
+    <input name="scale" type="float" value="2" />
+    <texcoord name="uv" type="vector2" />
+    <separate2 name="split" type="multioutput">
+      <input name="in" type="vector2" nodename="uv" />
+    </separate2>
+    <multiply name="mul" type="float">
+      <input name="in1" type="float" nodename="split" output="outy" />
+      <input name="in2" type="float" interfacename="scale" />
+    </multiply>
+    <power name="pow" type="float">
+      <input name="in1" type="float" nodename="mul" />
+      <input name="in2" type="float" value="2" />
+    </power>
+    <clamp name="clamped" type="float">
+      <input name="in" type="float" nodename="pow" />
+    </clamp>
+    <combine3 name="vec" type="vector3">
+      <input name="in1" type="float" nodename="pow" />
+      <input name="in3" type="float" value="1" />
+    </combine3>
+    <normalize name="norm" type="vector3">
+      <input name="in" type="vector3" nodename="vec" />
+    </normalize>
+    <dotproduct name="dot" type="float">
+      <input name="in1" type="vector3" nodename="norm" />
+      <input name="in2" type="vector3" value="0, 0, 1" />
+    </dotproduct>
+    <output name="out_pow" type="float" nodename="pow" />
+    <output name="out_clamped" type="float" nodename="clamped" />
+    <output name="out_dot" type="float" nodename="dot" />
+  </nodegraph>
+</materialx>"#,
+        )
+        .unwrap();
+        let evaluator = Evaluator::new(&mat).unwrap();
+        let pow = evaluator.output("NG", "out_pow").unwrap();
+        assert!(!pow.is_constant());
+
+        let ctx = ShadingContext {
+            uv: Vector2([0.0, 0.75]),
+            ..Default::default()
+        };
+        assert_eq!(float(pow.eval(&ctx).unwrap().unwrap()), 2.25);
+        let clamped = evaluator.output("NG", "out_clamped").unwrap();
+        assert_eq!(float(clamped.eval(&ctx).unwrap().unwrap()), 1.0);
+        let dot = evaluator.output("NG", "out_dot").unwrap();
+        let expected = 1.0 / (2.25f64 * 2.25 + 1.0).sqrt();
+        assert!((float(dot.eval(&ctx).unwrap().unwrap()) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn place2d() {
+        let mat = MaterialX::from_str(
+            r#"<materialx version="1.39">
+  <nodegraph name="NG">
+    <texcoord name="uv" type="vector2" />
+    <place2d name="placed" type="vector2">
+      <input name="texcoord" type="vector2" nodename="uv" />
+      <input name="pivot" type="vector2" value="0.5, 0.5" />
+      <input name="scale" type="vector2" value="2, 2" />
+      <input name="rotate" type="float" value="90" />
+      <input name="offset" type="vector2" value="0.1, 0" />
+    </place2d>
+    <output name="out" type="vector2" nodename="placed" />
+  </nodegraph>
+</materialx>"#,
+        )
+        .unwrap();
+        let evaluator = Evaluator::new(&mat).unwrap();
+        let ctx = ShadingContext {
+            uv: Vector2([0.75, 0.5]),
+            ..Default::default()
+        };
+        let value = evaluator.output("NG", "out").unwrap().eval(&ctx).unwrap();
+        let Some(DataTypeAndValue::Vector2(uv)) = value else {
+            panic!("expected vector2, got {value:?}");
+        };
+        // (0.25, 0) / 2, rotated to (0, -0.125), offset and moved back
+        for (found, expected) in uv.0.into_iter().zip([0.4, 0.375]) {
+            assert!((found - expected).abs() < 1e-12, "{uv:?}");
+        }
+    }
+
+    #[test]
+    fn marble() {
+        let xml = std::fs::read_to_string(
+            "../assets/materialx-examples/StandardSurface/standard_surface_marble_solid.mtlx",
+        )
+        .unwrap();
+        let mat = MaterialX::from_str(&xml).unwrap();
+        let evaluator = Evaluator::new(&mat).unwrap();
+        assert!(matches!(
+            evaluator.output("NG_marble1", "out"),
+            Err(EvalError::Unsupported { category, .. }) if category == "fractal3d"
+        ));
+
+        // Replace the noise with a constant
+        let mut evaluator = evaluator;
+        evaluator.register("fractal3d", |_, _| Ok(DataTypeAndValue::Float(0.0)));
+        let program = evaluator.output("NG_marble1", "out").unwrap();
+        let ctx = ShadingContext::default();
+        // sin(0) * 0.5 + 0.5 = 0.5, to the power of 3
+        let DataTypeAndValue::Color3(color) = program.eval(&ctx).unwrap().unwrap() else {
+            panic!("expected color");
+        };
+        let mix = 0.125;
+        for (i, (bg, fg)) in [(0.8, 0.1), (0.8, 0.1), (0.8, 0.3)].into_iter().enumerate() {
+            assert!((color.0[i] - (bg * (1.0 - mix) + fg * mix)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn constant_folding() {
+        let xml = std::fs::read_to_string(
+            "../assets/materialx-examples/StandardSurface/standard_surface_marble_solid.mtlx",
+        )
+        .unwrap();
+        let mat = MaterialX::from_str(&xml).unwrap();
+        let evaluator = Evaluator::new(&mat).unwrap();
+        let surface = evaluator.graph().find(None, "SR_marble1").unwrap();
+        let roughness = evaluator.input(surface, "specular_roughness").unwrap();
+        assert!(roughness.is_constant());
+        let value = roughness.eval(&ShadingContext::default()).unwrap();
+        assert!(matches!(value, Some(DataTypeAndValue::Float(x)) if x == 0.1));
+        let missing = evaluator.input(surface, "thin_walled").unwrap();
+        assert!(missing.eval(&ShadingContext::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn output_errors() {
+        let xml = r#"<materialx version="1.39">
+  <nodedef name="ND_f" node="f">
+    <input name="in" type="float" />
+    <output name="out" type="float" />
+  </nodedef>
+  <nodegraph name="NG_f" nodedef="ND_f">
+    <output name="out" type="float" interfacename="in" />
+  </nodegraph>
+</materialx>"#;
+        let eval = Evaluator::new(&MaterialX::from_str(xml).unwrap()).unwrap();
+        assert!(eval.output("NG_f", "out").is_err());
+
+        let xml = xml.replace(r#"interfacename="in""#, r#"value="one""#);
+        let err = Evaluator::new(&MaterialX::from_str(&xml).unwrap()).unwrap_err();
+        assert!(
+            matches!(err, EvalError::Access(AccessError::ValueParseError { .. })),
+            "{err:?}"
+        );
+    }
+}
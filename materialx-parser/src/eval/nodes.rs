@@ -0,0 +1,507 @@
+//! Built-in node implementations
+
+use super::{EvalError, Inputs, ShadingContext};
+use crate::data_types::{DataType, DataTypeAndValue, Vector2, Vector3, Vector4};
+
+/// Node categories [`eval`] implements
+pub(super) const BUILTIN: &[&str] = &[
+    // Math
+    "constant",
+    "add",
+    "subtract",
+    "multiply",
+    "divide",
+    "modulo",
+    "invert",
+    "absval",
+    "sign",
+    "floor",
+    "ceil",
+    "round",
+    "power",
+    "safepower",
+    "sin",
+    "cos",
+    "tan",
+    "asin",
+    "acos",
+    "atan2",
+    "sqrt",
+    "ln",
+    "exp",
+    "clamp",
+    "min",
+    "max",
+    "trianglewave",
+    "mix",
+    "smoothstep",
+    "remap",
+    // Logic
+    "and",
+    "or",
+    "xor",
+    "not",
+    "ifgreater",
+    "ifgreatereq",
+    "ifequal",
+    // Vectors
+    "normalize",
+    "magnitude",
+    "distance",
+    "dotproduct",
+    "crossproduct",
+    "convert",
+    "combine2",
+    "combine3",
+    "combine4",
+    "separate2",
+    "separate3",
+    "separate4",
+    "extract",
+    "dot",
+    "rotate2d",
+    "place2d",
+    // Color
+    "hsvtorgb",
+    "rgbtohsv",
+    "luminance",
+    // Shading context
+    "texcoord",
+    "position",
+    "normal",
+    "tangent",
+    "time",
+];
+
+/// Built-in node categories that read from the [`ShadingContext`]
+///
+/// `place2d` reads the texture coordinates when its `texcoord` isn't set.
+pub(super) const VARYING: &[&str] = &[
+    "texcoord", "position", "normal", "tangent", "time", "place2d",
+];
+
+/// Components of a numeric value
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Lanes {
+    v: [f64; 4],
+    n: usize,
+}
+
+impl Lanes {
+    fn splat(x: f64) -> Self {
+        Lanes { v: [x; 4], n: 1 }
+    }
+
+    fn new<const N: usize>(v: [f64; N]) -> Self {
+        let mut res = Lanes { v: [0.0; 4], n: N };
+        res.v[..N].copy_from_slice(&v);
+        res
+    }
+
+    fn from_value(value: &DataTypeAndValue) -> Option<Self> {
+        Some(match value {
+            DataTypeAndValue::Integer(x) => Lanes::splat(*x as f64),
+            DataTypeAndValue::Boolean(x) => Lanes::splat(if *x { 1.0 } else { 0.0 }),
+            DataTypeAndValue::Float(x) => Lanes::splat(*x),
+            DataTypeAndValue::Vector2(v) => Lanes::new(v.0),
+            DataTypeAndValue::Vector3(v) | DataTypeAndValue::Color3(v) => Lanes::new(v.0),
+            DataTypeAndValue::Vector4(v) | DataTypeAndValue::Color4(v) => Lanes::new(v.0),
+            _ => return None,
+        })
+    }
+
+    /// Component `i`, repeating scalars
+    fn get(&self, i: usize) -> f64 {
+        if self.n == 1 {
+            self.v[0]
+        } else {
+            self.v[i]
+        }
+    }
+
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        Lanes {
+            v: self.v.map(f),
+            n: self.n,
+        }
+    }
+
+    fn zip(self, other: Lanes, f: impl Fn(f64, f64) -> f64) -> Self {
+        let n = self.n.max(other.n);
+        let mut v = [0.0; 4];
+        for (i, x) in v.iter_mut().enumerate().take(n) {
+            *x = f(self.get(i), other.get(i));
+        }
+        Lanes { v, n }
+    }
+
+    fn dot(self, other: Lanes) -> f64 {
+        (0..self.n.max(other.n))
+            .map(|i| self.get(i) * other.get(i))
+            .sum()
+    }
+
+    fn into_value(self, r#type: &DataType, node: &Inputs) -> Result<DataTypeAndValue, EvalError> {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| self.get(i));
+        Ok(match r#type {
+            DataType::Float => DataTypeAndValue::Float(x),
+            DataType::Integer => DataTypeAndValue::Integer(x.round().max(0.0) as u64),
+            DataType::Boolean => DataTypeAndValue::Boolean(x != 0.0),
+            DataType::Vector2 => DataTypeAndValue::Vector2(Vector2([x, y])),
+            DataType::Vector3 => DataTypeAndValue::Vector3(Vector3([x, y, z])),
+            DataType::Color3 => DataTypeAndValue::Color3(Vector3([x, y, z])),
+            DataType::Vector4 => DataTypeAndValue::Vector4(Vector4([x, y, z, w])),
+            DataType::Color4 => DataTypeAndValue::Color4(Vector4([x, y, z, w])),
+            other => {
+                return Err(EvalError::InvalidOutput {
+                    node: node.node.name.clone(),
+                    expected: "a numeric type",
+                    found: other.clone(),
+                })
+            }
+        })
+    }
+}
+
+impl Inputs<'_> {
+    fn lanes(&self, name: &str, default: f64) -> Result<Lanes, EvalError> {
+        match self.get(name) {
+            None => Ok(Lanes::splat(default)),
+            Some(value) => {
+                Lanes::from_value(value).ok_or_else(|| self.invalid(name, "a numeric type", value))
+            }
+        }
+    }
+
+    fn bool(&self, name: &str) -> Result<bool, EvalError> {
+        Ok(self.lanes(name, 0.0)?.v[0] != 0.0)
+    }
+}
+
+/// Evaluate a built-in node
+pub(super) fn eval(inputs: &Inputs, ctx: &ShadingContext) -> Result<DataTypeAndValue, EvalError> {
+    let node = inputs.node;
+    let unary =
+        |f: fn(f64) -> f64| -> Result<Lanes, EvalError> { Ok(inputs.lanes("in", 0.0)?.map(f)) };
+    let binary = |default: f64, f: fn(f64, f64) -> f64| -> Result<Lanes, EvalError> {
+        Ok(inputs
+            .lanes("in1", 0.0)?
+            .zip(inputs.lanes("in2", default)?, f))
+    };
+
+    let res = match node.category.as_str() {
+        "constant" | "dot" => {
+            return Ok(inputs
+                .get(if node.category == "dot" {
+                    "in"
+                } else {
+                    "value"
+                })
+                .cloned()
+                .unwrap_or(DataTypeAndValue::Float(0.0)))
+        }
+
+        "add" => binary(0.0, |a, b| a + b)?,
+        "subtract" => binary(0.0, |a, b| a - b)?,
+        "multiply" => binary(1.0, |a, b| a * b)?,
+        "divide" => binary(1.0, |a, b| a / b)?,
+        "modulo" => binary(1.0, |a, b| a - b * (a / b).floor())?,
+        "invert" => inputs
+            .lanes("amount", 1.0)?
+            .zip(inputs.lanes("in", 0.0)?, |amount, x| amount - x),
+        "absval" => unary(f64::abs)?,
+        "sign" => unary(|x| if x == 0.0 { 0.0 } else { x.signum() })?,
+        "floor" => unary(f64::floor)?,
+        "ceil" => unary(f64::ceil)?,
+        "round" => unary(f64::round)?,
+        "power" => binary(1.0, f64::powf)?,
+        "safepower" => binary(1.0, |a, b| a.abs().powf(b).copysign(a))?,
+        "sin" => unary(f64::sin)?,
+        "cos" => unary(f64::cos)?,
+        "tan" => unary(f64::tan)?,
+        "asin" => unary(f64::asin)?,
+        "acos" => unary(f64::acos)?,
+        "atan2" => inputs
+            .lanes("iny", 0.0)?
+            .zip(inputs.lanes("inx", 1.0)?, f64::atan2),
+        "sqrt" => unary(f64::sqrt)?,
+        "ln" => inputs.lanes("in", 1.0)?.map(f64::ln),
+        "exp" => unary(f64::exp)?,
+        "clamp" => inputs
+            .lanes("in", 0.0)?
+            .zip(inputs.lanes("low", 0.0)?, f64::max)
+            .zip(inputs.lanes("high", 1.0)?, f64::min),
+        "min" => binary(0.0, f64::min)?,
+        "max" => binary(0.0, f64::max)?,
+        "trianglewave" => unary(|x| 2.0 * (x - (x + 0.5).floor()).abs())?,
+        "mix" => {
+            let mix = inputs.lanes("mix", 0.0)?;
+            let bg = inputs.lanes("bg", 0.0)?;
+            let fg = inputs.lanes("fg", 0.0)?;
+            let n = bg.n.max(fg.n);
+            let mut res = Lanes { v: [0.0; 4], n };
+            for i in 0..n {
+                res.v[i] = bg.get(i) * (1.0 - mix.get(i)) + fg.get(i) * mix.get(i);
+            }
+            res
+        }
+        "smoothstep" => {
+            let low = inputs.lanes("low", 0.0)?;
+            let high = inputs.lanes("high", 1.0)?;
+            let x = inputs.lanes("in", 0.0)?;
+            let t = x
+                .zip(low, |x, low| x - low)
+                .zip(high.zip(low, |high, low| high - low), |a, b| {
+                    (a / b).clamp(0.0, 1.0)
+                });
+            t.map(|t| t * t * (3.0 - 2.0 * t))
+        }
+        "remap" => {
+            let x = inputs.lanes("in", 0.0)?;
+            let in_low = inputs.lanes("inlow", 0.0)?;
+            let in_high = inputs.lanes("inhigh", 1.0)?;
+            let out_low = inputs.lanes("outlow", 0.0)?;
+            let out_high = inputs.lanes("outhigh", 1.0)?;
+            let n = x.n;
+            let mut res = Lanes { v: [0.0; 4], n };
+            for i in 0..n {
+                let t = (x.get(i) - in_low.get(i)) / (in_high.get(i) - in_low.get(i));
+                res.v[i] = out_low.get(i) + t * (out_high.get(i) - out_low.get(i));
+            }
+            res
+        }
+
+        "and" => Lanes::splat((inputs.bool("in1")? && inputs.bool("in2")?) as u8 as f64),
+        "or" => Lanes::splat((inputs.bool("in1")? || inputs.bool("in2")?) as u8 as f64),
+        "xor" => Lanes::splat((inputs.bool("in1")? != inputs.bool("in2")?) as u8 as f64),
+        "not" => Lanes::splat((!inputs.bool("in")?) as u8 as f64),
+        "ifgreater" | "ifgreatereq" | "ifequal" => {
+            let value1 = inputs.float("value1", 1.0)?;
+            let value2 = inputs.float("value2", 0.0)?;
+            let condition = match node.category.as_str() {
+                "ifgreater" => value1 > value2,
+                "ifgreatereq" => value1 >= value2,
+                _ => value1 == value2,
+            };
+            if matches!(node.r#type, DataType::Boolean) && inputs.get("in1").is_none() {
+                Lanes::splat(condition as u8 as f64)
+            } else {
+                inputs.lanes(if condition { "in1" } else { "in2" }, 0.0)?
+            }
+        }
+
+        "normalize" => {
+            let x = inputs.lanes("in", 0.0)?;
+            let length = x.dot(x).sqrt();
+            if length > 0.0 {
+                x.map(|c| c / length)
+            } else {
+                x
+            }
+        }
+        "magnitude" => {
+            let x = inputs.lanes("in", 0.0)?;
+            Lanes::splat(x.dot(x).sqrt())
+        }
+        "distance" => {
+            let d = binary(0.0, |a, b| a - b)?;
+            Lanes::splat(d.dot(d).sqrt())
+        }
+        "dotproduct" => {
+            let a = inputs.lanes("in1", 0.0)?;
+            Lanes::splat(a.dot(inputs.lanes("in2", 0.0)?))
+        }
+        "crossproduct" => {
+            let a = inputs.lanes("in1", 0.0)?;
+            let b = inputs.lanes("in2", 0.0)?;
+            let [ax, ay, az] = [0, 1, 2].map(|i| a.get(i));
+            let [bx, by, bz] = [0, 1, 2].map(|i| b.get(i));
+            Lanes::new([ay * bz - az * by, az * bx - ax * bz, ax * by - ay * bx])
+        }
+        "convert" => {
+            let x = inputs.lanes("in", 0.0)?;
+            // Converting to a type with an alpha channel fills it with 1
+            if x.n == 3 && matches!(node.r#type, DataType::Color4 | DataType::Vector4) {
+                Lanes::new([x.v[0], x.v[1], x.v[2], 1.0])
+            } else {
+                x
+            }
+        }
+        "combine2" | "combine3" | "combine4" => {
+            let count = (node.category.as_bytes()[7] - b'0') as usize;
+            let mut res = Lanes { v: [0.0; 4], n: 0 };
+            // e.g. combine2 of a color3 and a float makes a color4
+            for name in &["in1", "in2", "in3", "in4"][..count] {
+                let part = inputs.lanes(name, 0.0)?;
+                for &c in &part.v[..part.n] {
+                    if res.n < 4 {
+                        res.v[res.n] = c;
+                        res.n += 1;
+                    }
+                }
+            }
+            res
+        }
+        // The value of separate nodes is their input, the outputs are
+        // picked in `select_output`
+        "separate2" | "separate3" | "separate4" => {
+            return inputs
+                .get("in")
+                .cloned()
+                .ok_or_else(|| EvalError::InvalidInput {
+                    node: node.name.clone(),
+                    input: "in".into(),
+                    expected: "a vector",
+                    found: DataType::Unknown(String::new()),
+                })
+        }
+        "extract" => {
+            let x = inputs.lanes("in", 0.0)?;
+            let index = inputs.float("index", 0.0)? as usize;
+            Lanes::splat(x.v[index.min(3)])
+        }
+        "rotate2d" => {
+            let [x, y] = rotate2d(inputs.lanes("in", 0.0)?, inputs.float("amount", 0.0)?);
+            Lanes::new([x, y])
+        }
+        "place2d" => {
+            let uv = match inputs.get("texcoord") {
+                Some(_) => inputs.lanes("texcoord", 0.0)?,
+                None => Lanes::new(ctx.uv.0),
+            };
+            let pivot = inputs.lanes("pivot", 0.0)?;
+            let scale = inputs.lanes("scale", 1.0)?;
+            let rotate = inputs.float("rotate", 0.0)?;
+            let offset = inputs.lanes("offset", 0.0)?;
+            let centered = uv.zip(pivot, |a, b| a - b);
+            // 0 scales, rotates and then offsets, 1 does the opposite
+            let placed = if inputs.float("operationorder", 0.0)? == 0.0 {
+                let scaled = centered.zip(scale, |a, b| a / b);
+                Lanes::new(rotate2d(scaled, rotate)).zip(offset, |a, b| a - b)
+            } else {
+                let offset = centered.zip(offset, |a, b| a - b);
+                Lanes::new(rotate2d(offset, rotate)).zip(scale, |a, b| a / b)
+            };
+            placed.zip(pivot, |a, b| a + b)
+        }
+
+        "hsvtorgb" => {
+            let x = inputs.lanes("in", 0.0)?;
+            let [r, g, b] = hsv_to_rgb([x.v[0], x.v[1], x.v[2]]);
+            Lanes {
+                v: [r, g, b, x.v[3]],
+                n: x.n,
+            }
+        }
+        "rgbtohsv" => {
+            let x = inputs.lanes("in", 0.0)?;
+            let [h, s, v] = rgb_to_hsv([x.v[0], x.v[1], x.v[2]]);
+            Lanes {
+                v: [h, s, v, x.v[3]],
+                n: x.n,
+            }
+        }
+        "luminance" => {
+            let x = inputs.lanes("in", 0.0)?;
+            let coeffs = match inputs.get("lumacoeffs") {
+                Some(_) => inputs.lanes("lumacoeffs", 0.0)?,
+                None => Lanes::new([0.2722287, 0.6740818, 0.0536895]),
+            };
+            let y = Lanes::new([x.v[0], x.v[1], x.v[2]]).dot(coeffs);
+            Lanes {
+                v: [y, y, y, x.v[3]],
+                n: x.n,
+            }
+        }
+
+        "texcoord" => Lanes::new(ctx.uv.0),
+        "position" => Lanes::new(ctx.position.0),
+        "normal" => Lanes::new(ctx.normal.0),
+        "tangent" => Lanes::new(ctx.tangent.0),
+        "time" => Lanes::splat(ctx.time),
+
+        _ => {
+            return Err(EvalError::Unsupported {
+                node: node.name.clone(),
+                category: node.category.clone(),
+            })
+        }
+    };
+    res.into_value(&node.r#type, inputs)
+}
+
+/// Pick one output of a node with multiple outputs
+pub(super) fn select_output(value: &DataTypeAndValue, output: &str) -> DataTypeAndValue {
+    let component = match output {
+        "outx" | "outr" => 0,
+        "outy" | "outg" => 1,
+        "outz" | "outb" => 2,
+        "outw" | "outa" => 3,
+        _ => return value.clone(),
+    };
+    match Lanes::from_value(value) {
+        Some(lanes) if component < lanes.n => DataTypeAndValue::Float(lanes.v[component]),
+        _ => value.clone(),
+    }
+}
+
+/// Rotate `v` by `degrees`, like MaterialX's reference implementation
+fn rotate2d(v: Lanes, degrees: f64) -> [f64; 2] {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let [x, y] = [v.get(0), v.get(1)];
+    [cos * x + sin * y, -sin * x + cos * y]
+}
+
+fn hsv_to_rgb([h, s, v]: [f64; 3]) -> [f64; 3] {
+    let h = (h - h.floor()) * 6.0;
+    let i = h.floor();
+    let f = h - i;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    match i as u8 {
+        0 => [v, t, p],
+        1 => [q, v, p],
+        2 => [p, v, t],
+        3 => [p, q, v],
+        4 => [t, p, v],
+        _ => [v, p, q],
+    }
+}
+
+fn rgb_to_hsv([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let s = if max > 0.0 { delta / max } else { 0.0 };
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    [h / 6.0, s, max]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hsv_round_trip() {
+        for rgb in [
+            [0.2, 0.4, 0.6],
+            [1.0, 0.0, 0.0],
+            [0.5, 0.5, 0.5],
+            [0.9, 0.7, 0.1],
+        ] {
+            let back = hsv_to_rgb(rgb_to_hsv(rgb));
+            for i in 0..3 {
+                assert!((back[i] - rgb[i]).abs() < 1e-12, "{rgb:?} -> {back:?}");
+            }
+        }
+    }
+}
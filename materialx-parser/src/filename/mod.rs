@@ -0,0 +1,416 @@
+//! File names (`fileprefix`, tokens and UDIMs)
+//!
+//! A `filename` value is appended to the `fileprefix` declared closest to it:
+//! on the `<input>` itself, on the node or nodegraph containing it, or on the
+//! document. It may contain tokens:
+//!
+//! - `[name]` stands for the value of the `<token>` named `name` of the node
+//!   or nodegraph the input is in,
+//! - `<UDIM>` and `<UVTILE>` stand for one tile of a texture set spread over
+//!   several UV tiles (see [`Udim`]),
+//! - other `<name>` tokens are geometry tokens, whose values are given per
+//!   geometry by `<geominfo>` elements (see [`MaterialX::geom_tokens`]).
+//!
+//! [`MaterialX::resolve`] prepends the `fileprefix` and replaces `[name]`
+//! tokens; the others depend on what is rendered and are left to the caller.
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{data_types::DataTypeAndValue, filename::Udim, nodes::ResolvedInput, MaterialX};
+//!
+//! let mat = MaterialX::from_str(r#"
+//!     <materialx version="1.39" fileprefix="textures/">
+//!       <nodegraph name="graph">
+//!         <token name="part" type="string" value="body" />
+//!         <image name="color" type="color3">
+//!           <input name="file" type="filename" value="[part]_color.&lt;UDIM&gt;.png" />
+//!         </image>
+//!       </nodegraph>
+//!     </materialx>
+//! "#)?;
+//! let graph = mat.element("graph")?;
+//! let ResolvedInput::Value(DataTypeAndValue::Filename(file)) =
+//!     mat.resolve(&graph.children["color"], Some(graph), "file".into())?
+//! else {
+//!     unreachable!()
+//! };
+//! assert_eq!(file, "textures/body_color.<UDIM>.png");
+//! let tile = Udim::from_tile(1, 0).unwrap();
+//! assert_eq!(tile.filename(&file), "textures/body_color.1002.png");
+//! # Ok::<(), materialx_parser::Error>(())
+//! ```
+
+use crate::{
+    builder::element, data_types::DataTypeAndValue, look::geom_matches, Element, MaterialX,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+use std::fmt;
+
+/// A UV tile, numbered like UDIM texture sets do
+///
+/// Tile `1001` covers UVs from `(0, 0)` to `(1, 1)`, numbers go up by one
+/// along `u` and by ten along `v`, so there are ten tiles per row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Udim(u32);
+
+impl Udim {
+    /// The tile covering UVs `0..1`
+    pub const FIRST: Udim = Udim(1001);
+    /// Tiles are numbered with four digits
+    pub const MAX: Udim = Udim(9999);
+
+    /// `None` for numbers that aren't UDIMs
+    pub fn new(number: u32) -> Option<Self> {
+        (Self::FIRST.0..=Self::MAX.0)
+            .contains(&number)
+            .then_some(Udim(number))
+    }
+
+    /// The tile at column `u` and row `v`
+    pub fn from_tile(u: u32, v: u32) -> Option<Self> {
+        if u >= 10 {
+            return None;
+        }
+        Self::new(v.checked_mul(10)?.checked_add(Self::FIRST.0 + u)?)
+    }
+
+    pub fn number(self) -> u32 {
+        self.0
+    }
+
+    /// Column and row of the tile
+    pub fn tile(self) -> (u32, u32) {
+        let i = self.0 - Self::FIRST.0;
+        (i % 10, i / 10)
+    }
+
+    /// `pattern` with `<UDIM>` and `<UVTILE>` replaced by this tile
+    ///
+    /// `<UVTILE>` is the Mudbox convention, `u1_v1` for the first tile.
+    pub fn filename(self, pattern: &str) -> String {
+        let (u, v) = self.tile();
+        pattern
+            .replace("<UDIM>", &self.0.to_string())
+            .replace("<UVTILE>", &format!("u{}_v{}", u + 1, v + 1))
+    }
+
+    /// The first tiles in order, `rows` rows of ten, e.g. to look for the
+    /// files of a set that has no [`udimset`](MaterialX::udimset)
+    pub fn candidates(rows: u32) -> impl Iterator<Item = Udim> {
+        (0..rows).flat_map(|v| (0..10).filter_map(move |u| Udim::from_tile(u, v)))
+    }
+}
+
+impl fmt::Display for Udim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Whether `filename` names a set of UV tiles rather than one file
+pub fn has_udim(filename: &str) -> bool {
+    filename.contains("<UDIM>") || filename.contains("<UVTILE>")
+}
+
+/// Replace the geometry tokens in `filename` with `tokens`
+///
+/// `<UDIM>`, `<UVTILE>` and tokens missing from `tokens` are kept.
+pub fn substitute_geom_tokens(filename: &str, tokens: &IndexMap<SmolStr, SmolStr>) -> String {
+    substitute(filename, ('<', '>'), |name| {
+        tokens
+            .get(name)
+            .filter(|_| !matches!(name, "UDIM" | "UVTILE"))
+            .cloned()
+    })
+}
+
+/// Replace all `{open}name{close}` in `text` for which `value` returns a value
+fn substitute(
+    text: &str,
+    (open, close): (char, char),
+    value: impl Fn(&str) -> Option<SmolStr>,
+) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(open) {
+        let Some(len) = rest[start + 1..].find(close) else {
+            break;
+        };
+        let name = &rest[start + 1..start + 1 + len];
+        res.push_str(&rest[..start]);
+        match value(name) {
+            Some(value) => res.push_str(&value),
+            None => res.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    res.push_str(rest);
+    res
+}
+
+impl MaterialX {
+    /// The `fileprefix` declared closest to the innermost of `scope`
+    ///
+    /// Like [`MaterialX::colorspace_in`], `scope` lists elements from the
+    /// inside out, and the document's prefix applies if none of them has one.
+    pub fn fileprefix_in<'a>(&'a self, scope: impl IntoIterator<Item = &'a Element>) -> &'a str {
+        scope
+            .into_iter()
+            .find_map(|element| element.attributes.get("fileprefix"))
+            .or_else(|| self.attributes.get("fileprefix"))
+            .map_or("", |prefix| prefix.as_str())
+    }
+
+    /// `filename` read in `scope`, with its `fileprefix` prepended and
+    /// `[name]` tokens replaced
+    ///
+    /// Tokens are looked up in the `<token>` children of `scope`, innermost
+    /// first.
+    pub fn filename_in<'a>(
+        &'a self,
+        filename: &str,
+        scope: impl IntoIterator<Item = &'a Element> + Clone,
+    ) -> String {
+        let filename = substitute(filename, ('[', ']'), |name| {
+            scope.clone().into_iter().find_map(|element| {
+                element
+                    .children
+                    .get(name)
+                    .filter(|token| token.tag == "token")
+                    .and_then(|token| token.attributes.get("value"))
+                    .cloned()
+            })
+        });
+        format!("{}{filename}", self.fileprefix_in(scope))
+    }
+
+    /// Resolve every `filename` value of the document with
+    /// [`MaterialX::filename_in`] and remove the `fileprefix` attributes
+    ///
+    /// The result doesn't depend on where values are read anymore, so they
+    /// can be used as they are, e.g. when they are rewritten.
+    pub fn flatten_filenames(&mut self) {
+        let mut resolved = Vec::new();
+        for element in self.elements.values() {
+            self.collect_filenames(element, &mut vec![element], &mut resolved);
+        }
+        for (path, value) in resolved {
+            let mut element = self.elements.get_mut(&path[0]);
+            for name in &path[1..] {
+                element = element.and_then(|e| e.children.get_mut(name));
+            }
+            if let Some(element) = element {
+                element.attributes.insert("value".into(), value.into());
+            }
+        }
+
+        fn remove_prefix(element: &mut Element) {
+            element.attributes.shift_remove("fileprefix");
+            element.children.values_mut().for_each(remove_prefix);
+        }
+        self.attributes.shift_remove("fileprefix");
+        self.elements.values_mut().for_each(remove_prefix);
+    }
+
+    /// Resolved values of the filename inputs in `scope` (innermost last),
+    /// by the names of the elements leading to them
+    fn collect_filenames<'a>(
+        &'a self,
+        element: &'a Element,
+        scope: &mut Vec<&'a Element>,
+        resolved: &mut Vec<(Vec<SmolStr>, String)>,
+    ) {
+        if element.tag == "input"
+            && element
+                .attributes
+                .get("type")
+                .is_some_and(|t| t == "filename")
+        {
+            if let Some(value) = element.attributes.get("value") {
+                let path = scope.iter().map(|e| e.name.clone()).collect();
+                resolved.push((path, self.filename_in(value, scope.iter().rev().copied())));
+            }
+        }
+        for child in element.children.values() {
+            scope.push(child);
+            self.collect_filenames(child, scope, resolved);
+            scope.pop();
+        }
+    }
+
+    /// Values of the geometry tokens for the geometry at `path`
+    ///
+    /// These are the `<token>` children of the `<geominfo>` elements whose
+    /// `geom` pattern matches `path` (see [`geom_matches`]), later ones
+    /// taking precedence.
+    pub fn geom_tokens(&self, path: &str) -> IndexMap<SmolStr, SmolStr> {
+        let mut tokens = IndexMap::new();
+        for geominfo in self.geominfos() {
+            let applies = geominfo
+                .attributes
+                .get("geom")
+                .is_none_or(|geom| geom_matches(geom, path));
+            if !applies {
+                continue;
+            }
+            for token in geominfo.children.values().filter(|e| e.tag == "token") {
+                if let Some(value) = token.attributes.get("value") {
+                    tokens.insert(token.name.clone(), value.clone());
+                }
+            }
+        }
+        tokens
+    }
+
+    /// The UV tiles listed by the `udimset` geomprops of the document, in
+    /// order, or `None` if there are none
+    pub fn udimset(&self) -> Option<Vec<Udim>> {
+        let mut udims = Vec::new();
+        let mut found = false;
+        for geominfo in self.geominfos() {
+            let Some(udimset) = geominfo.children.get("udimset") else {
+                continue;
+            };
+            let Some(value) = udimset.attributes.get("value") else {
+                continue;
+            };
+            found = true;
+            if let Ok(DataTypeAndValue::StringArray(numbers)) =
+                DataTypeAndValue::from_tag_and_value("stringarray", value)
+            {
+                udims.extend(
+                    numbers
+                        .iter()
+                        .filter_map(|n| Udim::new(n.trim().parse().ok()?)),
+                );
+            }
+        }
+        udims.sort();
+        udims.dedup();
+        found.then_some(udims)
+    }
+
+    /// Record `udims` in a `udimset` geomprop of a new `<geominfo>` named
+    /// `name`, e.g. after looking for the tiles of a set on disk
+    pub fn add_udimset(&mut self, name: &str, udims: &[Udim]) {
+        let value = udims
+            .iter()
+            .map(Udim::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut geominfo = element("geominfo", name, []);
+        let mut udimset = element(
+            "geomprop",
+            "udimset",
+            [("type", "stringarray"), ("value", &value)],
+        );
+        udimset.location = geominfo.location.child("udimset");
+        geominfo.children.insert(udimset.name.clone(), udimset);
+        self.elements.insert(geominfo.name.clone(), geominfo);
+    }
+
+    fn geominfos(&self) -> impl Iterator<Item = &Element> {
+        self.elements.values().filter(|e| e.tag == "geominfo")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr as _;
+
+    #[test]
+    fn udim_tiles() {
+        assert_eq!(Udim::FIRST.tile(), (0, 0));
+        assert_eq!(Udim::new(1012).unwrap().tile(), (1, 1));
+        assert_eq!(Udim::from_tile(9, 2), Udim::new(1030));
+        assert_eq!(Udim::from_tile(10, 0), None);
+        assert_eq!(Udim::new(1000), None);
+        assert_eq!(
+            Udim::new(1012).unwrap().filename("a.<UDIM>.png"),
+            "a.1012.png"
+        );
+        assert_eq!(
+            Udim::new(1012).unwrap().filename("a_<UVTILE>.png"),
+            "a_u2_v2.png"
+        );
+        assert_eq!(Udim::candidates(2).count(), 20);
+    }
+
+    #[test]
+    fn geom_tokens() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <geominfo name="all">
+                <token name="side" type="string" value="left" />
+              </geominfo>
+              <geominfo name="body" geom="/car/body">
+                <token name="part" type="string" value="body" />
+                <geomprop name="udimset" type="stringarray" value="1002, 1001, 1011" />
+              </geominfo>
+            </materialx>
+            "#,
+        )
+        .unwrap();
+        let tokens = mat.geom_tokens("/car/body");
+        assert_eq!(
+            substitute_geom_tokens("<part>_<side>.<UDIM>.<ext>", &tokens),
+            "body_left.<UDIM>.<ext>"
+        );
+        assert!(!mat.geom_tokens("/car/wheel").contains_key("part"));
+        assert_eq!(
+            mat.udimset().unwrap(),
+            [1001, 1002, 1011].map(|n| Udim::new(n).unwrap())
+        );
+    }
+
+    #[test]
+    fn add_udimset() {
+        let mut mat = MaterialX::from_str(
+            r#"<materialx version="1.39"><geominfo name="empty" /></materialx>"#,
+        )
+        .unwrap();
+        assert_eq!(mat.udimset(), None);
+        let udims = [Udim::FIRST, Udim::from_tile(0, 1).unwrap()];
+        mat.add_udimset("found", &udims);
+        assert_eq!(mat.udimset().unwrap(), udims);
+    }
+
+    #[test]
+    fn flatten() {
+        let mut mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39" fileprefix="root/">
+              <nodegraph name="graph" fileprefix="graph/">
+                <token name="kind" type="string" value="color" />
+                <image name="a" type="color3">
+                  <input name="file" type="filename" value="[kind].png" />
+                </image>
+                <image name="b" type="color3">
+                  <input name="file" type="filename" value="[other].png" fileprefix="" />
+                </image>
+              </nodegraph>
+              <image name="c" type="color3">
+                <input name="file" type="filename" value="c.png" />
+              </image>
+            </materialx>
+            "#,
+        )
+        .unwrap();
+        mat.flatten_filenames();
+        let value = |path: &[&str]| {
+            let mut element = &mat.elements[path[0]];
+            for name in &path[1..] {
+                element = &element.children[*name];
+            }
+            element.attributes["value"].clone()
+        };
+        assert_eq!(value(&["graph", "a", "file"]), "graph/color.png");
+        assert_eq!(value(&["graph", "b", "file"]), "[other].png");
+        assert_eq!(value(&["c", "file"]), "root/c.png");
+        assert!(!mat.attributes.contains_key("fileprefix"));
+        assert!(!mat.elements["graph"].attributes.contains_key("fileprefix"));
+    }
+}
@@ -0,0 +1,313 @@
+//! Typed node graph
+//!
+//! The [`Element`](crate::Element) tree mirrors the XML and refers to
+//! connections by name. [`NodeGraph`] lowers a document into an arena of
+//! nodes with typed ports and explicit edges, so that consumers can walk
+//! connections without looking up names again.
+//!
+//! Nodegraph outputs and interface inputs are resolved while lowering: an
+//! edge always connects two nodes directly, and interface inputs with
+//! constant values end up as values on the input ports that use them.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{graph::NodeGraph, MaterialX};
+//! # let xml = include_str!(
+//! #   "../../../assets/materialx-examples/StandardSurface/standard_surface_brick_procedural.mtlx"
+//! # );
+//! let mat = MaterialX::from_str(xml)?;
+//! let graph = NodeGraph::try_from(&mat)?;
+//! let material = graph.find(None, "M_BrickPattern").unwrap();
+//! assert_eq!(graph.upstream(material).count(), 1);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{
+    ast::ColorSpace,
+    data_types::{DataType, DataTypeAndValue},
+    nodes::{AccessError, ResolvedInput},
+    Element, GetByTypeAndName as _, Input, MaterialX,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+
+mod topo;
+
+/// Stable identifier of a node in a [`NodeGraph`]
+///
+/// Nodes are numbered in document order, so the same document always
+/// produces the same IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub id: NodeId,
+    /// Node category, i.e. the element's tag (e.g. `multiply`)
+    pub category: SmolStr,
+    pub name: SmolStr,
+    /// Name of the nodegraph the node is defined in (`None` for the root)
+    pub nodegraph: Option<SmolStr>,
+    /// Output type of the node
+    pub r#type: DataType,
+    pub inputs: Vec<Port>,
+}
+
+impl GraphNode {
+    pub fn input(&self, name: &str) -> Option<&Port> {
+        self.inputs.iter().find(|port| port.name == name)
+    }
+}
+
+/// A typed input of a node
+#[derive(Debug, Clone)]
+pub struct Port {
+    pub name: SmolStr,
+    pub r#type: DataType,
+    /// Constant value, if the input is not connected to another node
+    ///
+    /// Colors are in linear Rec.709.
+    pub value: Option<DataTypeAndValue>,
+    /// Color space declared for the input, or for the node, nodegraph or
+    /// document containing it
+    ///
+    /// Values have already been converted, this tells how to decode the
+    /// images read by `file` inputs.
+    pub colorspace: Option<ColorSpace>,
+}
+
+/// Connection from the output of one node to the input of another
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: NodeId,
+    /// Selected output, for nodes with multiple outputs
+    pub from_output: Option<SmolStr>,
+    pub to: NodeId,
+    pub to_input: SmolStr,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeGraph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<Edge>,
+    by_name: IndexMap<(Option<SmolStr>, SmolStr), NodeId>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum GraphError {
+    #[error("Failed to resolve input `{input}` of node `{node}`")]
+    Input {
+        node: SmolStr,
+        input: SmolStr,
+        source: Box<AccessError>,
+    },
+    #[error("Node `{name}` is connected but not part of the graph")]
+    UnknownNode { name: SmolStr },
+    #[error("Graph contains a cycle through {} nodes", nodes.len())]
+    Cycle { nodes: Vec<NodeId> },
+}
+
+impl NodeGraph {
+    pub fn node(&self, id: NodeId) -> &GraphNode {
+        &self.nodes[id.index()]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &GraphNode> {
+        self.nodes.iter()
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Find a node by name, in a nodegraph or (with `None`) the document root
+    pub fn find(&self, nodegraph: Option<&str>, name: &str) -> Option<NodeId> {
+        self.by_name
+            .get(&(nodegraph.map(SmolStr::from), SmolStr::from(name)))
+            .copied()
+    }
+
+    /// Edges going into the inputs of `id`
+    pub fn upstream(&self, id: NodeId) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.to == id)
+    }
+
+    /// Edges going out of `id` to the inputs of other nodes
+    pub fn downstream(&self, id: NodeId) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == id)
+    }
+
+    fn add_node(&mut self, element: &Element, nodegraph: Option<&SmolStr>) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(GraphNode {
+            id,
+            category: element.tag.clone(),
+            name: element.name.clone(),
+            nodegraph: nodegraph.cloned(),
+            r#type: data_type(element.attributes.get("type")),
+            inputs: Vec::new(),
+        });
+        self.by_name
+            .insert((nodegraph.cloned(), element.name.clone()), id);
+        id
+    }
+
+    fn connect_inputs(
+        &mut self,
+        def: &MaterialX,
+        id: NodeId,
+        element: &Element,
+        parent: Option<&Element>,
+    ) -> Result<(), GraphError> {
+        for input in element.children.values().filter(|e| e.tag == "input") {
+            let error = |source| GraphError::Input {
+                node: element.name.clone(),
+                input: input.name.clone(),
+                source: Box::new(source),
+            };
+            let port = element.get::<Input>(input.name.clone()).map_err(error)?;
+            let mut port = Port {
+                name: port.name,
+                r#type: data_type(Some(&port.r#type)),
+                value: None,
+                colorspace: def.colorspace_in([input, element].into_iter().chain(parent)),
+            };
+            match def
+                .resolve(element, parent, input.name.clone())
+                .map_err(error)?
+            {
+                ResolvedInput::Value(value) => port.value = Some(value),
+                ResolvedInput::Node(upstream) => {
+                    let from = self
+                        .by_name
+                        .get(&(upstream.nodegraph.clone(), upstream.element.name.clone()))
+                        .copied()
+                        .ok_or_else(|| GraphError::UnknownNode {
+                            name: upstream.element.name.clone(),
+                        })?;
+                    self.edges.push(Edge {
+                        from,
+                        from_output: upstream.output,
+                        to: id,
+                        to_input: port.name.clone(),
+                    });
+                }
+            }
+            self.nodes[id.index()].inputs.push(port);
+        }
+        Ok(())
+    }
+}
+
+fn data_type(tag: Option<&SmolStr>) -> DataType {
+    match tag {
+        Some(tag) => tag.parse().unwrap_or(DataType::Unknown(tag.to_string())),
+        None => DataType::Unknown(String::new()),
+    }
+}
+
+impl TryFrom<&MaterialX> for NodeGraph {
+    type Error = GraphError;
+
+    fn try_from(def: &MaterialX) -> Result<Self, Self::Error> {
+        let mut graph = NodeGraph::default();
+
+        // Collect all nodes first so connections can refer to nodes defined
+        // later in the document
+        let mut pending = Vec::new();
+        for element in def.elements.values() {
+            if element.tag == "nodegraph" {
+                for child in element.children.values().filter(|e| e.is_node()) {
+                    let id = graph.add_node(child, Some(&element.name));
+                    pending.push((id, child, Some(element)));
+                }
+            } else if element.is_node() {
+                let id = graph.add_node(element, None);
+                pending.push((id, element, None));
+            }
+        }
+
+        for (id, element, parent) in pending {
+            graph.connect_inputs(def, id, element, parent)?;
+        }
+
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr as _;
+
+    fn load(name: &str) -> NodeGraph {
+        let file = std::fs::read_to_string(format!(
+            "../assets/materialx-examples/StandardSurface/{name}"
+        ))
+        .unwrap();
+        let mat = MaterialX::from_str(&file).unwrap();
+        NodeGraph::try_from(&mat).unwrap()
+    }
+
+    #[test]
+    fn chess_set() {
+        let graph = load("standard_surface_chess_set.mtlx");
+        let surface = graph.find(None, "Bishop_B").unwrap();
+        let normal = graph
+            .upstream(surface)
+            .find(|edge| edge.to_input == "normal")
+            .unwrap();
+        let normalmap = graph.node(normal.from);
+        assert_eq!(normalmap.category, "normalmap");
+        assert_eq!(normalmap.nodegraph.as_deref(), Some("NG_BishopBlack"));
+        assert!(matches!(normalmap.r#type, DataType::Vector3));
+
+        let material = graph.find(None, "M_Bishop_B").unwrap();
+        assert_eq!(
+            graph.downstream(surface).map(|e| e.to).collect::<Vec<_>>(),
+            [material]
+        );
+    }
+
+    #[test]
+    fn interface_values() {
+        let graph = load("standard_surface_brick_procedural.mtlx");
+        let node = graph
+            .find(Some("NG_BrickPattern"), "node_multiply_14")
+            .unwrap();
+        let port = graph.node(node).input("in2").unwrap();
+        assert!(matches!(port.value, Some(DataTypeAndValue::Float(x)) if x == 0.083));
+    }
+
+    #[test]
+    fn all_examples() {
+        for example in glob::glob("../assets/**/*.mtlx").unwrap() {
+            let path = example.unwrap();
+            let Ok(mat) = MaterialX::from_str(&std::fs::read_to_string(&path).unwrap()) else {
+                continue;
+            };
+            let graph =
+                NodeGraph::try_from(&mat).unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+            graph
+                .topological_order()
+                .unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+        }
+    }
+}
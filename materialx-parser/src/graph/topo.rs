@@ -0,0 +1,128 @@
+use super::{GraphError, NodeGraph, NodeId};
+use std::collections::VecDeque;
+
+impl NodeGraph {
+    /// All nodes ordered so that every node comes after the nodes it depends on
+    ///
+    /// Nodes without dependencies between them stay in document order. Fails
+    /// with [`GraphError::Cycle`] listing the nodes of one cycle if the graph
+    /// is not acyclic.
+    pub fn topological_order(&self) -> Result<Vec<NodeId>, GraphError> {
+        let mut in_degree = vec![0usize; self.nodes.len()];
+        for edge in &self.edges {
+            in_degree[edge.to.index()] += 1;
+        }
+
+        let mut queue = self
+            .nodes
+            .iter()
+            .filter(|node| in_degree[node.id.index()] == 0)
+            .map(|node| node.id)
+            .collect::<VecDeque<_>>();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(id) = queue.pop_front() {
+            order.push(id);
+            for edge in self.downstream(id) {
+                let degree = &mut in_degree[edge.to.index()];
+                *degree -= 1;
+                if *degree == 0 {
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+
+        if order.len() == self.nodes.len() {
+            return Ok(order);
+        }
+
+        // Every node left over still has an unvisited upstream node, so
+        // walking upstream from any of them has to run into a cycle
+        let start = in_degree.iter().position(|&d| d > 0).unwrap();
+        let mut path = vec![NodeId(start as u32)];
+        loop {
+            let current = *path.last().unwrap();
+            let next = self
+                .upstream(current)
+                .map(|edge| edge.from)
+                .find(|from| in_degree[from.index()] > 0)
+                .unwrap();
+            if let Some(pos) = path.iter().position(|&id| id == next) {
+                let mut nodes = path.split_off(pos);
+                nodes.reverse();
+                return Err(GraphError::Cycle { nodes });
+            }
+            path.push(next);
+        }
+    }
+
+    /// Whether the graph contains no cycles
+    pub fn is_acyclic(&self) -> bool {
+        self.topological_order().is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MaterialX;
+    use std::str::FromStr as _;
+
+    #[test]
+    fn order() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+                <add name="b" type="float">
+                    <input name="in1" type="float" nodename="a" />
+                    <input name="in2" type="float" value="1" />
+                </add>
+                <constant name="a" type="float">
+                    <input name="value" type="float" value="1" />
+                </constant>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        let graph = NodeGraph::try_from(&mat).unwrap();
+        let names = graph
+            .topological_order()
+            .unwrap()
+            .into_iter()
+            .map(|id| graph.node(id).name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn cycle() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+                <add name="a" type="float">
+                    <input name="in1" type="float" nodename="b" />
+                </add>
+                <add name="b" type="float">
+                    <input name="in1" type="float" nodename="a" />
+                </add>
+                <add name="c" type="float">
+                    <input name="in1" type="float" nodename="b" />
+                </add>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        let graph = NodeGraph::try_from(&mat).unwrap();
+        assert!(!graph.is_acyclic());
+        let Err(GraphError::Cycle { mut nodes }) = graph.topological_order() else {
+            panic!("expected cycle");
+        };
+        nodes.sort();
+        assert_eq!(
+            nodes,
+            [
+                graph.find(None, "a").unwrap(),
+                graph.find(None, "b").unwrap()
+            ]
+        );
+    }
+}
@@ -1,8 +1,17 @@
 #![doc = include_str!("../README.md")]
 
 pub mod ast;
+pub mod builder;
+pub mod color;
 pub mod data_types;
+pub mod eval;
+pub mod filename;
+pub mod graph;
+pub mod look;
+pub mod nodedef;
 pub mod nodes;
+pub mod validate;
+pub mod variant;
 
 pub use ast::{Element, MaterialX};
 pub use nodes::{AccessError, GetAllByType, GetByTypeAndName, Input, InputData, Node};
@@ -16,8 +25,10 @@ pub enum Error {
     Xml(#[from] roxmltree::Error),
     #[error("Failed to build structure from AST")]
     Ast(#[from] ast::AstError),
-    #[error("Include elements are not supported yet")]
+    #[error("Include elements are only supported when loading with a file resolver")]
     IncludesNotSupported,
+    #[error("Failed to resolve include")]
+    Include(#[from] ast::IncludeError),
     #[error("Failed to access element")]
     Get(#[from] AccessError),
 }
@@ -89,13 +100,15 @@ mod tests {
                 let name = path.file_name().unwrap().to_str().unwrap().to_string();
                 let xml = std::fs::read_to_string(path).unwrap();
 
-                match MaterialX::from_str(&xml) {
+                let res = match MaterialX::from_str(&xml) {
+                    Err(Error::IncludesNotSupported) => {
+                        MaterialX::load_with_resolver(path.to_str().unwrap(), &ast::FsResolver)
+                    }
+                    res => res,
+                };
+                match res {
                     Ok(_) => println!("{name}: Success"),
                     Err(e) => {
-                        if matches!(e, Error::IncludesNotSupported) {
-                            println!("{name}: Includes not supported");
-                            continue;
-                        }
                         eprintln!("{name}: Failed {e:?}");
                         failed += 1;
                     }
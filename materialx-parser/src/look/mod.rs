@@ -0,0 +1,322 @@
+//! Looks and material assignments (`<look>`, `<materialassign>`, `<collection>`)
+//!
+//! A look assigns materials to parts of a scene by geometry path, like
+//! `/car/body` or `/car/wheel_*`. A path pattern matches the geometry it names
+//! and everything below it. Patterns starting with `/` are matched from the
+//! root of the scene, others at any depth, and `*` matches any characters
+//! within one path segment. Several patterns can be given separated by commas.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{look::Look, GetByTypeAndName as _, MaterialX};
+//!
+//! let mat = MaterialX::from_str(r#"
+//!     <materialx version="1.39">
+//!       <look name="showroom">
+//!         <materialassign name="paint" geom="/car/body" material="M_paint" />
+//!         <materialassign name="rims" geom="wheel_*" material="M_chrome" />
+//!       </look>
+//!     </materialx>
+//! "#)?;
+//! let look = mat.get::<Look>("showroom".into())?;
+//! assert_eq!(look.material_for(&mat, "/car/body/door").as_deref(), Some("M_paint"));
+//! assert_eq!(look.material_for(&mat, "/car/wheel_fl").as_deref(), Some("M_chrome"));
+//! assert_eq!(look.material_for(&mat, "/car/seat"), None);
+//! # Ok::<(), materialx_parser::Error>(())
+//! ```
+
+use crate::{
+    nodes::{check_tag, AccessError},
+    Element, GetAllByType as _, MaterialX, Node,
+};
+use smol_str::SmolStr;
+
+/// How deep we follow look inheritance and nested collections
+const MAX_DEPTH: usize = 32;
+
+/// A `<look>` element
+#[derive(Debug, Clone)]
+pub struct Look {
+    pub name: SmolStr,
+    /// Look whose assignments apply where this one has none
+    pub inherit: Option<SmolStr>,
+    pub material_assigns: Vec<MaterialAssign>,
+}
+
+/// A `<materialassign>` element
+#[derive(Debug, Clone)]
+pub struct MaterialAssign {
+    pub name: SmolStr,
+    pub material: SmolStr,
+    /// Geometry path patterns, see the [module documentation](self)
+    pub geom: Option<SmolStr>,
+    /// Collection of geometry, in addition to `geom`
+    pub collection: Option<SmolStr>,
+}
+
+/// A `<collection>` element
+#[derive(Debug, Clone)]
+pub struct Collection {
+    pub name: SmolStr,
+    pub include_geom: Option<SmolStr>,
+    pub exclude_geom: Option<SmolStr>,
+    /// Comma-separated names of other collections to include
+    pub include_collection: Option<SmolStr>,
+}
+
+impl Look {
+    /// Name of the material assigned to the geometry at `path`
+    ///
+    /// The first matching assignment wins, then those of inherited looks.
+    pub fn material_for(&self, def: &MaterialX, path: &str) -> Option<SmolStr> {
+        let mut look = self.clone();
+        for _ in 0..MAX_DEPTH {
+            if let Some(assign) = look
+                .material_assigns
+                .iter()
+                .find(|assign| assign.matches(def, path))
+            {
+                return Some(assign.material.clone());
+            }
+            look = def.look(look.inherit.as_ref()?).ok()?;
+        }
+        None
+    }
+}
+
+impl MaterialAssign {
+    /// Whether the assignment applies to the geometry at `path`
+    ///
+    /// Assignments without `geom` and `collection` apply to everything.
+    pub fn matches(&self, def: &MaterialX, path: &str) -> bool {
+        match (&self.geom, &self.collection) {
+            (None, None) => true,
+            (geom, collection) => {
+                geom.as_ref().is_some_and(|geom| geom_matches(geom, path))
+                    || collection.as_ref().is_some_and(|name| {
+                        def.collection(name)
+                            .is_ok_and(|collection| collection.contains(def, path))
+                    })
+            }
+        }
+    }
+}
+
+impl Collection {
+    /// Whether the geometry at `path` is part of this collection
+    pub fn contains(&self, def: &MaterialX, path: &str) -> bool {
+        self.contains_at(def, path, 0)
+    }
+
+    fn contains_at(&self, def: &MaterialX, path: &str, depth: usize) -> bool {
+        if depth > MAX_DEPTH {
+            return false;
+        }
+        if self
+            .exclude_geom
+            .as_ref()
+            .is_some_and(|geom| geom_matches(geom, path))
+        {
+            return false;
+        }
+        self.include_geom
+            .as_ref()
+            .is_some_and(|geom| geom_matches(geom, path))
+            || self.include_collection.iter().any(|names| {
+                names.split(',').any(|name| {
+                    def.collection(name.trim())
+                        .is_ok_and(|collection| collection.contains_at(def, path, depth + 1))
+                })
+            })
+    }
+}
+
+/// Whether one of the comma-separated patterns in `patterns` matches `path`
+/// or one of its ancestors
+pub fn geom_matches(patterns: &str, path: &str) -> bool {
+    let path = segments(path).collect::<Vec<_>>();
+    patterns.split(',').map(str::trim).any(|pattern| {
+        let absolute = pattern.starts_with('/');
+        let pattern = segments(pattern).collect::<Vec<_>>();
+        if pattern.is_empty() {
+            // `/` is the root of everything
+            return absolute;
+        }
+        let starts = if absolute { 0..1 } else { 0..path.len() };
+        starts.into_iter().any(|start| {
+            path.len() >= start + pattern.len()
+                && pattern
+                    .iter()
+                    .zip(&path[start..])
+                    .all(|(pattern, segment)| glob(pattern.as_bytes(), segment.as_bytes()))
+        })
+    })
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+/// Match `text` against `pattern`, where `*` matches any characters
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        Some((c, rest)) => text
+            .split_first()
+            .is_some_and(|(t, text)| t == c && glob(rest, text)),
+    }
+}
+
+impl Node for Look {
+    const ELEMENT_NAME: Option<&'static str> = Some("look");
+
+    fn from_element(element: &Element) -> Result<Self, AccessError> {
+        check_tag(element, "look")?;
+        let material_assigns = element
+            .children
+            .values()
+            .filter(|e| e.tag == "materialassign")
+            .map(MaterialAssign::from_element)
+            .collect::<Result<_, _>>()?;
+        Ok(Look {
+            name: element.name.clone(),
+            inherit: element.attr("inherit").ok(),
+            material_assigns,
+        })
+    }
+}
+
+impl Node for MaterialAssign {
+    const ELEMENT_NAME: Option<&'static str> = Some("materialassign");
+
+    fn from_element(element: &Element) -> Result<Self, AccessError> {
+        check_tag(element, "materialassign")?;
+        Ok(MaterialAssign {
+            name: element.name.clone(),
+            material: element.attr("material")?,
+            geom: element.attr("geom").ok(),
+            collection: element.attr("collection").ok(),
+        })
+    }
+}
+
+impl Node for Collection {
+    const ELEMENT_NAME: Option<&'static str> = Some("collection");
+
+    fn from_element(element: &Element) -> Result<Self, AccessError> {
+        check_tag(element, "collection")?;
+        Ok(Collection {
+            name: element.name.clone(),
+            include_geom: element.attr("includegeom").ok(),
+            exclude_geom: element.attr("excludegeom").ok(),
+            include_collection: element.attr("includecollection").ok(),
+        })
+    }
+}
+
+impl MaterialX {
+    /// All looks contained in this document
+    pub fn looks(&self) -> impl Iterator<Item = Look> + '_ {
+        self.all::<Look>()
+    }
+
+    pub fn look(&self, name: &str) -> Result<Look, AccessError> {
+        Look::from_element(self.element(name)?)
+    }
+
+    pub fn collection(&self, name: &str) -> Result<Collection, AccessError> {
+        Collection::from_element(self.element(name)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::FsResolver;
+    use std::str::FromStr as _;
+
+    #[test]
+    fn patterns() {
+        assert!(geom_matches("/a/b", "/a/b"));
+        assert!(geom_matches("/a/b", "/a/b/c"));
+        assert!(!geom_matches("/a/b", "/a/bc"));
+        assert!(!geom_matches("/b", "/a/b"));
+        assert!(geom_matches("b", "/a/b/c"));
+        assert!(geom_matches("/a/*/c", "/a/xyz/c"));
+        assert!(geom_matches("/a/b*", "/a/bc"));
+        assert!(!geom_matches("/a/b*d", "/a/bc"));
+        assert!(geom_matches("/x, /a", "/a/b"));
+        assert!(geom_matches("/", "/a"));
+    }
+
+    #[test]
+    fn example_look() {
+        let mat = MaterialX::load_with_resolver(
+            "../assets/materialx-examples/StandardSurface/standard_surface_look_wood_tiled.mtlx",
+            &FsResolver,
+        )
+        .unwrap();
+        let looks = mat.looks().collect::<Vec<_>>();
+        assert_eq!(looks.len(), 1);
+        let look = &looks[0];
+        assert_eq!(look.material_assigns.len(), 2);
+        assert_eq!(
+            look.material_for(&mat, "/Preview_Mesh").as_deref(),
+            Some("Tiled_Wood")
+        );
+        assert_eq!(
+            look.material_for(&mat, "/Scene/Calibration_Mesh/Primitive0")
+                .as_deref(),
+            Some("Greysphere_Calibration")
+        );
+    }
+
+    #[test]
+    fn collections_and_inheritance() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <collection name="wheels" includegeom="/car/wheel_*" excludegeom="/car/wheel_spare" />
+              <collection name="all_wheels" includecollection="wheels" />
+              <look name="base">
+                <materialassign name="default" material="M_grey" />
+              </look>
+              <look name="showroom" inherit="base">
+                <materialassign name="rims" collection="all_wheels" material="M_chrome" />
+              </look>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        let look = mat.look("showroom").unwrap();
+        assert_eq!(
+            look.material_for(&mat, "/car/wheel_fl").as_deref(),
+            Some("M_chrome")
+        );
+        assert_eq!(
+            look.material_for(&mat, "/car/wheel_spare").as_deref(),
+            Some("M_grey")
+        );
+        assert_eq!(
+            look.material_for(&mat, "/car/body").as_deref(),
+            Some("M_grey")
+        );
+    }
+
+    #[test]
+    fn inheritance_cycle() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <look name="a" inherit="b" />
+              <look name="b" inherit="a" />
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        assert_eq!(mat.look("a").unwrap().material_for(&mat, "/x"), None);
+    }
+}
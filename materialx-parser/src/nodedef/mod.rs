@@ -0,0 +1,490 @@
+//! Node definitions (`<nodedef>`)
+//!
+//! MaterialX describes the signature of every node in `<nodedef>` elements,
+//! and ships definitions for all standard nodes in its `libraries` folder
+//! (`stdlib`, `pbrlib`, `bxdf`). A [`NodeDefRegistry`] collects these
+//! definitions so node instances can be matched to them, checked, and
+//! completed with default values.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{nodedef::NodeDefRegistry, MaterialX};
+//!
+//! let library = MaterialX::from_str(r#"
+//!     <materialx version="1.39">
+//!       <nodedef name="ND_add_float" node="add">
+//!         <input name="in1" type="float" value="0.0" />
+//!         <input name="in2" type="float" value="0.0" />
+//!         <output name="out" type="float" />
+//!       </nodedef>
+//!     </materialx>
+//! "#)?;
+//! let mut registry = NodeDefRegistry::default();
+//! registry.add_document(&library)?;
+//!
+//! let mut mat = MaterialX::from_str(r#"
+//!     <materialx version="1.39">
+//!       <add name="sum" type="float">
+//!         <input name="in1" type="float" value="1.0" />
+//!       </add>
+//!     </materialx>
+//! "#)?;
+//! let issues = registry.apply_defaults(&mut mat);
+//! assert!(issues.is_empty());
+//! assert!(mat.element("sum")?.children.contains_key("in2"));
+//! # Ok::<(), materialx_parser::Error>(())
+//! ```
+
+use crate::{
+    ast::FsResolver,
+    data_types::DataTypeAndValue,
+    nodes::{check_tag, AccessError},
+    Element, Error, GetAllByType as _, MaterialX, Node,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+
+/// Signature of a node, read from a `<nodedef>` element
+#[derive(Debug, Clone)]
+pub struct NodeDef {
+    pub name: SmolStr,
+    /// Category of the nodes this defines (e.g. `add`)
+    pub node: SmolStr,
+    pub nodegroup: Option<SmolStr>,
+    pub inputs: IndexMap<SmolStr, NodeDefInput>,
+    /// Output names and their types
+    pub outputs: IndexMap<SmolStr, SmolStr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeDefInput {
+    pub name: SmolStr,
+    pub r#type: SmolStr,
+    /// Raw default value, as written in the nodedef
+    pub value: Option<SmolStr>,
+    /// Geometric property used as the default instead of a value (e.g. `UV0`)
+    pub default_geomprop: Option<SmolStr>,
+    pub uniform: bool,
+}
+
+impl NodeDefInput {
+    pub fn default_value(&self) -> Option<DataTypeAndValue> {
+        self.value
+            .as_ref()
+            .and_then(|v| DataTypeAndValue::from_tag_and_value(&self.r#type, v).ok())
+    }
+}
+
+impl NodeDef {
+    /// Type of the node's output, or `multioutput` for nodes with several
+    pub fn output_type(&self) -> &str {
+        match self.outputs.len() {
+            1 => self.outputs[0].as_str(),
+            _ => "multioutput",
+        }
+    }
+
+    /// Whether the inputs set on `element` exist with the same types
+    fn accepts(&self, element: &Element) -> bool {
+        inputs(element).all(|input| {
+            self.inputs
+                .get(&input.name)
+                .is_some_and(|def| input.attributes.get("type") == Some(&def.r#type))
+        })
+    }
+}
+
+impl Node for NodeDef {
+    const ELEMENT_NAME: Option<&'static str> = Some("nodedef");
+
+    fn from_element(element: &Element) -> Result<Self, AccessError> {
+        check_tag(element, "nodedef")?;
+
+        let mut inputs = IndexMap::new();
+        let mut outputs = IndexMap::new();
+        for child in element.children.values() {
+            match child.tag.as_str() {
+                "input" => {
+                    inputs.insert(
+                        child.name.clone(),
+                        NodeDefInput {
+                            name: child.name.clone(),
+                            r#type: child.attr("type")?,
+                            value: child.attr("value").ok(),
+                            default_geomprop: child.attr("defaultgeomprop").ok(),
+                            uniform: child.attr("uniform").is_ok_and(|u| u == "true"),
+                        },
+                    );
+                }
+                "output" => {
+                    outputs.insert(child.name.clone(), child.attr("type")?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(NodeDef {
+            name: element.name.clone(),
+            node: element.attr("node")?,
+            nodegroup: element.attr("nodegroup").ok(),
+            inputs,
+            outputs,
+        })
+    }
+}
+
+/// Problems found when matching node instances against their definitions
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum NodeDefIssue {
+    #[error("No nodedef found for node `{node}` of category `{category}` and type `{type}`")]
+    NoNodeDef {
+        node: SmolStr,
+        category: SmolStr,
+        r#type: SmolStr,
+    },
+    #[error("Input `{input}` of node `{node}` is not defined in `{nodedef}`")]
+    UnknownInput {
+        node: SmolStr,
+        input: SmolStr,
+        nodedef: SmolStr,
+    },
+    #[error(
+        "Input `{input}` of node `{node}` has type `{found}`, but `{nodedef}` expects `{expected}`"
+    )]
+    TypeMismatch {
+        node: SmolStr,
+        input: SmolStr,
+        nodedef: SmolStr,
+        expected: SmolStr,
+        found: SmolStr,
+    },
+}
+
+/// Collection of node definitions, indexed by name and node category
+#[derive(Debug, Clone, Default)]
+pub struct NodeDefRegistry {
+    defs: IndexMap<SmolStr, NodeDef>,
+    by_node: IndexMap<SmolStr, Vec<SmolStr>>,
+}
+
+impl NodeDefRegistry {
+    /// Add all `<nodedef>` elements of a document
+    ///
+    /// Definitions with the same name replace earlier ones.
+    pub fn add_document(&mut self, doc: &MaterialX) -> Result<(), AccessError> {
+        for element in doc.tags("nodedef") {
+            self.insert(NodeDef::from_element(element)?);
+        }
+        Ok(())
+    }
+
+    /// Load a definition file (e.g. `libraries/stdlib/stdlib_defs.mtlx`) from
+    /// disk and add its `<nodedef>` elements
+    pub fn add_file(&mut self, path: &str) -> Result<(), Error> {
+        let doc = MaterialX::load_with_resolver(path, &FsResolver)?;
+        self.add_document(&doc)?;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, nodedef: NodeDef) {
+        let names = self.by_node.entry(nodedef.node.clone()).or_default();
+        if !names.contains(&nodedef.name) {
+            names.push(nodedef.name.clone());
+        }
+        self.defs.insert(nodedef.name.clone(), nodedef);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NodeDef> {
+        self.defs.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    /// All definitions for a node category
+    pub fn for_node<'a>(&'a self, category: &str) -> impl Iterator<Item = &'a NodeDef> + 'a {
+        self.by_node
+            .get(category)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| self.defs.get(name))
+    }
+
+    /// Find the definition of a node instance
+    ///
+    /// Uses the `nodedef` attribute if present. Otherwise picks a definition
+    /// for the node's category with matching output type, preferring one that
+    /// declares all inputs set on the instance with the same types.
+    pub fn find(&self, element: &Element) -> Option<&NodeDef> {
+        if let Some(name) = element.attributes.get("nodedef") {
+            return self.get(name);
+        }
+        let r#type = element.attributes.get("type")?;
+        let mut candidates = self
+            .for_node(&element.tag)
+            .filter(|def| def.output_type() == r#type)
+            .peekable();
+        let first = candidates.peek().copied();
+        candidates.find(|def| def.accepts(element)).or(first)
+    }
+
+    /// Check all nodes against their definitions
+    pub fn check(&self, doc: &MaterialX) -> Vec<NodeDefIssue> {
+        let mut issues = Vec::new();
+        for element in doc.elements.values() {
+            if element.tag == "nodegraph" {
+                for child in element.children.values().filter(|e| e.is_node()) {
+                    let path = format!("{}/{}", element.name, child.name).into();
+                    self.check_node(&path, child, &mut issues);
+                }
+            } else if element.is_node() {
+                self.check_node(&element.name, element, &mut issues);
+            }
+        }
+        issues
+    }
+
+    /// Check all nodes and add inputs with default values where missing
+    ///
+    /// Inputs whose defaults come from geometric properties (like texture
+    /// coordinates) are left out, as they have no constant value.
+    pub fn apply_defaults(&self, doc: &mut MaterialX) -> Vec<NodeDefIssue> {
+        let mut issues = Vec::new();
+        for element in doc.elements.values_mut() {
+            if element.tag == "nodegraph" {
+                let scope = element.name.clone();
+                for child in element.children.values_mut().filter(|e| e.is_node()) {
+                    let path = format!("{scope}/{}", child.name).into();
+                    self.apply_to_node(&path, child, &mut issues);
+                }
+            } else if element.is_node() {
+                let path = element.name.clone();
+                self.apply_to_node(&path, element, &mut issues);
+            }
+        }
+        issues
+    }
+
+    fn apply_to_node(&self, path: &SmolStr, element: &mut Element, issues: &mut Vec<NodeDefIssue>) {
+        let Some(nodedef) = self.check_node(path, element, issues) else {
+            return;
+        };
+        for input in nodedef.inputs.values() {
+            let Some(value) = &input.value else {
+                continue;
+            };
+            if element.children.contains_key(&input.name) {
+                continue;
+            }
+            element.children.insert(
+                input.name.clone(),
+                Element {
+                    tag: "input".into(),
+                    name: input.name.clone(),
+                    attributes: [
+                        ("name".into(), input.name.clone()),
+                        ("type".into(), input.r#type.clone()),
+                        ("value".into(), value.clone()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    children: IndexMap::new(),
+                    location: element.location.child(&input.name),
+                    comments: Vec::new(),
+                },
+            );
+        }
+    }
+
+    fn check_node(
+        &self,
+        path: &SmolStr,
+        element: &Element,
+        issues: &mut Vec<NodeDefIssue>,
+    ) -> Option<&NodeDef> {
+        let Some(nodedef) = self.find(element) else {
+            issues.push(NodeDefIssue::NoNodeDef {
+                node: path.clone(),
+                category: element.tag.clone(),
+                r#type: element.attributes.get("type").cloned().unwrap_or_default(),
+            });
+            return None;
+        };
+
+        for input in inputs(element) {
+            let Some(def) = nodedef.inputs.get(&input.name) else {
+                issues.push(NodeDefIssue::UnknownInput {
+                    node: path.clone(),
+                    input: input.name.clone(),
+                    nodedef: nodedef.name.clone(),
+                });
+                continue;
+            };
+            let found = input.attributes.get("type").cloned().unwrap_or_default();
+            if found != def.r#type {
+                issues.push(NodeDefIssue::TypeMismatch {
+                    node: path.clone(),
+                    input: input.name.clone(),
+                    nodedef: nodedef.name.clone(),
+                    expected: def.r#type.clone(),
+                    found,
+                });
+            }
+        }
+
+        Some(nodedef)
+    }
+}
+
+impl MaterialX {
+    /// All node definitions contained in this document
+    pub fn nodedefs(&self) -> impl Iterator<Item = NodeDef> + '_ {
+        self.all::<NodeDef>()
+    }
+}
+
+fn inputs(element: &Element) -> impl Iterator<Item = &Element> {
+    element.children.values().filter(|e| e.tag == "input")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GetByTypeAndName as _, Input, InputData};
+    use std::str::FromStr as _;
+
+    const LIBRARY: &str = r#"
+        <materialx version="1.39">
+          <nodedef name="ND_multiply_color3" node="multiply" nodegroup="math">
+            <input name="in1" type="color3" value="0.0, 0.0, 0.0" />
+            <input name="in2" type="color3" value="1.0, 1.0, 1.0" />
+            <output name="out" type="color3" />
+          </nodedef>
+          <nodedef name="ND_multiply_color3FA" node="multiply" nodegroup="math">
+            <input name="in1" type="color3" value="0.0, 0.0, 0.0" />
+            <input name="in2" type="float" value="1.0" />
+            <output name="out" type="color3" />
+          </nodedef>
+          <nodedef name="ND_image_color3" node="image" nodegroup="texture2d">
+            <input name="file" type="filename" value="" uniform="true" />
+            <input name="texcoord" type="vector2" defaultgeomprop="UV0" />
+            <output name="out" type="color3" />
+          </nodedef>
+        </materialx>
+    "#;
+
+    fn registry() -> NodeDefRegistry {
+        let mut registry = NodeDefRegistry::default();
+        registry
+            .add_document(&MaterialX::from_str(LIBRARY).unwrap())
+            .unwrap();
+        registry
+    }
+
+    #[test]
+    fn parse() {
+        let registry = registry();
+        assert_eq!(registry.len(), 3);
+        let image = registry.get("ND_image_color3").unwrap();
+        assert_eq!(image.output_type(), "color3");
+        assert!(image.inputs["file"].uniform);
+        assert_eq!(
+            image.inputs["texcoord"].default_geomprop.as_deref(),
+            Some("UV0")
+        );
+        assert_eq!(registry.for_node("multiply").count(), 2);
+    }
+
+    #[test]
+    fn find_by_input_types() {
+        let registry = registry();
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <multiply name="a" type="color3">
+                <input name="in2" type="float" value="0.5" />
+              </multiply>
+              <multiply name="b" type="color3">
+                <input name="in2" type="color3" value="0.5, 0.5, 0.5" />
+              </multiply>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        let a = registry.find(mat.element("a").unwrap()).unwrap();
+        assert_eq!(a.name, "ND_multiply_color3FA");
+        let b = registry.find(mat.element("b").unwrap()).unwrap();
+        assert_eq!(b.name, "ND_multiply_color3");
+        assert!(registry.check(&mat).is_empty());
+    }
+
+    #[test]
+    fn issues_and_defaults() {
+        let registry = registry();
+        let mut mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <nodegraph name="NG">
+                <image name="img" type="color3">
+                  <input name="file" type="filename" value="a.png" />
+                  <input name="bogus" type="float" value="1" />
+                </image>
+                <output name="out" type="color3" nodename="img" />
+              </nodegraph>
+              <multiply name="m" type="color3">
+                <input name="in1" type="color3" value="1, 0, 0" />
+              </multiply>
+              <unknown name="u" type="float" />
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        let issues = registry.apply_defaults(&mut mat);
+        assert!(
+            matches!(
+                &issues[..],
+                [
+                    NodeDefIssue::UnknownInput { node: a, .. },
+                    NodeDefIssue::NoNodeDef { node: b, .. },
+                ] if a == "NG/img" && b == "u"
+            ),
+            "{issues:?}"
+        );
+
+        let m = mat.element("m").unwrap();
+        let in2 = m.get::<Input>("in2".into()).unwrap();
+        assert!(matches!(in2.data, InputData::Value(v) if v == "1.0, 1.0, 1.0"));
+
+        // No constant default for texture coordinates
+        let img = &mat.element("NG").unwrap().children["img"];
+        assert!(!img.children.contains_key("texcoord"));
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let registry = registry();
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <image name="img" type="color3" nodedef="ND_image_color3">
+                <input name="file" type="string" value="a.png" />
+              </image>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            &registry.check(&mat)[..],
+            [NodeDefIssue::TypeMismatch { expected, found, .. }]
+                if expected == "filename" && found == "string"
+        ));
+    }
+}
@@ -2,7 +2,7 @@ use std::fmt::Debug;
 
 use super::Node;
 use crate::{
-    ast::{Element, MaterialX},
+    ast::{Element, Location, MaterialX},
     data_types::ValueParseError,
 };
 use smol_str::SmolStr;
@@ -16,13 +16,22 @@ pub trait GetByTypeAndName {
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum AccessError {
-    #[error("No element found with name `{name}` (in `{parent}`)")]
-    NotFound { name: SmolStr, parent: SmolStr },
-    #[error("Element `{name}` has wrong tag mismatch, expected `{expected}`, found `{found}`")]
+    #[error("{location}: No element found with name `{name}` (in `{parent}`)")]
+    NotFound {
+        name: SmolStr,
+        parent: SmolStr,
+        /// Location of the parent element, or of the input referring to
+        /// the missing element
+        location: Box<Location>,
+    },
+    #[error(
+        "{location}: Element `{name}` has wrong tag mismatch, expected `{expected}`, found `{found}`"
+    )]
     TagMismatch {
         name: SmolStr,
         expected: SmolStr,
         found: SmolStr,
+        location: Box<Location>,
     },
     #[error("Failed to convert element `{name}` to `{type}`")]
     ConversionError {
@@ -30,21 +39,29 @@ pub enum AccessError {
         r#type: &'static str,
         source: Box<AccessError>,
     },
-    #[error("Failed to convert element `{name}` to `{type}`")]
+    #[error("{location}: Failed to convert element `{name}` to `{type}`")]
     ValueParseError {
         name: SmolStr,
         r#type: &'static str,
+        location: Box<Location>,
         source: Box<ValueParseError>,
     },
-    #[error("No value, node name, or interface name found for input `{name}`")]
-    InputMissingData { name: SmolStr },
-    #[error("No value found for input `{name}`")]
-    InputMissingValue { name: SmolStr },
-    #[error("Failed to convert input `{parent}.{name}` to `{type}`")]
+    #[error("{location}: No value, node name, or interface name found for input `{name}`")]
+    InputMissingData {
+        name: SmolStr,
+        location: Box<Location>,
+    },
+    #[error("{location}: No value found for input `{name}`")]
+    InputMissingValue {
+        name: SmolStr,
+        location: Box<Location>,
+    },
+    #[error("{location}: Failed to convert input `{parent}.{name}` to `{type}`")]
     InputConvertError {
         name: SmolStr,
         parent: SmolStr,
         r#type: &'static str,
+        location: Box<Location>,
         source: ValueParseError,
     },
     #[error("Could not get `{child}` from `{parent}`")]
@@ -53,10 +70,34 @@ pub enum AccessError {
         parent: SmolStr,
         source: Box<AccessError>,
     },
+    #[error("{location}: Input `{name}` is connected to node `{node}`, not a constant value")]
+    NotConstant {
+        name: SmolStr,
+        node: SmolStr,
+        location: Box<Location>,
+    },
+    #[error("{location}: Too many nested references while resolving `{name}`, is there a cycle?")]
+    ReferenceCycle {
+        name: SmolStr,
+        location: Box<Location>,
+    },
     #[error("Unimplemented: {0}")]
     Unimplemented(&'static str),
 }
 
+/// Fail with [`AccessError::TagMismatch`] unless `element` has the tag `expected`
+pub(crate) fn check_tag(element: &Element, expected: &str) -> Result<(), AccessError> {
+    if element.tag != expected {
+        return Err(AccessError::TagMismatch {
+            name: element.name.clone(),
+            location: Box::new(element.location.clone()),
+            expected: expected.into(),
+            found: element.tag.clone(),
+        });
+    }
+    Ok(())
+}
+
 impl GetByTypeAndName for MaterialX {
     fn get<T>(&self, name: SmolStr) -> Result<T, AccessError>
     where
@@ -68,6 +109,7 @@ impl GetByTypeAndName for MaterialX {
             .ok_or_else(|| AccessError::NotFound {
                 name: name.clone(),
                 parent: MaterialX::NAME,
+                location: Box::new(MaterialX::location()),
             })?;
         T::from_element(elem).map_err(|e| AccessError::ConversionError {
             name: name.clone(),
@@ -85,6 +127,7 @@ impl MaterialX {
             .ok_or_else(|| AccessError::NotFound {
                 name,
                 parent: MaterialX::NAME,
+                location: Box::new(MaterialX::location()),
             })
     }
 
@@ -105,6 +148,7 @@ impl GetByTypeAndName for Element {
             .ok_or_else(|| AccessError::NotFound {
                 name: name.clone(),
                 parent: self.name.clone(),
+                location: Box::new(self.location.clone()),
             })?;
         T::from_element(elem).map_err(|e| AccessError::ConversionError {
             name: name.clone(),
@@ -114,13 +158,25 @@ impl GetByTypeAndName for Element {
     }
 }
 
+/// Tags of child elements that describe ports or metadata instead of nodes
+const NON_NODE_TAGS: &[&str] = &["input", "output", "token"];
+
 impl Element {
+    /// Whether this element is a node instance (as opposed to a port,
+    /// definition, or other metadata element)
+    pub(crate) fn is_node(&self) -> bool {
+        self.attributes.contains_key("type") && !NON_NODE_TAGS.contains(&self.tag.as_str())
+    }
+
     pub fn attr(&self, name: impl Into<SmolStr>) -> Result<SmolStr, AccessError> {
         let name = name.into();
         self.attributes
             .get(&name)
             .cloned()
-            .ok_or_else(|| AccessError::InputMissingData { name })
+            .ok_or_else(|| AccessError::InputMissingData {
+                name,
+                location: Box::new(self.location.clone()),
+            })
     }
 }
 
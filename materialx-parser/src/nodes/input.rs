@@ -1,5 +1,8 @@
 use super::{accessor::AccessError, Node};
-use crate::{ast::Element, data_types::DataTypeAndValue};
+use crate::{
+    ast::{Element, Location},
+    data_types::DataTypeAndValue,
+};
 use smol_str::SmolStr;
 
 #[derive(Debug, Clone)]
@@ -9,6 +12,7 @@ pub struct Input {
     pub data: InputData,
     pub output: Option<SmolStr>,
     pub color_space: Option<SmolStr>,
+    pub location: Location,
 }
 
 impl Node for Input {
@@ -24,6 +28,7 @@ impl Node for Input {
             data,
             output: element.attr("output").ok(),
             color_space: element.attr("colorspace").ok(),
+            location: element.location.clone(),
         })
     }
 }
@@ -35,12 +40,14 @@ impl TryFrom<&Input> for DataTypeAndValue {
         let InputData::Value(data) = &value.data else {
             return Err(AccessError::InputMissingValue {
                 name: value.name.clone(),
+                location: Box::new(value.location.clone()),
             });
         };
         DataTypeAndValue::from_tag_and_value(&value.r#type, data).map_err(|e| {
             AccessError::ValueParseError {
                 name: value.name.clone(),
                 r#type: "DataTypeAndValue",
+                location: Box::new(value.location.clone()),
                 source: Box::new(e),
             }
         })
@@ -61,13 +68,14 @@ impl Node for InputData {
             Ok(InputData::Value(value))
         } else if let Ok(node_name) = e.attr("nodename") {
             Ok(InputData::NodeReference { node_name })
-        } else if let Ok(interface_name) = e.attr("interface_name") {
+        } else if let Ok(interface_name) = e.attr("interfacename") {
             Ok(InputData::InputReference { interface_name })
         } else if let (Ok(nodegraph), Ok(output)) = (e.attr("nodegraph"), e.attr("output")) {
             Ok(InputData::OutputReference { nodegraph, output })
         } else {
             Err(AccessError::InputMissingData {
                 name: e.name.clone(),
+                location: Box::new(e.location.clone()),
             })
         }
     }
@@ -8,6 +8,7 @@ pub mod standard_nodes;
 
 pub use accessor::*;
 pub use input::{Input, InputData};
+pub use resolve::{ResolvedInput, UpstreamNode};
 
 pub trait Node: Sized + Debug {
     const ELEMENT_NAME: Option<&'static str> = None;
@@ -58,6 +59,7 @@ macro_rules! wrap_node {
                     if element.tag != expected {
                         return Err($crate::AccessError::TagMismatch {
                             name: element.name.clone(),
+                            location: Box::new(element.location.clone()),
                             expected: expected.into(),
                             found: element.tag.clone(),
                         });
@@ -122,6 +124,7 @@ macro_rules! node {
                     if element.tag != expected {
                         return Err($crate::AccessError::TagMismatch {
                             name: element.name.clone(),
+                            location: Box::new(element.location.clone()),
                             expected: expected.into(),
                             found: element.tag.clone(),
                         });
@@ -7,41 +7,244 @@ use crate::{
 use smol_str::SmolStr;
 use std::any::type_name;
 
+/// How deep we follow interface and output references before giving up
+const MAX_DEPTH: usize = 32;
+
+/// What an input is connected to
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // Short-lived, boxing would only add an allocation
+pub enum ResolvedInput {
+    /// A constant value, either given directly or via an interface input
+    ///
+    /// Colors are converted from their [color space](crate::color) to
+    /// linear Rec.709, file names are [prefixed](crate::filename).
+    Value(DataTypeAndValue),
+    /// The output of another node
+    Node(UpstreamNode),
+}
+
+/// A node whose output is connected to an input
+#[derive(Debug, Clone)]
+pub struct UpstreamNode {
+    pub element: Element,
+    /// Name of the nodegraph the node lives in (`None` for the document root)
+    pub nodegraph: Option<SmolStr>,
+    /// Data type of the connection
+    pub r#type: SmolStr,
+    /// Selected output, for nodes with multiple outputs
+    pub output: Option<SmolStr>,
+}
+
+impl UpstreamNode {
+    /// The element containing this node, to be passed to
+    /// [`MaterialX::resolve`] when resolving the node's own inputs
+    pub fn parent<'a>(&self, def: &'a MaterialX) -> Option<&'a Element> {
+        self.nodegraph
+            .as_ref()
+            .and_then(|name| def.elements.get(name))
+    }
+}
+
 impl MaterialX {
-    // FIXME: Access nodes that are siblings of the current node using node_name
+    /// Resolve the input `name` of `element` to a constant or a node
+    ///
+    /// `parent` is the nodegraph (or nodedef) `element` is defined in, or
+    /// `None` if it is a direct child of the document. It is used to look up
+    /// sibling nodes (`nodename`) and interface inputs (`interfacename`).
+    /// Nodegraph outputs (`nodegraph` + `output`) are followed to the node
+    /// they are connected to.
+    pub fn resolve(
+        &self,
+        element: &Element,
+        parent: Option<&Element>,
+        name: SmolStr,
+    ) -> Result<ResolvedInput, AccessError> {
+        let input = element.get::<Input>(name)?;
+        self.resolve_port(&input, Some(element), parent, 0)
+    }
+
+    /// Resolve the input `name` of `element` to a constant value
+    ///
+    /// See [`MaterialX::resolve`]. Inputs connected to nodes return
+    /// [`AccessError::NotConstant`].
     pub fn resolve_input<T>(
         &self,
         element: &Element,
-        _parent: Option<&Element>,
+        parent: Option<&Element>,
         name: SmolStr,
     ) -> Result<T, AccessError>
     where
         T: TryFrom<DataTypeAndValue, Error = ValueParseError>,
     {
-        let input = element.get::<Input>(name.clone())?;
-        match input.data {
-            InputData::Value(x) => Ok(DataTypeAndValue::from_tag_and_value(&input.r#type, &x)
-                .map_err(|e| AccessError::ValueParseError {
-                    name: name.clone(),
-                    r#type: "DataTypeAndValue",
-                    source: Box::new(e),
-                })?
-                .try_into()
-                .map_err(|e| AccessError::InputConvertError {
-                    name: input.name.clone(),
-                    parent: name.clone(),
-                    r#type: type_name::<T>(),
-                    source: e,
-                }))?,
-            InputData::NodeReference { .. } => {
-                Err(AccessError::Unimplemented("following node references"))
+        let location = Box::new(element.location.child(&name));
+        match self.resolve(element, parent, name.clone())? {
+            ResolvedInput::Value(x) => x.try_into().map_err(|e| AccessError::InputConvertError {
+                name,
+                parent: element.name.clone(),
+                r#type: type_name::<T>(),
+                location,
+                source: e,
+            }),
+            ResolvedInput::Node(node) => Err(AccessError::NotConstant {
+                name,
+                node: node.element.name,
+                location,
+            }),
+        }
+    }
+
+    /// Follow the connection of an `<input>` or `<output>` element
+    ///
+    /// `owner` is the element `port` is a child of, which may be `scope`
+    /// itself for interface inputs.
+    fn resolve_port(
+        &self,
+        port: &Input,
+        owner: Option<&Element>,
+        scope: Option<&Element>,
+        depth: usize,
+    ) -> Result<ResolvedInput, AccessError> {
+        if depth > MAX_DEPTH {
+            return Err(AccessError::ReferenceCycle {
+                name: port.name.clone(),
+                location: Box::new(port.location.clone()),
+            });
+        }
+
+        match &port.data {
+            InputData::Value(x) => {
+                let value = DataTypeAndValue::from_tag_and_value(&port.r#type, x).map_err(|e| {
+                    AccessError::ValueParseError {
+                        name: port.name.clone(),
+                        r#type: "DataTypeAndValue",
+                        location: Box::new(port.location.clone()),
+                        source: Box::new(e),
+                    }
+                })?;
+                if let DataTypeAndValue::Filename(file) = value {
+                    let element = owner.and_then(|owner| owner.children.get(&port.name));
+                    let scope = element.into_iter().chain(owner).chain(scope);
+                    let file = self.filename_in(&file, scope);
+                    return Ok(ResolvedInput::Value(DataTypeAndValue::Filename(file)));
+                }
+                let colorspace = match &port.color_space {
+                    Some(name) => name.parse().ok(),
+                    None => self.colorspace_in(owner.into_iter().chain(scope)),
+                };
+                Ok(ResolvedInput::Value(match colorspace {
+                    Some(colorspace) => colorspace.linearize(value),
+                    None => value,
+                }))
+            }
+            InputData::NodeReference { node_name } => {
+                let siblings = match scope {
+                    Some(scope) => &scope.children,
+                    None => &self.elements,
+                };
+                let element = siblings
+                    .get(node_name)
+                    .ok_or_else(|| AccessError::NotFound {
+                        name: node_name.clone(),
+                        parent: scope.map_or(MaterialX::NAME, |s| s.name.clone()),
+                        location: Box::new(port.location.clone()),
+                    })?;
+                Ok(ResolvedInput::Node(UpstreamNode {
+                    r#type: element.attr("type").unwrap_or_else(|_| port.r#type.clone()),
+                    element: element.clone(),
+                    nodegraph: scope.map(|s| s.name.clone()),
+                    output: port.output.clone(),
+                }))
             }
-            InputData::InputReference { .. } => {
-                Err(AccessError::Unimplemented("following input references"))
+            InputData::InputReference { interface_name } => {
+                let scope = scope.ok_or_else(|| AccessError::NotFound {
+                    name: interface_name.clone(),
+                    parent: MaterialX::NAME,
+                    location: Box::new(port.location.clone()),
+                })?;
+                match scope.get::<Input>(interface_name.clone()) {
+                    // Interface inputs of a nodegraph may connect to nodes in
+                    // the document root
+                    Ok(input) => self.resolve_port(&input, Some(scope), None, depth + 1),
+                    Err(AccessError::NotFound { .. }) => {
+                        // Functional nodegraphs take their interface from the
+                        // nodedef, so the best we can do is its default value
+                        let nodedef = self.element(scope.attr("nodedef")?)?;
+                        let input = nodedef.get::<Input>(interface_name.clone())?;
+                        self.resolve_port(&input, Some(nodedef), Some(nodedef), depth + 1)
+                    }
+                    Err(e) => Err(e),
+                }
             }
-            InputData::OutputReference { .. } => {
-                Err(AccessError::Unimplemented("following output references"))
+            InputData::OutputReference { nodegraph, output } => {
+                let nodegraph = self.element(nodegraph.clone())?;
+                let output = nodegraph.get::<Input>(output.clone())?;
+                self.resolve_port(&output, Some(nodegraph), Some(nodegraph), depth + 1)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::DataTypeAndValue;
+    use std::str::FromStr as _;
+
+    fn load(name: &str) -> MaterialX {
+        let file = std::fs::read_to_string(format!(
+            "../assets/materialx-examples/StandardSurface/{name}"
+        ))
+        .unwrap();
+        MaterialX::from_str(&file).unwrap()
+    }
+
+    #[test]
+    fn nodegraph_output() {
+        let mat = load("standard_surface_chess_set.mtlx");
+        let surface = mat.element("Bishop_B").unwrap();
+        let ResolvedInput::Node(node) = mat.resolve(surface, None, "normal".into()).unwrap() else {
+            panic!("expected node");
+        };
+        assert_eq!(node.element.name, "mtlxnormalmap4");
+        assert_eq!(node.nodegraph.as_deref(), Some("NG_BishopBlack"));
+        assert_eq!(node.r#type, "vector3");
+
+        // Follow the sibling inside the nodegraph
+        let ResolvedInput::Node(image) = mat
+            .resolve(&node.element, node.parent(&mat), "in".into())
+            .unwrap()
+        else {
+            panic!("expected node");
+        };
+        assert_eq!(image.element.name, "normal2");
+        let ResolvedInput::Value(DataTypeAndValue::Filename(file)) = mat
+            .resolve(&image.element, image.parent(&mat), "file".into())
+            .unwrap()
+        else {
+            panic!("expected filename");
+        };
+        assert_eq!(file, "chess_set/bishop_black_normal.jpg");
+    }
+
+    #[test]
+    fn interface_input() {
+        let mat = load("standard_surface_brick_procedural.mtlx");
+        let graph = mat.element("NG_BrickPattern").unwrap();
+        let node = graph.children.get("node_multiply_14").unwrap();
+        let value = mat.resolve(node, Some(graph), "in2".into()).unwrap();
+        assert!(matches!(
+            value,
+            ResolvedInput::Value(DataTypeAndValue::Float(x)) if x == 0.083
+        ));
+    }
+
+    #[test]
+    fn constant() {
+        let mat = load("standard_surface_brick_procedural.mtlx");
+        let surface = mat.element("N_StandardSurface").unwrap();
+        assert!(matches!(
+            mat.resolve_input::<f32>(surface, None, "base_color".into()),
+            Err(AccessError::NotConstant { .. })
+        ));
+    }
+}
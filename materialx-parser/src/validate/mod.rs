@@ -0,0 +1,459 @@
+//! Checking documents for problems
+//!
+//! [`MaterialX::validate`] walks the whole document and collects every
+//! problem it finds as a [`Diagnostic`], instead of stopping at the first
+//! one like the accessors do.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{validate::Code, MaterialX};
+//!
+//! let mat = MaterialX::from_str(r#"<materialx version="1.39">
+//!   <standard_surface name="SR" type="surfaceshader">
+//!     <input name="base" type="float" value="one" />
+//!   </standard_surface>
+//!   <surfacematerial name="M" type="material">
+//!     <input name="surfaceshader" type="surfaceshader" nodename="SR_missing" />
+//!   </surfacematerial>
+//! </materialx>"#)?;
+//! let diagnostics = mat.validate();
+//! assert_eq!(diagnostics.len(), 2);
+//! assert_eq!(diagnostics[0].code, Code::InvalidValue);
+//! assert_eq!(diagnostics[1].code, Code::DanglingReference);
+//! assert_eq!(diagnostics[1].location.path, "M/surfaceshader");
+//! # Ok::<(), materialx_parser::Error>(())
+//! ```
+
+use crate::{
+    ast::{ColorSpace, Location},
+    data_types::DataTypeAndValue,
+    Element, MaterialX,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Kind of problem found by [`MaterialX::validate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Code {
+    /// Two elements in the same scope have the same name
+    DuplicateName,
+    /// `nodename`, `nodegraph`, `output`, or `interfacename` refers to an
+    /// element that does not exist
+    DanglingReference,
+    /// A port is connected to an output of a different type
+    TypeMismatch,
+    /// A `value` can't be parsed as the declared `type`
+    InvalidValue,
+    /// A `colorspace` attribute names a color space we don't know
+    UnknownColorSpace,
+    /// Nodes are connected in a loop
+    Cycle,
+}
+
+impl Code {
+    /// Stable name of the code, e.g. for filtering in CI
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::DuplicateName => "duplicate-name",
+            Code::DanglingReference => "dangling-reference",
+            Code::TypeMismatch => "type-mismatch",
+            Code::InvalidValue => "invalid-value",
+            Code::UnknownColorSpace => "unknown-colorspace",
+            Code::Cycle => "cycle",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Code::UnknownColorSpace => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A problem found in a document
+///
+/// Displayed like
+/// `error[dangling-reference] file.mtlx:3:5 (M/surfaceshader): ...`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub location: Location,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(code: Code, location: &Location, message: String) -> Self {
+        Diagnostic {
+            severity: code.severity(),
+            code,
+            location: location.clone(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}] {}: {}",
+            self.severity, self.code, self.location, self.message
+        )
+    }
+}
+
+impl MaterialX {
+    /// Check the document and return all problems found
+    ///
+    /// Diagnostics are ordered by kind of check, and in document order
+    /// within each kind.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+
+        for location in &self.duplicates {
+            res.push(Diagnostic::new(
+                Code::DuplicateName,
+                location,
+                "Element has the same name as an earlier sibling, which it replaces".into(),
+            ));
+        }
+
+        if let Some(ColorSpace::Unknown(name)) = &self.colorspace {
+            if !name.is_empty() {
+                res.push(Diagnostic::new(
+                    Code::UnknownColorSpace,
+                    &MaterialX::location(),
+                    format!("Unknown color space `{name}`"),
+                ));
+            }
+        }
+
+        for element in self.elements.values() {
+            check_attributes(element, &mut res);
+        }
+
+        for element in self.elements.values() {
+            if element.tag == "nodegraph" {
+                for child in element.children.values() {
+                    self.check_connections(child, Some(element), &mut res);
+                }
+            }
+            self.check_connections(element, None, &mut res);
+        }
+
+        check_cycles(&self.elements, &mut res);
+        for element in self.elements.values() {
+            if element.tag == "nodegraph" {
+                check_cycles(&element.children, &mut res);
+            }
+        }
+
+        res
+    }
+
+    /// Check the references of the ports of `element`, which is defined in
+    /// `graph` (or the document root)
+    fn check_connections(
+        &self,
+        element: &Element,
+        graph: Option<&Element>,
+        res: &mut Vec<Diagnostic>,
+    ) {
+        // Nodegraph outputs and interface inputs connect directly, node
+        // inputs are children of the node
+        let ports: Vec<(&Element, Option<&Element>)> = match element.tag.as_str() {
+            "output" if graph.is_some() => vec![(element, graph)],
+            "input" if graph.is_some() => vec![(element, None)],
+            "nodegraph" | "nodedef" => return,
+            _ => element
+                .children
+                .values()
+                .filter(|c| c.tag == "input")
+                .map(|c| (c, graph))
+                .collect(),
+        };
+
+        for (port, scope) in ports {
+            let Some(found) = self.connected_type(port, scope, res) else {
+                continue;
+            };
+            let Some(expected) = port.attributes.get("type") else {
+                continue;
+            };
+            if found != *expected && found != "multioutput" {
+                res.push(Diagnostic::new(
+                    Code::TypeMismatch,
+                    &port.location,
+                    format!("Port of type `{expected}` is connected to a `{found}` output"),
+                ));
+            }
+        }
+    }
+
+    /// Output type of whatever `port` is connected to
+    ///
+    /// Dangling references are reported, and `None` is returned for them and
+    /// for ports with values.
+    fn connected_type(
+        &self,
+        port: &Element,
+        scope: Option<&Element>,
+        res: &mut Vec<Diagnostic>,
+    ) -> Option<SmolStr> {
+        let attrs = &port.attributes;
+        let dangling = |res: &mut Vec<Diagnostic>, message: String| {
+            res.push(Diagnostic::new(
+                Code::DanglingReference,
+                &port.location,
+                message,
+            ));
+            None
+        };
+
+        if let Some(name) = attrs.get("nodename") {
+            let siblings = scope.map_or(&self.elements, |s| &s.children);
+            let Some(node) = siblings.get(name).filter(|n| n.is_node()) else {
+                let scope = scope.map_or(MaterialX::NAME, |s| s.name.clone());
+                return dangling(res, format!("No node `{name}` in `{scope}`"));
+            };
+            node.attributes.get("type").cloned()
+        } else if let Some(name) = attrs.get("nodegraph") {
+            let Some(graph) = self.elements.get(name).filter(|g| g.tag == "nodegraph") else {
+                return dangling(res, format!("No nodegraph `{name}`"));
+            };
+            let outputs = graph.children.values().filter(|c| c.tag == "output");
+            let output = match attrs.get("output") {
+                Some(output) => graph.children.get(output).filter(|c| c.tag == "output"),
+                // Nodegraphs with a single output can be connected without
+                // naming it
+                None => outputs.clone().next().filter(|_| outputs.count() == 1),
+            };
+            let Some(output) = output else {
+                let output = attrs.get("output").map_or("", |o| o.as_str());
+                return dangling(res, format!("No output `{output}` in nodegraph `{name}`"));
+            };
+            output.attributes.get("type").cloned()
+        } else if let Some(name) = attrs.get("interfacename") {
+            let scope = scope?;
+            if let Some(input) = scope.children.get(name).filter(|c| c.tag == "input") {
+                return input.attributes.get("type").cloned();
+            }
+            // Functional nodegraphs take their interface from their nodedef,
+            // which is usually defined in a library
+            if let Some(nodedef) = scope.attributes.get("nodedef") {
+                let input = self.elements.get(nodedef)?.children.get(name);
+                if let Some(input) = input {
+                    return input.attributes.get("type").cloned();
+                }
+            }
+            let scope = &scope.name;
+            dangling(res, format!("No interface input `{name}` in `{scope}`"))
+        } else {
+            None
+        }
+    }
+}
+
+/// Check values and color spaces of `element` and its children
+fn check_attributes(element: &Element, res: &mut Vec<Diagnostic>) {
+    if let (Some(r#type), Some(value)) = (
+        element.attributes.get("type"),
+        element.attributes.get("value"),
+    ) {
+        if let Err(e) = DataTypeAndValue::from_tag_and_value(r#type, value) {
+            res.push(Diagnostic::new(
+                Code::InvalidValue,
+                &element.location,
+                format!("Invalid `{type}` value `{value}`: {e}"),
+            ));
+        }
+    }
+
+    if let Some(name) = element.attributes.get("colorspace") {
+        if let Ok(ColorSpace::Unknown(_)) = name.parse() {
+            if !name.is_empty() {
+                res.push(Diagnostic::new(
+                    Code::UnknownColorSpace,
+                    &element.location,
+                    format!("Unknown color space `{name}`"),
+                ));
+            }
+        }
+    }
+
+    for child in element.children.values() {
+        check_attributes(child, res);
+    }
+}
+
+/// Report cycles between the nodes in one scope
+fn check_cycles(elements: &IndexMap<SmolStr, Element>, res: &mut Vec<Diagnostic>) {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        New,
+        Visiting,
+        Done,
+    }
+
+    fn visit(
+        elements: &IndexMap<SmolStr, Element>,
+        index: usize,
+        state: &mut [State],
+        stack: &mut Vec<usize>,
+        res: &mut Vec<Diagnostic>,
+    ) {
+        state[index] = State::Visiting;
+        stack.push(index);
+        let (_, element) = elements.get_index(index).unwrap();
+        for input in element.children.values() {
+            let Some(upstream) = input
+                .attributes
+                .get("nodename")
+                .and_then(|name| elements.get_index_of(name))
+                .filter(|&i| elements[i].is_node())
+            else {
+                continue;
+            };
+            match state[upstream] {
+                State::New => visit(elements, upstream, state, stack, res),
+                State::Visiting => {
+                    let start = stack.iter().position(|&i| i == upstream).unwrap();
+                    let names = stack[start..]
+                        .iter()
+                        .map(|&i| elements[i].name.as_str())
+                        .collect::<Vec<_>>();
+                    res.push(Diagnostic::new(
+                        Code::Cycle,
+                        &input.location,
+                        format!(
+                            "Connection closes a cycle: {} -> {}",
+                            names.join(" -> "),
+                            elements[upstream].name
+                        ),
+                    ));
+                }
+                State::Done => {}
+            }
+        }
+        stack.pop();
+        state[index] = State::Done;
+    }
+
+    let mut state = vec![State::New; elements.len()];
+    let mut stack = Vec::new();
+    for index in 0..elements.len() {
+        if state[index] == State::New && elements[index].is_node() {
+            visit(elements, index, &mut state, &mut stack, res);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr as _;
+
+    fn codes(xml: &str) -> Vec<(Code, String)> {
+        MaterialX::from_str(xml)
+            .unwrap()
+            .validate()
+            .into_iter()
+            .map(|d| (d.code, d.location.path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn examples_are_valid() {
+        for example in glob::glob("../assets/materialx-examples/StandardSurface/*.mtlx").unwrap() {
+            let path = example.unwrap();
+            let xml = std::fs::read_to_string(&path).unwrap();
+            let Ok(mat) = MaterialX::from_str(&xml) else {
+                continue;
+            };
+            let errors = mat
+                .validate()
+                .into_iter()
+                .filter(|d| d.severity == Severity::Error)
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>();
+            assert!(errors.is_empty(), "{}: {errors:#?}", path.display());
+        }
+    }
+
+    #[test]
+    fn all_problems() {
+        let res = codes(
+            r#"<materialx version="1.39" colorspace="lin_rec709">
+  <nodegraph name="NG">
+    <input name="scale" type="float" value="2" />
+    <multiply name="a" type="float">
+      <input name="in1" type="float" nodename="b" />
+      <input name="in2" type="float" interfacename="scale" />
+    </multiply>
+    <add name="b" type="float">
+      <input name="in1" type="float" nodename="a" />
+      <input name="in2" type="float" interfacename="missing" />
+    </add>
+    <output name="out" type="color3" nodename="a" />
+  </nodegraph>
+  <image name="img" type="color3">
+    <input name="file" type="filename" value="a.png" colorspace="srgb_gamma" />
+    <input name="default" type="color3" value="0, 0" />
+  </image>
+  <constant name="k" type="float" />
+  <constant name="k" type="float" />
+  <standard_surface name="SR" type="surfaceshader">
+    <input name="base_color" type="color3" nodegraph="NG" output="nope" />
+    <input name="coat_color" type="color3" nodename="nope" />
+    <input name="specular_color" type="color3" nodegraph="NG" output="out" />
+    <input name="normal" type="vector3" nodegraph="NG" output="out" />
+  </standard_surface>
+</materialx>"#,
+        );
+        let expected = [
+            (Code::DuplicateName, "k"),
+            (Code::UnknownColorSpace, "img/file"),
+            (Code::InvalidValue, "img/default"),
+            (Code::DanglingReference, "NG/b/in2"),
+            (Code::TypeMismatch, "NG/out"),
+            (Code::DanglingReference, "SR/base_color"),
+            (Code::DanglingReference, "SR/coat_color"),
+            (Code::TypeMismatch, "SR/normal"),
+            (Code::Cycle, "NG/b/in1"),
+        ];
+        assert_eq!(
+            res,
+            expected
+                .into_iter()
+                .map(|(code, path)| (code, path.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Material variants (`<variantset>`, `<variant>`)
+//!
+//! A variant is a named set of values for the inputs of a node definition,
+//! e.g. the colors of one paint job of a car. Variants are grouped into
+//! variant sets; a set's `nodedef` attribute names the definition its
+//! variants are meant for, which is usually implemented by a nodegraph (see
+//! [`VariantSet::nodegraph`]).
+//!
+//! Variants only hold values: they can't connect inputs to nodes.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::str::FromStr;
+//! use materialx_parser::{data_types::DataTypeAndValue, MaterialX};
+//!
+//! let mat = MaterialX::from_str(r#"
+//!     <materialx version="1.39">
+//!       <variantset name="paint" nodedef="ND_car_paint">
+//!         <variant name="red">
+//!           <input name="base_color" type="color3" value="0.8, 0.1, 0.1" />
+//!         </variant>
+//!         <variant name="matte">
+//!           <input name="roughness" type="float" value="0.9" />
+//!         </variant>
+//!       </variantset>
+//!     </materialx>
+//! "#)?;
+//! let matte = mat.variant("paint", "matte")?;
+//! assert!(matches!(matte.values["roughness"], DataTypeAndValue::Float(x) if x == 0.9));
+//! # Ok::<(), materialx_parser::Error>(())
+//! ```
+
+use crate::{
+    data_types::DataTypeAndValue,
+    nodes::{check_tag, AccessError},
+    Element, GetByTypeAndName as _, MaterialX, Node,
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+
+/// A `<variantset>` element
+#[derive(Debug)]
+pub struct VariantSet {
+    pub name: SmolStr,
+    /// Node definition the variants provide values for
+    pub nodedef: Option<SmolStr>,
+    /// The variants by name, or why they can't be read, like
+    /// [`MaterialX::variant`] reports it
+    pub variants: IndexMap<SmolStr, Result<Variant, AccessError>>,
+}
+
+/// A `<variant>` element
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: SmolStr,
+    /// Values of `<input>` and `<token>` children, by name
+    ///
+    /// Colors are only converted from their color space when the variant is
+    /// read with [`MaterialX::variant`] or [`MaterialX::variantsets`].
+    pub values: IndexMap<SmolStr, DataTypeAndValue>,
+}
+
+impl VariantSet {
+    /// The nodegraph the variants apply to
+    ///
+    /// That's the nodegraph implementing [`nodedef`](Self::nodedef), or else a
+    /// nodegraph with the same name as the nodedef.
+    pub fn nodegraph<'a>(&self, def: &'a MaterialX) -> Option<&'a Element> {
+        let nodedef = self.nodedef.as_ref()?;
+        let mut nodegraphs = def.elements.values().filter(|e| e.tag == "nodegraph");
+        nodegraphs
+            .clone()
+            .find(|e| e.attributes.get("nodedef") == Some(nodedef))
+            .or_else(|| nodegraphs.find(|e| e.name == *nodedef))
+    }
+}
+
+impl Node for VariantSet {
+    const ELEMENT_NAME: Option<&'static str> = Some("variantset");
+
+    fn from_element(element: &Element) -> Result<Self, AccessError> {
+        check_tag(element, "variantset")?;
+        let variants = element
+            .children
+            .values()
+            .filter(|e| e.tag == "variant")
+            .map(|e| (e.name.clone(), Variant::from_element(e)))
+            .collect();
+        Ok(VariantSet {
+            name: element.name.clone(),
+            nodedef: element.attr("nodedef").ok(),
+            variants,
+        })
+    }
+}
+
+impl Node for Variant {
+    const ELEMENT_NAME: Option<&'static str> = Some("variant");
+
+    fn from_element(element: &Element) -> Result<Self, AccessError> {
+        check_tag(element, "variant")?;
+        let mut values = IndexMap::new();
+        for child in element.children.values() {
+            if !matches!(child.tag.as_str(), "input" | "token") {
+                continue;
+            }
+            let r#type = child.attr("type")?;
+            let value = DataTypeAndValue::from_tag_and_value(&r#type, &child.attr("value")?)
+                .map_err(|e| AccessError::ValueParseError {
+                    name: child.name.clone(),
+                    r#type: "DataTypeAndValue",
+                    location: Box::new(child.location.clone()),
+                    source: Box::new(e),
+                })?;
+            values.insert(child.name.clone(), value);
+        }
+        Ok(Variant {
+            name: element.name.clone(),
+            values,
+        })
+    }
+}
+
+impl MaterialX {
+    /// All variant sets contained in this document
+    ///
+    /// A variant that can't be read doesn't hide the other variants of its
+    /// set, see [`VariantSet::variants`].
+    ///
+    /// Like for [`MaterialX::resolve`], colors are converted to linear
+    /// Rec.709.
+    pub fn variantsets(&self) -> impl Iterator<Item = VariantSet> + '_ {
+        self.elements.values().filter_map(|element| {
+            let mut set = VariantSet::from_element(element).ok()?;
+            for variant in set.variants.values_mut().flatten() {
+                self.linearize_variant(element, variant);
+            }
+            Some(set)
+        })
+    }
+
+    /// The variant named `variant` in the variant set `set`
+    pub fn variant(&self, set: &str, variant: &str) -> Result<Variant, AccessError> {
+        let set = self.element(set)?;
+        check_tag(set, "variantset")?;
+        let mut variant = set.get::<Variant>(variant.into())?;
+        self.linearize_variant(set, &mut variant);
+        Ok(variant)
+    }
+
+    fn linearize_variant(&self, set: &Element, variant: &mut Variant) {
+        let Some(element) = set.children.get(&variant.name) else {
+            return;
+        };
+        for (name, value) in &mut variant.values {
+            let input = element.children.get(name);
+            let scope = input.into_iter().chain([element, set]);
+            if let Some(colorspace) = self.colorspace_in(scope) {
+                *value = colorspace.linearize(value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr as _;
+
+    const XML: &str = r#"
+        <materialx version="1.39">
+          <nodedef name="ND_tinted" node="tinted">
+            <input name="tint" type="color3" value="1, 1, 1" />
+            <output name="out" type="surfaceshader" />
+          </nodedef>
+          <nodegraph name="NG_tinted" nodedef="ND_tinted">
+            <standard_surface name="surface" type="surfaceshader">
+              <input name="base_color" type="color3" interfacename="tint" />
+            </standard_surface>
+            <output name="out" type="surfaceshader" nodename="surface" />
+          </nodegraph>
+          <variantset name="tints" nodedef="ND_tinted">
+            <variant name="red">
+              <input name="tint" type="color3" value="1, 0, 0" />
+            </variant>
+            <variant name="green">
+              <input name="tint" type="color3" value="0, 1, 0" />
+              <token name="suffix" type="string" value="_g" />
+            </variant>
+          </variantset>
+        </materialx>
+    "#;
+
+    #[test]
+    fn parse() {
+        let mat = MaterialX::from_str(XML).unwrap();
+        let sets = mat.variantsets().collect::<Vec<_>>();
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].nodedef.as_deref(), Some("ND_tinted"));
+        assert_eq!(
+            sets[0].variants.keys().collect::<Vec<_>>(),
+            ["red", "green"]
+        );
+        assert!(sets[0].variants.values().all(Result::is_ok));
+
+        let green = mat.variant("tints", "green").unwrap();
+        assert!(matches!(
+            &green.values["tint"],
+            DataTypeAndValue::Color3(c) if c.0 == [0.0, 1.0, 0.0]
+        ));
+        assert!(matches!(
+            &green.values["suffix"],
+            DataTypeAndValue::String(s) if s == "_g"
+        ));
+    }
+
+    #[test]
+    fn nodegraph() {
+        let mat = MaterialX::from_str(XML).unwrap();
+        let set = mat.get::<VariantSet>("tints".into()).unwrap();
+        assert_eq!(set.nodegraph(&mat).unwrap().name, "NG_tinted");
+    }
+
+    #[test]
+    fn missing_variant() {
+        let mat = MaterialX::from_str(XML).unwrap();
+        let err = mat.variant("tints", "blue").unwrap_err();
+        assert!(matches!(err, AccessError::NotFound { .. }));
+    }
+
+    #[test]
+    fn values_only() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39">
+              <variantset name="set">
+                <variant name="v">
+                  <input name="tint" type="color3" nodename="noise" />
+                </variant>
+              </variantset>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        assert!(mat.variant("set", "v").is_err());
+    }
+
+    #[test]
+    fn invalid_variant() {
+        let xml = XML.replace(r#"value="1, 0, 0""#, r#"value="red""#);
+        let mat = MaterialX::from_str(&xml).unwrap();
+        let sets = mat.variantsets().collect::<Vec<_>>();
+        assert_eq!(sets.len(), 1);
+        let variants = &sets[0].variants;
+        assert!(matches!(
+            variants["red"],
+            Err(AccessError::ValueParseError { .. })
+        ));
+        assert!(variants["green"].is_ok());
+        assert!(mat.variant("tints", "red").is_err());
+    }
+
+    #[test]
+    fn colorspace() {
+        let mat = MaterialX::from_str(
+            r#"
+            <materialx version="1.39" colorspace="srgb_texture">
+              <variantset name="set">
+                <variant name="v">
+                  <input name="a" type="color3" value="0.5, 0.5, 0.5" />
+                  <input name="b" type="color3" value="0.5, 0.5, 0.5" colorspace="lin_rec709" />
+                </variant>
+              </variantset>
+            </materialx>
+        "#,
+        )
+        .unwrap();
+        let v = mat.variant("set", "v").unwrap();
+        assert!(matches!(
+            &v.values["a"],
+            DataTypeAndValue::Color3(c) if (c.0[0] - 0.214).abs() < 1e-3
+        ));
+        assert!(matches!(&v.values["b"], DataTypeAndValue::Color3(c) if c.0[0] == 0.5));
+    }
+}